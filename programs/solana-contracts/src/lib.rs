@@ -1,65 +1,404 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer, Mint};
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, spl_token::native_mint, CloseAccount, Mint, SyncNative, Token, TokenAccount, Transfer};
 
 // Program ID: update in Anchor.toml as needed
 
 declare_id!("41f3Bi7jwTJ8Q3qr29AtaLZh3193AArY1nsgoTrEyRYx");
 
+// Upper bound for any fee expressed in basis points (10%)
+pub const MAX_FEE_BPS: u16 = 1000;
+
+// fee_bps value asking create_poll to use the Config's default network fee
+pub const USE_DEFAULT_FEE: u16 = u16::MAX;
+
+// Upper bound on the number of outcomes a poll can offer
+pub const MAX_OUTCOMES: usize = 8;
+
+// Upper bound on the length of a poll's metadata URI, in bytes
+pub const MAX_METADATA_URI_LEN: usize = 200;
+
+// Hard upper bound on the title cap the admin can configure, in bytes
+pub const MAX_TITLE_LEN: usize = 256;
+
+// Title cap of a new Config, in bytes
+pub const DEFAULT_MAX_TITLE_LEN: u16 = 64;
+
+// Upper bound on the number of token mints the admin can allow
+pub const MAX_ALLOWED_MINTS: usize = 16;
+
+// Seed of the program-wide Config PDA
+pub const CONFIG_SEED: &[u8] = b"config";
+
+// PDA seeds, each combined with the poll address
+pub const POOL_AUTHORITY_SEED: &[u8] = b"pool";
+pub const POOL_VAULT_SEED: &[u8] = b"pool_vault";
+pub const FEE_VAULT_SEED: &[u8] = b"fee_vault";
+pub const AUTHORITY_FEE_VAULT_SEED: &[u8] = b"authority_fee_vault";
+
+// Seed of a user's Vote PDA, combined with the poll and user addresses. The
+// hedge leg of a split vote also adds its 1-based outcome, so it never
+// collides with the user's regular position
+pub const VOTE_SEED: &[u8] = b"vote";
+// Seed of the temporary wSOL account a SOL payout is unwrapped through,
+// combined with the poll and user addresses
+pub const UNWRAP_SEED: &[u8] = b"unwrap";
+// Seed of a provider's LpPosition PDA, combined with the poll and provider addresses
+pub const LP_SEED: &[u8] = b"lp";
+// Seed of an authority's PollRegistry PDA, combined with the authority address
+pub const REGISTRY_SEED: &[u8] = b"registry";
+// Seed of a Delegation PDA, combined with the beneficiary and delegate addresses
+pub const DELEGATION_SEED: &[u8] = b"delegation";
+// Accounts per position passed to claim_winnings_batch
+pub const CLAIM_BATCH_GROUP_LEN: usize = 5;
+
+// PollCanceledEvent reason code of a consensus poll voided by a tie for the
+// lead; cancel_poll callers choose their own codes
+pub const CANCEL_REASON_CONSENSUS_TIE: u8 = u8::MAX;
+// PollCanceledEvent reason code of a poll force-canceled because it was not
+// resolved before its resolution deadline
+pub const CANCEL_REASON_STALE: u8 = u8::MAX - 1;
+
+// Largest early-bird bonus, in basis points of the shares bought, paid on a
+// bet placed the moment a bonus-enabled poll opens
+pub const EARLY_BIRD_BONUS_BPS: u16 = 2000;
+
+// How far, in basis points, a liquidity deposit may stray from the current
+// reserve ratio before it is rejected as moving the price
+pub const LIQUIDITY_RATIO_TOLERANCE_BPS: u16 = 100;
+
+// Part of the protocol's network fees that goes to a poll's liquidity
+// providers while any are invested
+pub const LP_FEE_SHARE_BPS: u16 = 5000;
+
+// Fixed-point scale of Poll::fee_per_deposit
+pub const FEE_PER_DEPOSIT_SCALE: u128 = 1_000_000_000_000;
+
+// Part of every reserve that stays locked in the pool for its whole life,
+// so no withdrawal can drain an outcome and leave its price undefined
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
+
+// Layout version written to new polls; migrate_poll upgrades older accounts.
+// Bump it whenever a field is added to Poll, and give the new version a
+// step in Poll::upgrade
+//   1: first versioned layout
+//   2: final_prices
+//   3: min_reserve
+//   4: claim_deadline
+//   5: outcome_disabled
+//   6: lp_fees, fee_per_deposit
+pub const POLL_VERSION: u8 = 6;
+
 #[program]
 pub mod solana_contracts {
     use super::*;
 
+    // Creates the program-wide Config; the PDA can only be initialized once,
+    // and only by the program's upgrade authority
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.pending_admin = None;
+        config.max_active_polls = 0;
+        config.allowed_mints = Vec::new();
+        config.round_fee_up = false;
+        config.default_fee_bps = 0;
+        config.max_title_len = DEFAULT_MAX_TITLE_LEN;
+        config.refund_fees_on_cancel = false;
+        config.claim_window = 0;
+        Ok(())
+    }
+
+    // First step of an admin rotation: the current admin proposes a
+    // successor, who only takes over once they accept
+    pub fn transfer_admin(ctx: Context<TransferAdmin>, new_admin: Pubkey) -> Result<()> {
+        ctx.accounts.config.pending_admin = Some(new_admin);
+
+        emit!(AdminTransferProposed {
+            admin: ctx.accounts.admin.key(),
+            pending_admin: new_admin,
+        });
+
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_admin = config.admin;
+        config.admin = ctx.accounts.new_admin.key();
+        config.pending_admin = None;
+
+        emit!(AdminTransferred {
+            previous_admin,
+            new_admin: config.admin,
+        });
+
+        Ok(())
+    }
+
+    // Emergency stop: while paused no new positions can be opened, but
+    // resolution, claims and refunds still work so users can exit
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        ctx.accounts.config.paused = paused;
+
+        emit!(PausedEvent {
+            admin: ctx.accounts.admin.key(),
+            paused,
+        });
+
+        Ok(())
+    }
+
+    // Caps how many unsettled polls a single authority may run at once, to
+    // keep spam out; 0 lifts the cap
+    pub fn set_max_active_polls(ctx: Context<SetMaxActivePolls>, max_active_polls: u32) -> Result<()> {
+        ctx.accounts.config.max_active_polls = max_active_polls;
+        Ok(())
+    }
+
+    // Restricts new polls to approved tokens; while the list is empty any
+    // mint may be used
+    pub fn add_allowed_mint(ctx: Context<UpdateAllowedMints>, mint: Pubkey) -> Result<()> {
+        let allowed_mints = &mut ctx.accounts.config.allowed_mints;
+        if allowed_mints.contains(&mint) {
+            return Ok(());
+        }
+        require!(allowed_mints.len() < MAX_ALLOWED_MINTS, AmmError::TooManyAllowedMints);
+        allowed_mints.push(mint);
+        Ok(())
+    }
+
+    // Existing polls on a removed mint are unaffected
+    pub fn remove_allowed_mint(ctx: Context<UpdateAllowedMints>, mint: Pubkey) -> Result<()> {
+        let allowed_mints = &mut ctx.accounts.config.allowed_mints;
+        let index = allowed_mints
+            .iter()
+            .position(|allowed| *allowed == mint)
+            .ok_or(AmmError::MintNotAllowed)?;
+        allowed_mints.remove(index);
+        Ok(())
+    }
+
+    // Chooses how network fees on votes and sales round; claim fees always
+    // round down
+    pub fn set_fee_rounding(ctx: Context<SetFeeRounding>, round_fee_up: bool) -> Result<()> {
+        ctx.accounts.config.round_fee_up = round_fee_up;
+        Ok(())
+    }
+
+    // Whether refunds on canceled polls also return the protocol's part of
+    // the network fee. While unset that part stays in the fee vault for
+    // withdraw_fees to sweep
+    pub fn set_refund_fees_on_cancel(
+        ctx: Context<SetRefundFeesOnCancel>,
+        refund_fees_on_cancel: bool,
+    ) -> Result<()> {
+        ctx.accounts.config.refund_fees_on_cancel = refund_fees_on_cancel;
+        Ok(())
+    }
+
+    // Network fee of polls created with fee_bps = USE_DEFAULT_FEE. Polls keep
+    // the fee they were created with when the default changes later
+    pub fn set_default_fee(ctx: Context<SetDefaultFee>, default_fee_bps: u16) -> Result<()> {
+        require!(default_fee_bps <= MAX_FEE_BPS, AmmError::FeeTooHigh);
+        ctx.accounts.config.default_fee_bps = default_fee_bps;
+        Ok(())
+    }
+
+    // Seconds winners have to claim once a resolved poll's dispute window
+    // has closed, after which the admin may sweep what is left to the
+    // treasury. Polls that already resolved keep their deadline; 0 lets
+    // claims wait indefinitely
+    pub fn set_claim_window(ctx: Context<SetClaimWindow>, claim_window: i64) -> Result<()> {
+        require!(claim_window >= 0, AmmError::InvalidClaimWindow);
+        ctx.accounts.config.claim_window = claim_window;
+        Ok(())
+    }
+
+    // Longest title new polls may use. Existing polls keep their title and
+    // account size when the cap changes
+    pub fn set_max_title_len(ctx: Context<SetMaxTitleLen>, max_title_len: u16) -> Result<()> {
+        require!(max_title_len as usize <= MAX_TITLE_LEN, AmmError::TitleLimitTooHigh);
+        ctx.accounts.config.max_title_len = max_title_len;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn create_poll(
         ctx: Context<CreatePoll>,
         title_bytes: Vec<u8>,
         closes_at: i64,
-        nft1: Pubkey,
-        nft2: Pubkey,
-        initial_nft1_shares: u64,
-        initial_nft2_shares: u64,
+        outcomes: Vec<Pubkey>,
+        initial_shares: Vec<u64>,
+        required_parent_outcome: Option<Pubkey>,
+        claim_fee_bps: u16,
+        resolution_mode: ResolutionMode,
+        fee_bps: u16,
+        min_bet: u64,
+        max_bet: u64,
+        metadata_uri: Vec<u8>,
+        resolver: Pubkey,
+        dispute_period: i64,
+        expected_decimals: Option<u8>,
+        bonus_enabled: bool,
+        authority_fee_bps: u16,
+        resolution_grace_period: i64,
+        require_ownership_proof: bool,
+        min_vote_interval: i64,
+        require_votes: bool,
+        min_reserve: u64,
     ) -> Result<()> {
-        require!(title_bytes.len() <= 64, AmmError::TitleTooLong);
-        require!(initial_nft1_shares > 0, AmmError::InvalidShares);
-        require!(initial_nft2_shares > 0, AmmError::InvalidShares);
+        require!(
+            title_bytes.len() <= ctx.accounts.config.max_title_len as usize,
+            AmmError::TitleTooLong
+        );
+        // Frontends decode the title as text, so it must be valid UTF-8
+        require!(std::str::from_utf8(&title_bytes).is_ok(), AmmError::InvalidTitleEncoding);
+        require!(metadata_uri.len() <= MAX_METADATA_URI_LEN, AmmError::UriTooLong);
+        require!(
+            outcomes.len() >= 2 && outcomes.len() <= MAX_OUTCOMES,
+            AmmError::InvalidOutcomeCount
+        );
+        // Each outcome must be its own NFT, or a winner would match several sides
+        require!(
+            outcomes.iter().enumerate().all(|(i, outcome)| !outcomes[..i].contains(outcome)),
+            AmmError::DuplicateOutcome
+        );
+        require!(initial_shares.len() == outcomes.len(), AmmError::InvalidShares);
+        require!(initial_shares.iter().all(|&shares| shares > 0), AmmError::InvalidShares);
+        require!(
+            initial_shares.iter().all(|&shares| shares > MINIMUM_LIQUIDITY.max(min_reserve)),
+            AmmError::InsufficientInitialLiquidity
+        );
+        require!(claim_fee_bps <= MAX_FEE_BPS, AmmError::FeeTooHigh);
+        let fee_bps = if fee_bps == USE_DEFAULT_FEE {
+            ctx.accounts.config.default_fee_bps
+        } else {
+            fee_bps
+        };
+        require!(fee_bps <= MAX_FEE_BPS, AmmError::FeeTooHigh);
+        // The authority's cut comes out of the network fee; the protocol
+        // keeps the remaining fee_bps - authority_fee_bps
+        require!(authority_fee_bps <= fee_bps, AmmError::InvalidFeeSplit);
+        require!(min_vote_interval >= 0, AmmError::InvalidVoteInterval);
+        require!(min_bet <= max_bet, AmmError::InvalidBetLimits);
+        require!(dispute_period >= 0, AmmError::InvalidDisputePeriod);
+        require!(resolution_grace_period >= 0, AmmError::InvalidGracePeriod);
+        let allowed_mints = &ctx.accounts.config.allowed_mints;
+        require!(
+            allowed_mints.is_empty() || allowed_mints.contains(&ctx.accounts.token_mint.key()),
+            AmmError::MintNotAllowed
+        );
+        // Lets the creator guard against a mint whose decimals the bet
+        // limits and initial shares were not sized for
+        let mint_decimals = ctx.accounts.token_mint.decimals;
+        if let Some(expected) = expected_decimals {
+            require!(mint_decimals == expected, AmmError::UnexpectedDecimals);
+        }
+        // A poll that is already closed could never take a vote
+        let clock = clock()?;
+        require!(closes_at > clock.unix_timestamp, AmmError::InvalidCloseTime);
+
+        // The poll counts against its authority's cap until it is settled
+        let registry = &mut ctx.accounts.registry;
+        let max_active_polls = ctx.accounts.config.max_active_polls;
+        require!(
+            max_active_polls == 0 || registry.active_poll_count < max_active_polls,
+            AmmError::TooManyActivePolls
+        );
+        registry.authority = ctx.accounts.authority.key();
+        registry.active_poll_count = registry.active_poll_count.checked_add(1).ok_or(AmmError::MathOverflow)?;
         
         let poll = &mut ctx.accounts.poll;
+        poll.version = POLL_VERSION;
         poll.authority = ctx.accounts.authority.key();
+        // A neutral resolver is optional; the zero key leaves it to the authority
+        poll.resolver = if resolver == Pubkey::default() { poll.authority } else { resolver };
         poll.title = title_bytes;
+        poll.metadata_uri = metadata_uri;
         poll.closes_at = closes_at;
-        poll.nft1 = nft1;
-        poll.nft2 = nft2;
-        poll.nft1_shares = initial_nft1_shares;
-        poll.nft2_shares = initial_nft2_shares;
-        poll.k = initial_nft1_shares * initial_nft2_shares;
+        poll.shares = initial_shares;
+        poll.outcome_pools = vec![0; outcomes.len()];
+        poll.vote_shares = vec![0; outcomes.len()];
+        poll.positions = vec![0; outcomes.len()];
+        poll.price_cumulative = vec![0; outcomes.len()];
+        poll.final_prices = vec![0; outcomes.len()];
+        poll.outcome_disabled = vec![false; outcomes.len()];
+        poll.outcomes = outcomes;
         poll.status = PollStatus::Active;
         poll.token_mint = ctx.accounts.token_mint.key();
+        poll.mint_decimals = mint_decimals;
+        poll.claim_fee_bps = claim_fee_bps;
+        poll.fee_bps = fee_bps;
+        poll.authority_fee_bps = authority_fee_bps;
+        poll.min_bet = min_bet;
+        poll.max_bet = max_bet;
+        poll.dispute_period = dispute_period;
+        // Without a grace period the poll waits for its resolver indefinitely
+        poll.resolution_deadline = if resolution_grace_period == 0 {
+            0
+        } else {
+            closes_at.checked_add(resolution_grace_period).ok_or(AmmError::MathOverflow)?
+        };
+        poll.bonus_enabled = bonus_enabled;
+        poll.require_ownership_proof = require_ownership_proof;
+        poll.min_vote_interval = min_vote_interval;
+        poll.require_votes = require_votes;
+        poll.min_reserve = min_reserve;
+        poll.created_at = clock.unix_timestamp;
+        poll.resolution_mode = resolution_mode;
+        poll.last_price_update = clock.unix_timestamp;
+        poll.last_update_slot = clock.slot;
+
+        // Conditional markets: the parent poll account and the outcome it must
+        // resolve to are provided together or not at all
+        match (&ctx.accounts.parent_poll, required_parent_outcome) {
+            (Some(parent), Some(outcome)) => {
+                require!(parent.outcomes.contains(&outcome), AmmError::InvalidNftChoice);
+                poll.parent_poll = Some(parent.key());
+                poll.required_parent_outcome = Some(outcome);
+            }
+            (None, None) => {
+                poll.parent_poll = None;
+                poll.required_parent_outcome = None;
+            }
+            _ => return err!(AmmError::InvalidParentPoll),
+        }
         
         emit!(PollCreatedEvent {
             poll: poll.key(),
             authority: poll.authority,
-            nft1,
-            nft2,
+            outcomes: poll.outcomes.clone(),
             closes_at
         });
         
         Ok(())
     }
 
-    pub fn vote(ctx: Context<VoteOnPoll>, nft_choice: u8, amount: u64) -> Result<()> {
-        let poll = &mut ctx.accounts.poll;
-        let vote = &mut ctx.accounts.vote;
-        require!(poll.status == PollStatus::Active, AmmError::PollNotActive);
-        require!(
-            Clock::get()?.unix_timestamp < poll.closes_at,
-            AmmError::PollClosed
-        );
-        require!(
-            nft_choice == 1 || nft_choice == 2,
-            AmmError::InvalidNftChoice
-        );
-        // Deduct 3% network fee
-        let fee = amount * 3 / 100;
-        let amount_after_fee = amount - fee;
+    // The signer pays for the bet from their own token account; with a
+    // `beneficiary` the position is credited to that account instead, which
+    // alone can then claim or sell it. The beneficiary must have approved
+    // the signer with approve_delegate
+    pub fn vote(
+        ctx: Context<VoteOnPoll>,
+        nft_choice: u8,
+        amount: u64,
+        min_shares_out: u64,
+        max_price_impact_bps: Option<u16>,
+        beneficiary: Option<Pubkey>,
+    ) -> Result<()> {
+        let fee = network_fee(amount, ctx.accounts.poll.fee_bps, ctx.accounts.config.round_fee_up);
+        let (amount_after_fee, protocol_fee, authority_fee) = place_bet(
+            &mut ctx.accounts.poll,
+            &mut ctx.accounts.vote,
+            credited_user(ctx.accounts.user.key(), beneficiary, ctx.accounts.delegation.as_ref())?,
+            nft_choice,
+            amount,
+            min_shares_out,
+            max_price_impact_bps,
+            fee,
+        )?;
         // SPL token transfer: user -> pool vault
         let cpi_ctx = CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
@@ -79,79 +418,518 @@ pub mod solana_contracts {
                 authority: ctx.accounts.user.to_account_info(),
             },
         );
-        token::transfer(cpi_ctx_fee, fee)?;
-        // AMM swap logic
-        let (received, new_nft1, new_nft2) = if nft_choice == 1 {
-            // Bet on NFT1: swap NFT2 for NFT1
-            let new_nft2 = poll.nft2_shares + amount_after_fee;
-            let new_nft1 = poll.k / new_nft2;
-            let received = poll.nft1_shares - new_nft1;
-            require!(
-                amount_after_fee <= poll.nft2_shares,
-                AmmError::NotEnoughLiquidity
+        token::transfer(cpi_ctx_fee, protocol_fee)?;
+        // SPL token transfer: user -> authority fee vault
+        let cpi_ctx_authority_fee = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.user_token_account.to_account_info(),
+                to: ctx.accounts.authority_fee_vault.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            },
+        );
+        token::transfer(cpi_ctx_authority_fee, authority_fee)?;
+
+        Ok(())
+    }
+
+    // Same bet as `vote` on a poll denominated in wrapped SOL, paid straight
+    // from the user's lamports: the stake and fee are sent to the vaults and
+    // synced into their token balances, so no wSOL account is needed
+    pub fn vote_sol(
+        ctx: Context<VoteSol>,
+        nft_choice: u8,
+        amount: u64,
+        min_shares_out: u64,
+        max_price_impact_bps: Option<u16>,
+        beneficiary: Option<Pubkey>,
+    ) -> Result<()> {
+        let fee = network_fee(amount, ctx.accounts.poll.fee_bps, ctx.accounts.config.round_fee_up);
+        let (amount_after_fee, protocol_fee, authority_fee) = place_bet(
+            &mut ctx.accounts.poll,
+            &mut ctx.accounts.vote,
+            credited_user(ctx.accounts.user.key(), beneficiary, ctx.accounts.delegation.as_ref())?,
+            nft_choice,
+            amount,
+            min_shares_out,
+            max_price_impact_bps,
+            fee,
+        )?;
+        wrap_sol(
+            &ctx.accounts.system_program,
+            &ctx.accounts.token_program,
+            &ctx.accounts.user,
+            &ctx.accounts.pool_vault,
+            amount_after_fee,
+        )?;
+        if protocol_fee > 0 {
+            wrap_sol(
+                &ctx.accounts.system_program,
+                &ctx.accounts.token_program,
+                &ctx.accounts.user,
+                &ctx.accounts.fee_vault,
+                protocol_fee,
+            )?;
+        }
+        if authority_fee > 0 {
+            wrap_sol(
+                &ctx.accounts.system_program,
+                &ctx.accounts.token_program,
+                &ctx.accounts.user,
+                &ctx.accounts.authority_fee_vault,
+                authority_fee,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Lets `delegate` place bets credited to the signer's positions, paid
+    // from the delegate's own tokens. The approval covers every poll until
+    // revoked
+    pub fn approve_delegate(ctx: Context<ApproveDelegate>, delegate: Pubkey) -> Result<()> {
+        let delegation = &mut ctx.accounts.delegation;
+        delegation.beneficiary = ctx.accounts.beneficiary.key();
+        delegation.delegate = delegate;
+
+        emit!(DelegationChangedEvent {
+            beneficiary: delegation.beneficiary,
+            delegate,
+            approved: true,
+        });
+
+        Ok(())
+    }
+
+    // Withdraws an approval; positions already credited stay the
+    // beneficiary's
+    pub fn revoke_delegate(ctx: Context<RevokeDelegate>) -> Result<()> {
+        emit!(DelegationChangedEvent {
+            beneficiary: ctx.accounts.beneficiary.key(),
+            delegate: ctx.accounts.delegation.delegate,
+            approved: false,
+        });
+
+        // The rent is returned to the beneficiary by the `close` constraint
+        Ok(())
+    }
+
+    // Hedges a stake across two outcomes in one transaction. The network fee
+    // is charged once, on the combined stake, and shared between the legs
+    // in proportion to their amounts. The second swap runs on the reserves
+    // the first one left. The first leg is a bet on the user's regular
+    // position, so it must back the same outcome as their earlier bets and
+    // waits out the poll's vote interval like one; the second is the hedge,
+    // a position of its own
+    pub fn vote_split(ctx: Context<VoteSplit>, first: SplitLeg, second: SplitLeg) -> Result<()> {
+        require!(first.amount > 0 && second.amount > 0, AmmError::BetTooSmall);
+        let total = first.amount.checked_add(second.amount).ok_or(AmmError::MathOverflow)?;
+        let fee = network_fee(total, ctx.accounts.poll.fee_bps, ctx.accounts.config.round_fee_up);
+        let first_fee = (fee as u128 * first.amount as u128 / total as u128) as u64;
+
+        let user = ctx.accounts.user.key();
+        let (first_stake, first_protocol_fee, first_authority_fee) = place_bet(
+            &mut ctx.accounts.poll,
+            &mut ctx.accounts.vote,
+            user,
+            first.nft_choice,
+            first.amount,
+            first.min_shares_out,
+            None,
+            first_fee,
+        )?;
+        let (second_stake, second_protocol_fee, second_authority_fee) = place_bet(
+            &mut ctx.accounts.poll,
+            &mut ctx.accounts.second_vote,
+            user,
+            second.nft_choice,
+            second.amount,
+            second.min_shares_out,
+            None,
+            fee - first_fee,
+        )?;
+
+        // One transfer per vault for both legs together
+        let transfers = [
+            (ctx.accounts.pool_vault.to_account_info(), first_stake + second_stake),
+            (ctx.accounts.fee_vault.to_account_info(), first_protocol_fee + second_protocol_fee),
+            (ctx.accounts.authority_fee_vault.to_account_info(), first_authority_fee + second_authority_fee),
+        ];
+        for (vault, amount) in transfers {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: vault,
+                    authority: ctx.accounts.user.to_account_info(),
+                },
             );
-            (received, new_nft1, new_nft2)
-        } else {
-            // Bet on NFT2: swap NFT1 for NFT2
-            let new_nft1 = poll.nft1_shares + amount_after_fee;
-            let new_nft2 = poll.k / new_nft1;
-            let received = poll.nft2_shares - new_nft2;
+            token::transfer(cpi_ctx, amount)?;
+        }
+
+        Ok(())
+    }
+
+    // Read-only preview of a vote: runs the same fee and AMM math and emits
+    // the result as a QuoteEvent, without moving tokens or changing the poll.
+    // Meant to be simulated by frontends before the user signs the real vote
+    pub fn quote_vote(ctx: Context<QuoteVote>, nft_choice: u8, amount: u64) -> Result<()> {
+        let poll = &ctx.accounts.poll;
+        require!(poll.status == PollStatus::Active, AmmError::PollNotActive);
+        require!(now()? < poll.closes_at, AmmError::PollClosed);
+
+        let round_fee_up = ctx.accounts.config.round_fee_up;
+        let (fee, received, new_shares) = poll.quote_bet(nft_choice, amount, round_fee_up)?;
+        emit!(QuoteEvent {
+            poll: poll.key(),
+            voted_for_nft: nft_choice,
+            value: amount,
+            received,
+            price_at_transaction: poll.prices_of(&new_shares)?[nft_choice as usize - 1],
+            fee,
+        });
+
+        Ok(())
+    }
+
+    // Read-only: emits each outcome's implied probability from the current
+    // reserves, in basis points that add up to exactly 10000
+    pub fn get_odds(ctx: Context<GetOdds>) -> Result<()> {
+        let poll = &ctx.accounts.poll;
+        emit!(OddsEvent {
+            poll: poll.key(),
+            probabilities: poll.odds_of(&poll.shares)?,
+        });
+
+        Ok(())
+    }
+
+    // Read-only: emits the poll's scalar fields and reserves as a flat
+    // PollStateEvent, for clients that cannot decode Anchor accounts
+    pub fn get_poll_state(ctx: Context<GetPollState>) -> Result<()> {
+        let poll = &ctx.accounts.poll;
+        emit!(poll.state(poll.key()));
+
+        Ok(())
+    }
+
+    // Read-only: sums the user's Vote accounts on the poll, passed as
+    // remaining accounts, into the shares held on each outcome and the
+    // tokens staked, and emits them as a PositionSummaryEvent
+    pub fn get_position<'info>(
+        ctx: Context<'_, '_, 'info, 'info, GetPosition<'info>>,
+        user: Pubkey,
+    ) -> Result<()> {
+        let poll = &ctx.accounts.poll;
+        let mut shares = vec![0u64; poll.outcomes.len()];
+        let mut total_value: u64 = 0;
+        let mut total_stake: u64 = 0;
+
+        for (i, vote_info) in ctx.remaining_accounts.iter().enumerate() {
+            // Listing a position twice would count it twice
             require!(
-                amount_after_fee <= poll.nft1_shares,
-                AmmError::NotEnoughLiquidity
+                ctx.remaining_accounts[..i].iter().all(|seen| seen.key() != vote_info.key()),
+                AmmError::DuplicateVote
             );
-            (received, new_nft1, new_nft2)
-        };
-        poll.nft1_shares = new_nft1;
-        poll.nft2_shares = new_nft2;
-        // Record vote
-        vote.poll = poll.key();
-        vote.user = ctx.accounts.user.key();
-        vote.voted_for_nft = nft_choice;
-        vote.amount = received;
-        vote.value = amount;
-        vote.price_at_transaction = get_price(poll.nft1_shares, poll.nft2_shares, nft_choice);
+            let vote = Account::<Vote>::try_from(vote_info)?;
+            require_keys_eq!(vote.poll, poll.key(), AmmError::InvalidVote);
+            require_keys_eq!(vote.user, user, AmmError::InvalidVote);
+            let index = vote.voted_for_nft as usize - 1;
+            shares[index] = shares[index].checked_add(vote.claim_shares()?).ok_or(AmmError::MathOverflow)?;
+            total_value = total_value.checked_add(vote.value).ok_or(AmmError::MathOverflow)?;
+            total_stake = total_stake.checked_add(vote.stake).ok_or(AmmError::MathOverflow)?;
+        }
+
+        emit!(PositionSummaryEvent {
+            poll: poll.key(),
+            user,
+            shares,
+            total_value,
+            total_stake,
+        });
+
+        Ok(())
+    }
+
+    pub fn sell_position(ctx: Context<SellPosition>, min_tokens_out: u64) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        let vote = &ctx.accounts.vote;
+        let clock = clock()?;
+        require!(
+            clock.unix_timestamp < poll.closes_at,
+            AmmError::PollClosed
+        );
+        let index = vote.voted_for_nft as usize - 1;
+        poll.accumulate_prices(&clock)?;
+        // Reverse AMM swap: return the shares to their reserve
+        let sale = poll.sell(index, vote.amount)?;
+        poll.require_reserve_floor(&sale.reserves)?;
+        let tokens_out = sale.amount_out;
+        poll.shares = sale.reserves;
+        // The same network fee as a vote, taken from the proceeds
+        let fee = network_fee(tokens_out, poll.fee_bps, ctx.accounts.config.round_fee_up);
+        let proceeds = tokens_out.checked_sub(fee).ok_or(AmmError::MathOverflow)?;
+        require!(proceeds >= min_tokens_out, AmmError::SlippageExceeded);
+        let (protocol_fee, authority_fee) = poll.split_fee(tokens_out, fee);
+        poll.book_lp_fee(protocol_fee)?;
+        // The position leaves the outcome's pool
+        poll.outcome_pools[index] = poll.outcome_pools[index]
+            .checked_sub(vote.stake)
+            .ok_or(AmmError::MathOverflow)?;
+        // Bonus shares only count towards winnings and are forfeited on a sale
+        poll.vote_shares[index] = poll.vote_shares[index]
+            .checked_sub(vote.claim_shares()?)
+            .ok_or(AmmError::MathOverflow)?;
+        poll.positions[index] = poll.positions[index].checked_sub(1).ok_or(AmmError::MathOverflow)?;
+        poll.vault_liability = poll
+            .vault_liability
+            .checked_sub(vote.stake)
+            .ok_or(AmmError::VaultInsolvent)?;
+        // Selling above or below the stake is a loss or gain for the
+        // liquidity; other stakes are never used to pay a seller
+        poll.liquidity = poll
+            .liquidity
+            .checked_add(vote.stake)
+            .ok_or(AmmError::MathOverflow)?
+            .checked_sub(tokens_out)
+            .ok_or(AmmError::NotEnoughLiquidity)?;
+
+        let pool_auth_bump = ctx.bumps.pool_authority;
+        let binding = poll.key();
+        let seeds = &[
+            POOL_AUTHORITY_SEED,
+            binding.as_ref(),
+            &[pool_auth_bump]
+        ];
+        let signer = &[&seeds[..]];
+
+        // SPL token transfer: pool vault -> user
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(cpi_ctx, proceeds)?;
+        // SPL token transfer: pool vault -> fee vault
+        let cpi_ctx_fee = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.fee_vault.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(cpi_ctx_fee, protocol_fee)?;
+        // SPL token transfer: pool vault -> authority fee vault
+        let cpi_ctx_authority_fee = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.authority_fee_vault.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(cpi_ctx_authority_fee, authority_fee)?;
+
+        emit!(PositionSold {
+            poll: poll.key(),
+            user: vote.user,
+            voted_for_nft: vote.voted_for_nft,
+            shares: vote.amount,
+            amount: proceeds,
+            fee,
+        });
+        emit!(PriceUpdateEvent {
+            poll: poll.key(),
+            shares: poll.shares.clone(),
+            prices: poll.prices_of(&poll.shares)?,
+            timestamp: clock.unix_timestamp,
+        });
+
+        // The vote account is closed and its rent returned by the `close` constraint
         Ok(())
     }
 
     pub fn resolve_poll(ctx: Context<ResolvePoll>, winning_nft: Pubkey) -> Result<()> {
         let poll = &mut ctx.accounts.poll;
         
-        // Ensure only the poll creator or a program admin can resolve
+        // Ensure only the poll creator, its resolver or a program admin can resolve
         require!(
             poll.authority == ctx.accounts.authority.key() || 
-            ctx.accounts.authority.key() == ctx.accounts.admin.key(), 
+            poll.resolver == ctx.accounts.authority.key() ||
+            ctx.accounts.authority.key() == ctx.accounts.config.admin, 
             AmmError::Unauthorized
         );
         
         require!(
-            poll.status == PollStatus::Active || poll.status == PollStatus::Closed,
-            AmmError::PollNotActive
+            poll.resolution_mode == ResolutionMode::Resolver,
+            AmmError::InvalidResolutionMode
         );
+        poll.require_unresolved()?;
+        // Resolving while bets are still accepted would let the resolver
+        // front-run the outcome
         require!(
-            winning_nft == poll.nft1 || winning_nft == poll.nft2,
-            AmmError::InvalidNftChoice
+            poll.status == PollStatus::Closed || now()? >= poll.closes_at,
+            AmmError::PollStillOpen
         );
+        // Resolving a market nobody bet on only moves its fees and liquidity
+        require!(!poll.require_votes || poll.vote_count > 0, AmmError::NoVotesCast);
+        let winning_index = poll.outcome_index(&winning_nft).ok_or(AmmError::InvalidNftChoice)?;
+        require!(!poll.outcome_disabled[winning_index], AmmError::OutcomeDisabled);
+        // Markets on real NFT ownership make the resolver show a token
+        // account of theirs holding the winning NFT
+        if poll.require_ownership_proof {
+            let proof = ctx.accounts.ownership_proof.as_ref().ok_or(AmmError::OwnershipProofRequired)?;
+            require!(
+                proof.mint == winning_nft && proof.owner == ctx.accounts.authority.key() && proof.amount == 1,
+                AmmError::OwnershipProofRequired
+            );
+        }
+        // Resolved or voided, the poll no longer counts as active
+        ctx.accounts.registry.release()?;
+
+        // A conditional poll whose parent did not resolve to the required
+        // outcome is voided: it is canceled instead of resolved
+        if !parent_condition_met(poll, ctx.accounts.parent_poll.as_ref())? {
+            poll.status = PollStatus::Canceled;
+
+            emit!(PollVoidedEvent {
+                poll: poll.key(),
+                parent_poll: poll.parent_poll.unwrap(),
+            });
+
+            return Ok(());
+        }
         
         poll.status = PollStatus::Resolved;
         poll.winning_nft = Some(winning_nft);
+        poll.winning_side_shares = poll.vote_shares[winning_index];
+        poll.count_unclaimed(winning_index);
+        poll.resolved_at = now()?;
+        poll.record_final_prices();
+        poll.open_claim_window(ctx.accounts.config.claim_window);
         
         emit!(PollResolvedEvent {
             poll: poll.key(),
             authority: ctx.accounts.authority.key(),
-            winning_nft
+            winning_nft,
+            final_prices: poll.final_prices.clone(),
         });
+        emit!(poll.market_state(poll.key(), winning_nft));
         
         Ok(())
     }
 
-    pub fn cancel_poll(ctx: Context<CancelPoll>) -> Result<()> {
+    pub fn resolve_by_consensus(ctx: Context<ResolveByConsensus>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+
+        require!(
+            poll.resolution_mode == ResolutionMode::Consensus,
+            AmmError::InvalidResolutionMode
+        );
+        poll.require_unresolved()?;
+        require!(
+            now()? >= poll.closes_at,
+            AmmError::PollStillOpen
+        );
+        // Resolved or voided, the poll no longer counts as active
+        ctx.accounts.registry.release()?;
+
+        if !parent_condition_met(poll, ctx.accounts.parent_poll.as_ref())? {
+            poll.status = PollStatus::Canceled;
+
+            emit!(PollVoidedEvent {
+                poll: poll.key(),
+                parent_poll: poll.parent_poll.unwrap(),
+            });
+
+            return Ok(());
+        }
+
+        // The live outcome with the most tokens staked wins; a tie for the
+        // lead voids the poll
+        let top_stake = poll.live_outcomes().map(|i| poll.outcome_pools[i]).max().unwrap_or(0);
+        let leaders: Vec<usize> = poll.live_outcomes().filter(|&i| poll.outcome_pools[i] == top_stake).collect();
+        let winning_index = match leaders[..] {
+            [index] => index,
+            _ => {
+                poll.status = PollStatus::Canceled;
+
+                emit!(PollCanceledEvent {
+                    poll: poll.key(),
+                    authority: ctx.accounts.caller.key(),
+                    reason_code: CANCEL_REASON_CONSENSUS_TIE,
+                    total_volume: poll.total_volume,
+                    vote_count: poll.vote_count,
+                });
+
+                return Ok(());
+            }
+        };
+        let winning_nft = poll.outcomes[winning_index];
+
+        poll.status = PollStatus::Resolved;
+        poll.winning_nft = Some(winning_nft);
+        poll.winning_side_shares = poll.vote_shares[winning_index];
+        poll.count_unclaimed(winning_index);
+        poll.resolved_at = now()?;
+        poll.record_final_prices();
+        poll.open_claim_window(ctx.accounts.config.claim_window);
+
+        emit!(PollResolvedEvent {
+            poll: poll.key(),
+            authority: ctx.accounts.caller.key(),
+            winning_nft,
+            final_prices: poll.final_prices.clone(),
+        });
+        emit!(poll.market_state(poll.key(), winning_nft));
+
+        Ok(())
+    }
+
+    // Governance correction of a wrong resolution. Only the program admin may
+    // move the win to another outcome, and only while the dispute window
+    // still keeps every payout locked
+    pub fn override_resolution(ctx: Context<OverrideResolution>, winning_nft: Pubkey) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        require!(
+            poll.dispute_window_open(now()?),
+            AmmError::DisputeWindowClosed
+        );
+        let winning_index = poll.outcome_index(&winning_nft).ok_or(AmmError::InvalidNftChoice)?;
+        require!(!poll.outcome_disabled[winning_index], AmmError::OutcomeDisabled);
+
+        let previous_winning_nft = poll.winning_nft.ok_or(AmmError::PollNotResolved)?;
+        poll.winning_nft = Some(winning_nft);
+        poll.winning_side_shares = poll.vote_shares[winning_index];
+        poll.count_unclaimed(winning_index);
+
+        emit!(ResolutionOverriddenEvent {
+            poll: poll.key(),
+            admin: ctx.accounts.admin.key(),
+            previous_winning_nft,
+            winning_nft,
+        });
+        emit!(poll.market_state(poll.key(), winning_nft));
+
+        Ok(())
+    }
+
+    // `reason_code` is passed through to PollCanceledEvent so off-chain
+    // consumers can tell voters why their poll was called off
+    pub fn cancel_poll(ctx: Context<CancelPoll>, reason_code: u8) -> Result<()> {
         let poll = &mut ctx.accounts.poll;
         
         // Ensure only the poll creator or a program admin can cancel
         require!(
             poll.authority == ctx.accounts.authority.key() || 
-            ctx.accounts.authority.key() == ctx.accounts.admin.key(), 
+            ctx.accounts.authority.key() == ctx.accounts.config.admin, 
             AmmError::Unauthorized
         );
         
@@ -161,343 +939,4215 @@ pub mod solana_contracts {
         );
         
         poll.status = PollStatus::Canceled;
+        ctx.accounts.registry.release()?;
         
         emit!(PollCanceledEvent {
             poll: poll.key(),
-            authority: ctx.accounts.authority.key()
+            authority: ctx.accounts.authority.key(),
+            reason_code,
+            total_volume: poll.total_volume,
+            vote_count: poll.vote_count,
         });
         
         Ok(())
     }
 
-    pub fn add_liquidity(
-        ctx: Context<AddLiquidity>,
-        nft1_amount: u64,
-        nft2_amount: u64,
-    ) -> Result<()> {
-        // SPL token transfer: user -> pool vault for both tokens
-        let cpi_ctx1 = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.user_token_account1.to_account_info(),
-                to: ctx.accounts.pool_vault1.to_account_info(),
-                authority: ctx.accounts.user.to_account_info(),
-            },
-        );
-        token::transfer(cpi_ctx1, nft1_amount)?;
-        let cpi_ctx2 = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.user_token_account2.to_account_info(),
-                to: ctx.accounts.pool_vault2.to_account_info(),
+    // Protects voters from an abandoned market: once a poll is past its
+    // resolution deadline without being resolved, anyone may cancel it so
+    // every stake can be refunded
+    pub fn force_cancel_stale(ctx: Context<ForceCancelStale>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+
+        require!(
+            poll.status == PollStatus::Active || poll.status == PollStatus::Closed,
+            AmmError::PollNotActive
+        );
+        require!(
+            poll.resolution_deadline != 0 && now()? > poll.resolution_deadline,
+            AmmError::PollNotStale
+        );
+
+        poll.status = PollStatus::Canceled;
+        ctx.accounts.registry.release()?;
+
+        emit!(PollCanceledEvent {
+            poll: poll.key(),
+            authority: ctx.accounts.caller.key(),
+            reason_code: CANCEL_REASON_STALE,
+            total_volume: poll.total_volume,
+            vote_count: poll.vote_count,
+        });
+
+        Ok(())
+    }
+
+    // Postpones the end of betting. The close time can only move later, and
+    // only while the poll is still taking votes
+    pub fn extend_poll(ctx: Context<ExtendPoll>, closes_at: i64) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+
+        // Ensure only the poll creator or a program admin can extend
+        require!(
+            poll.authority == ctx.accounts.authority.key() ||
+            ctx.accounts.authority.key() == ctx.accounts.config.admin,
+            AmmError::Unauthorized
+        );
+
+        require!(
+            poll.status == PollStatus::Active && now()? < poll.closes_at,
+            AmmError::InvalidCloseTime
+        );
+        require!(closes_at > poll.closes_at, AmmError::InvalidCloseTime);
+
+        let previous_closes_at = poll.closes_at;
+        poll.closes_at = closes_at;
+        // The resolver keeps the same grace period after the new close time
+        if poll.resolution_deadline != 0 {
+            poll.resolution_deadline = poll
+                .resolution_deadline
+                .checked_add(closes_at - previous_closes_at)
+                .ok_or(AmmError::MathOverflow)?;
+        }
+
+        emit!(PollExtendedEvent {
+            poll: poll.key(),
+            authority: ctx.accounts.authority.key(),
+            previous_closes_at,
+            closes_at,
+        });
+
+        Ok(())
+    }
+
+    // Changes the network fee of a poll that is still taking votes. Bets
+    // already placed keep the fee they paid
+    pub fn set_poll_fee(ctx: Context<SetPollFee>, fee_bps: u16) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+
+        require!(
+            poll.authority == ctx.accounts.authority.key() ||
+            ctx.accounts.authority.key() == ctx.accounts.config.admin,
+            AmmError::Unauthorized
+        );
+        require!(poll.status == PollStatus::Active, AmmError::PollNotActive);
+        require!(now()? < poll.closes_at, AmmError::PollClosed);
+        require!(fee_bps <= MAX_FEE_BPS, AmmError::FeeTooHigh);
+        // The authority's cut must still fit inside the new fee
+        require!(poll.authority_fee_bps <= fee_bps, AmmError::InvalidFeeSplit);
+
+        let previous_fee_bps = poll.fee_bps;
+        poll.fee_bps = fee_bps;
+
+        emit!(FeeChangedEvent {
+            poll: poll.key(),
+            authority: ctx.accounts.authority.key(),
+            previous_fee_bps,
+            fee_bps,
+        });
+
+        Ok(())
+    }
+
+    // Takes one outcome of an unresolved poll out of the market, e.g. when
+    // a contestant withdraws, so the other outcomes keep trading. Its
+    // reserve leaves the AMM: the remaining reserves are untouched and
+    // their prices renormalize among themselves. Bets on the outcome are
+    // refunded through claim_refund, and the winners split the other stakes
+    pub fn disable_outcome(ctx: Context<DisableOutcome>, outcome_index: u8) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+
+        require!(
+            poll.authority == ctx.accounts.authority.key() ||
+            ctx.accounts.authority.key() == ctx.accounts.config.admin,
+            AmmError::Unauthorized
+        );
+        poll.require_unresolved()?;
+        let index = outcome_index as usize;
+        require!(index < poll.outcomes.len(), AmmError::InvalidNftChoice);
+        require!(!poll.outcome_disabled[index], AmmError::OutcomeDisabled);
+        // A market needs two outcomes; cancel_poll ends it instead
+        require!(poll.live_outcomes().count() > 2, AmmError::InvalidOutcomeCount);
+
+        // Close out the TWAP at the prices before the outcome left
+        let clock = clock()?;
+        poll.accumulate_prices(&clock)?;
+        let reserve = poll.shares[index];
+        poll.shares[index] = 0;
+        poll.outcome_disabled[index] = true;
+
+        emit!(OutcomeDisabled {
+            poll: poll.key(),
+            authority: ctx.accounts.authority.key(),
+            outcome: poll.outcomes[index],
+            outcome_index,
+            reserve,
+            refunds_owed: poll.outcome_pools[index],
+            positions: poll.positions[index],
+        });
+        emit!(PriceUpdateEvent {
+            poll: poll.key(),
+            shares: poll.shares.clone(),
+            prices: poll.prices_of(&poll.shares)?,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Replaces the poll's metadata URI. The account is resized to fit the
+    // new URI: the authority pays the rent of a longer one and gets back
+    // the rent a shorter one frees
+    pub fn update_metadata(ctx: Context<UpdateMetadata>, metadata_uri: Vec<u8>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        poll.require_unresolved()?;
+        require!(metadata_uri.len() <= MAX_METADATA_URI_LEN, AmmError::UriTooLong);
+        poll.metadata_uri = metadata_uri;
+
+        emit!(MetadataUpdatedEvent {
+            poll: poll.key(),
+            metadata_uri: poll.metadata_uri.clone(),
+        });
+
+        Ok(())
+    }
+
+    pub fn close_poll(ctx: Context<ClosePoll>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+
+        // Anyone may close a poll once its betting window has ended
+        let now = now()?;
+        require!(poll.status == PollStatus::Active, AmmError::PollNotActive);
+        require!(now >= poll.closes_at, AmmError::PollStillOpen);
+
+        poll.status = PollStatus::Closed;
+        poll.closed_at = now;
+
+        let closed_by = ctx.accounts.caller.key();
+        emit!(PollClosedEvent {
+            poll: poll.key(),
+            closed_by,
+            auto: closed_by != poll.authority,
+        });
+
+        Ok(())
+    }
+
+    pub fn batch_close<'info>(ctx: Context<'_, '_, 'info, 'info, BatchClose<'info>>) -> Result<()> {
+        let now = now()?;
+
+        // Every remaining account is a poll; polls that are still open or no
+        // longer active are skipped so a keeper can pass a mixed batch
+        for poll_info in ctx.remaining_accounts.iter() {
+            require!(poll_info.is_writable, anchor_lang::error::ErrorCode::AccountNotMutable);
+            let mut poll = Account::<Poll>::try_from(poll_info)?;
+            if poll.status != PollStatus::Active || now < poll.closes_at {
+                continue;
+            }
+
+            poll.status = PollStatus::Closed;
+            poll.closed_at = now;
+            poll.exit(&crate::ID)?;
+
+            emit!(PollClosedEvent {
+                poll: poll.key(),
+                closed_by: ctx.accounts.caller.key(),
+                auto: ctx.accounts.caller.key() != poll.authority,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn add_liquidity(ctx: Context<AddLiquidity>, amounts: Vec<u64>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        let clock = clock()?;
+        // Tokens added after betting ends would only be stranded in the vault
+        require!(poll.status == PollStatus::Active, AmmError::PollNotActive);
+        require!(
+            clock.unix_timestamp < poll.closes_at,
+            AmmError::PollClosed
+        );
+        // One amount per outcome, added to that outcome's reserve
+        require!(amounts.len() == poll.outcomes.len(), AmmError::InvalidShares);
+        // A skewed deposit would shift the price for free, and a disabled
+        // outcome's reserve stays empty
+        require!(
+            poll.outcomes_disabled().all(|i| amounts[i] == 0)
+                && liquidity_is_balanced(&poll.gather(&poll.shares), &poll.gather(&amounts)),
+            AmmError::ImbalancedLiquidity
+        );
+        let mut total: u64 = 0;
+        for (reserve, &amount) in poll.shares.iter_mut().zip(amounts.iter()) {
+            *reserve = reserve.checked_add(amount).ok_or(AmmError::MathOverflow)?;
+            total = total.checked_add(amount).ok_or(AmmError::MathOverflow)?;
+        }
+        // SPL token transfer: user -> pool vault; every poll has a single
+        // token mint, so all deposits land in its one vault
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.user_token_account.to_account_info(),
+                to: ctx.accounts.pool_vault.to_account_info(),
                 authority: ctx.accounts.user.to_account_info(),
             },
         );
-        token::transfer(cpi_ctx2, nft2_amount)?;
-        let poll = &mut ctx.accounts.poll;
-        poll.nft1_shares = poll.nft1_shares.checked_add(nft1_amount).unwrap();
-        poll.nft2_shares = poll.nft2_shares.checked_add(nft2_amount).unwrap();
-        poll.k = poll.nft1_shares * poll.nft2_shares;
+        token::transfer(cpi_ctx, total)?;
+        poll.liquidity = poll.liquidity.checked_add(total).ok_or(AmmError::MathOverflow)?;
+        poll.lp_deposits = poll.lp_deposits.checked_add(total).ok_or(AmmError::MathOverflow)?;
+
+        // Credit the provider with the deposit, which entitles them to a
+        // share of the liquidity once the poll is settled and of the fees
+        // charged from now on
+        let lp_position = &mut ctx.accounts.lp_position;
+        lp_position.poll = poll.key();
+        lp_position.provider = ctx.accounts.user.key();
+        lp_position.accrue_fees(poll.fee_per_deposit)?;
+        lp_position.deposited = lp_position.deposited.checked_add(total).ok_or(AmmError::MathOverflow)?;
+
+        emit!(LiquidityAddedEvent {
+            poll: poll.key(),
+            provider: lp_position.provider,
+            amounts,
+            new_shares: poll.shares.clone(),
+            liquidity: poll.liquidity,
+            deposited: lp_position.deposited,
+        });
+        emit!(PriceUpdateEvent {
+            poll: poll.key(),
+            shares: poll.shares.clone(),
+            prices: poll.prices_of(&poll.shares)?,
+            timestamp: clock.unix_timestamp,
+        });
+
         Ok(())
     }
 
-    pub fn claim_winnings(ctx: Context<ClaimWinnings>) -> Result<()> {
-        let poll = &ctx.accounts.poll;
-        let vote = &mut ctx.accounts.vote;
-        
-        // Check if poll is resolved
-        require!(poll.status == PollStatus::Resolved, AmmError::PollNotResolved);
-        
-        // Check if this vote belongs to the correct user
-        require!(vote.user == ctx.accounts.user.key(), AmmError::Unauthorized);
-        
-        // Check if vote is already claimed
-        require!(!vote.claimed, AmmError::AlreadyClaimed);
-        
-        // Check if vote is for the winning NFT
-        let winning_nft = poll.winning_nft.ok_or(AmmError::PollNotResolved)?;
-        let voted_for_winner = 
-            (vote.voted_for_nft == 1 && winning_nft == poll.nft1) ||
-            (vote.voted_for_nft == 2 && winning_nft == poll.nft2);
-        
-        require!(voted_for_winner, AmmError::NotWinner);
-        
-        // Calculate payout based on vote amount
-        // In this simple implementation, winners get their tokens back plus their share
-        let payout_amount = vote.amount;
-        
-        // Transfer tokens from pool vault to user
+    pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, amounts: Vec<u64>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+
+        // Ensure only the poll creator or a program admin can withdraw
+        require!(
+            poll.authority == ctx.accounts.authority.key() ||
+            ctx.accounts.authority.key() == ctx.accounts.config.admin,
+            AmmError::Unauthorized
+        );
+
+        require!(amounts.len() == poll.outcomes.len(), AmmError::InvalidShares);
+        let mut total: u64 = 0;
+        for (reserve, &amount) in poll.shares.iter_mut().zip(amounts.iter()) {
+            *reserve = reserve.checked_sub(amount).ok_or(AmmError::NotEnoughLiquidity)?;
+            total = total.checked_add(amount).ok_or(AmmError::MathOverflow)?;
+        }
+        // MINIMUM_LIQUIDITY of every reserve is locked, settled or not, and
+        // withdrawals may not leave the reserves too thin for bets
+        let floor = MINIMUM_LIQUIDITY.max(poll.min_reserve);
+        require!(
+            poll.live_outcomes().all(|i| poll.shares[i] >= floor),
+            AmmError::NotEnoughLiquidity
+        );
+        // Initial shares are virtual; only deposited tokens can be withdrawn,
+        // which keeps the stakes in the vault untouched
+        poll.liquidity = poll.liquidity.checked_sub(total).ok_or(AmmError::NotEnoughLiquidity)?;
+        // The authority takes back its own deposit first. Beyond that it may
+        // only sweep liquidity no provider is invested in, such as the
+        // spread kept from sales once every other provider has withdrawn
+        let owned = ctx.accounts.lp_position.as_ref().map_or(0, |position| position.deposited);
+        let from_deposit = total.min(owned);
+        require!(
+            from_deposit == total || poll.lp_deposits == owned,
+            AmmError::NotEnoughLiquidity
+        );
+        poll.lp_deposits -= from_deposit;
+        if let Some(lp_position) = ctx.accounts.lp_position.as_mut() {
+            lp_position.accrue_fees(poll.fee_per_deposit)?;
+            lp_position.deposited -= from_deposit;
+        }
+
         let pool_auth_bump = ctx.bumps.pool_authority;
         let binding = poll.key();
         let seeds = &[
-            b"pool".as_ref(),
+            POOL_AUTHORITY_SEED,
             binding.as_ref(),
             &[pool_auth_bump]
         ];
         let signer = &[&seeds[..]];
-        
+
         let cpi_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
             Transfer {
                 from: ctx.accounts.pool_vault.to_account_info(),
-                to: ctx.accounts.user_token_account.to_account_info(),
+                to: ctx.accounts.authority_token_account.to_account_info(),
                 authority: ctx.accounts.pool_authority.to_account_info(),
             },
             signer,
         );
-        
-        token::transfer(cpi_ctx, payout_amount)?;
-        
-        // Mark vote as claimed
-        vote.claimed = true;
-        
-        emit!(WinningsClaimed {
+        token::transfer(cpi_ctx, total)?;
+
+        emit!(PriceUpdateEvent {
             poll: poll.key(),
-            user: ctx.accounts.user.key(),
-            amount: payout_amount,
+            shares: poll.shares.clone(),
+            prices: poll.prices_of(&poll.shares)?,
+            timestamp: now()?,
         });
-        
+
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct CreatePoll<'info> {
-    #[account(init, payer = authority, space = 8 + Poll::LEN)]
-    pub poll: Account<'info, Poll>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    /// The token mint that will be used for this poll
-    pub token_mint: Account<'info, Mint>,
-    pub system_program: Program<'info, System>,
-}
+    // Collects platform fees from a poll's fee vault; None withdraws the
+    // whole protocol part. The fees set aside for liquidity providers stay
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: Option<u64>) -> Result<()> {
+        let balance = ctx.accounts.poll.protocol_fees(ctx.accounts.fee_vault.amount);
+        let amount = amount.unwrap_or(balance);
+        require!(amount <= balance, AmmError::InsufficientFees);
 
-#[derive(Accounts)]
-pub struct VoteOnPoll<'info> {
-    #[account(mut, constraint = poll.status == PollStatus::Active @ AmmError::PollNotActive)]
-    pub poll: Account<'info, Poll>,
-    #[account(init, payer = user, space = 8 + Vote::LEN)]
-    pub vote: Account<'info, Vote>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    #[account(
-        mut, 
-        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
-        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = pool_vault.mint == poll.token_mint @ AmmError::InvalidTokenMint
-    )]
-    pub pool_vault: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = fee_vault.mint == poll.token_mint @ AmmError::InvalidTokenMint
-    )]
-    pub fee_vault: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+        let pool_auth_bump = ctx.bumps.pool_authority;
+        let binding = ctx.accounts.poll.key();
+        let seeds = &[
+            POOL_AUTHORITY_SEED,
+            binding.as_ref(),
+            &[pool_auth_bump]
+        ];
+        let signer = &[&seeds[..]];
 
-#[derive(Accounts)]
-pub struct ResolvePoll<'info> {
-    #[account(mut)]
-    pub poll: Account<'info, Poll>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    /// CHECK: Admin pubkey is verified in the instruction
-    pub admin: UncheckedAccount<'info>,
-}
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.fee_vault.to_account_info(),
+                to: ctx.accounts.destination.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(cpi_ctx, amount)?;
 
-#[derive(Accounts)]
-pub struct CancelPoll<'info> {
-    #[account(mut)]
-    pub poll: Account<'info, Poll>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    /// CHECK: Admin pubkey is verified in the instruction
-    pub admin: UncheckedAccount<'info>,
-}
+        emit!(FeesWithdrawn {
+            poll: binding,
+            destination: ctx.accounts.destination.key(),
+            amount,
+        });
 
-#[derive(Accounts)]
-pub struct AddLiquidity<'info> {
-    #[account(mut)]
-    pub poll: Account<'info, Poll>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    #[account(mut)]
-    pub user_token_account1: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_token_account2: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub pool_vault1: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub pool_vault2: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct ClaimWinnings<'info> {
-    #[account(constraint = poll.status == PollStatus::Resolved @ AmmError::PollNotResolved)]
-    pub poll: Account<'info, Poll>,
-    
-    #[account(
-        mut, 
-        constraint = vote.poll == poll.key() @ AmmError::InvalidVote,
-        constraint = vote.user == user.key() @ AmmError::Unauthorized
-    )]
-    pub vote: Account<'info, Vote>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
-    #[account(
-        mut,
-        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner,
-        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
-    #[account(
-        mut,
-        constraint = pool_vault.mint == poll.token_mint @ AmmError::InvalidTokenMint
-    )]
-    pub pool_vault: Account<'info, TokenAccount>,
-    
-    /// CHECK: PDA that serves as the pool authority
-    #[account(
-        seeds = [b"pool", poll.key().as_ref()],
-        bump
-    )]
-    pub pool_authority: UncheckedAccount<'info>,
-    
-    pub token_program: Program<'info, Token>,
+    // The poll authority's cut of the network fees is theirs to withdraw at
+    // any time, independently of the protocol's fee vault
+    pub fn withdraw_authority_fees(ctx: Context<WithdrawAuthorityFees>, amount: Option<u64>) -> Result<()> {
+        let balance = ctx.accounts.authority_fee_vault.amount;
+        let amount = amount.unwrap_or(balance);
+        require!(amount <= balance, AmmError::InsufficientFees);
+
+        let binding = ctx.accounts.poll.key();
+        let seeds = &[POOL_AUTHORITY_SEED, binding.as_ref(), &[ctx.bumps.pool_authority]];
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.authority_fee_vault.to_account_info(),
+            ctx.accounts.destination.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            &[&seeds[..]],
+            amount,
+        )?;
+
+        emit!(FeesWithdrawn {
+            poll: binding,
+            destination: ctx.accounts.destination.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    // Pays a liquidity provider out of a settled poll: their deposit's
+    // share of all deposits still invested, applied to the liquidity left
+    // in the pool vault, and the fees their deposit earned while it was
+    // invested. Each liquidity payout takes the same fraction of what
+    // remains, so the order providers withdraw in does not matter and the
+    // last one sweeps the rounding dust
+    pub fn withdraw_lp(ctx: Context<WithdrawLp>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        let lp_position = &mut ctx.accounts.lp_position;
+        lp_position.accrue_fees(poll.fee_per_deposit)?;
+        let deposited = lp_position.deposited;
+        let share_of = |amount: u64| -> Result<u64> {
+            // A position emptied through remove_liquidity is owed nothing
+            if deposited == 0 {
+                return Ok(0);
+            }
+            (amount as u128)
+                .checked_mul(deposited as u128)
+                .and_then(|value| value.checked_div(poll.lp_deposits as u128))
+                .and_then(|value| u64::try_from(value).ok())
+                .ok_or(error!(AmmError::MathOverflow))
+        };
+        let liquidity = share_of(poll.liquidity)?;
+        let fees = lp_position.fees_owed;
+        poll.liquidity -= liquidity;
+        poll.lp_deposits -= deposited;
+        poll.lp_fees = poll.lp_fees.checked_sub(fees).ok_or(AmmError::InsufficientFees)?;
+
+        let binding = poll.key();
+        let seeds = &[POOL_AUTHORITY_SEED, binding.as_ref(), &[ctx.bumps.pool_authority]];
+        let signer = &[&seeds[..]];
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.pool_vault.to_account_info(),
+            ctx.accounts.provider_token_account.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            liquidity,
+        )?;
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.fee_vault.to_account_info(),
+            ctx.accounts.provider_token_account.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            fees,
+        )?;
+
+        emit!(LpWithdrawn {
+            poll: binding,
+            provider: ctx.accounts.provider.key(),
+            deposited,
+            liquidity,
+            fees,
+        });
+
+        Ok(())
+    }
+
+    // Pays out `amount` of the position's remaining gross winnings, or all
+    // of them when None; the vote counts as claimed once nothing is left
+    pub fn claim_winnings(ctx: Context<ClaimWinnings>, amount: Option<u64>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        let (net_payout, claim_fee) = settle_winnings(
+            poll,
+            &mut ctx.accounts.vote,
+            ctx.accounts.user.key(),
+            ctx.accounts.parent_poll.as_ref(),
+            ctx.accounts.pool_vault.amount,
+            amount,
+        )?;
+
+        // Transfer tokens from pool vault to user
+        let pool_auth_bump = ctx.bumps.pool_authority;
+        let binding = poll.key();
+        let seeds = &[
+            POOL_AUTHORITY_SEED,
+            binding.as_ref(),
+            &[pool_auth_bump]
+        ];
+        let signer = &[&seeds[..]];
+
+        // Winnings go to the user's own account unless they name another
+        // account of the poll's mint, e.g. one held in separate custody
+        let destination = ctx.accounts.destination.as_ref().unwrap_or(&ctx.accounts.user_token_account);
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.pool_vault.to_account_info(),
+            destination.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            net_payout,
+        )?;
+        if claim_fee > 0 {
+            vault_transfer(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.pool_vault.to_account_info(),
+                ctx.accounts.fee_vault.to_account_info(),
+                ctx.accounts.pool_authority.to_account_info(),
+                signer,
+                claim_fee,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Same claim as `claim_winnings` on a poll denominated in wrapped SOL,
+    // paid out as lamports. The payout is moved into a temporary wSOL
+    // account, whose rent the user fronts, and closing it hands the user
+    // the rent back together with the unwrapped payout
+    pub fn claim_winnings_sol(ctx: Context<ClaimWinningsSol>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        let (net_payout, claim_fee) = settle_winnings(
+            poll,
+            &mut ctx.accounts.vote,
+            ctx.accounts.user.key(),
+            ctx.accounts.parent_poll.as_ref(),
+            ctx.accounts.pool_vault.amount,
+            None,
+        )?;
+
+        let binding = poll.key();
+        let seeds = &[POOL_AUTHORITY_SEED, binding.as_ref(), &[ctx.bumps.pool_authority]];
+        let signer = &[&seeds[..]];
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.pool_vault.to_account_info(),
+            ctx.accounts.unwrap_account.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            net_payout,
+        )?;
+        if claim_fee > 0 {
+            vault_transfer(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.pool_vault.to_account_info(),
+                ctx.accounts.fee_vault.to_account_info(),
+                ctx.accounts.pool_authority.to_account_info(),
+                signer,
+                claim_fee,
+            )?;
+        }
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.unwrap_account.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer,
+        ))?;
+
+        Ok(())
+    }
+
+    // Claims several winning positions in one transaction. The remaining
+    // accounts come in groups of five, one group per position:
+    //   [poll (writable), vote (writable), pool_vault (writable), fee_vault (writable), pool_authority]
+    // Every payout goes to `user_token_account`, so all polls must share its
+    // mint. Positions that cannot be claimed (poll not resolved or still in
+    // its dispute window, already claimed, losing side, or a conditional
+    // poll, which needs its parent and goes through claim_winnings) are
+    // skipped instead of failing the batch.
+    pub fn claim_winnings_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ClaimWinningsBatch<'info>>,
+    ) -> Result<()> {
+        let groups = ctx.remaining_accounts.chunks_exact(CLAIM_BATCH_GROUP_LEN);
+        require!(groups.remainder().is_empty(), AmmError::InvalidBatchAccounts);
+        let user = ctx.accounts.user.key();
+        let now = now()?;
+
+        for group in groups {
+            let [poll_info, vote_info, pool_vault, fee_vault, pool_authority] = group else {
+                unreachable!("chunks are exactly CLAIM_BATCH_GROUP_LEN long");
+            };
+            require!(poll_info.is_writable, anchor_lang::error::ErrorCode::AccountNotMutable);
+            let mut poll = Account::<Poll>::try_from(poll_info)?;
+            require!(vote_info.is_writable, anchor_lang::error::ErrorCode::AccountNotMutable);
+            let mut vote = Account::<Vote>::try_from(vote_info)?;
+            require_keys_eq!(vote.poll, poll.key(), AmmError::InvalidVote);
+            require_keys_eq!(vote.user, user, AmmError::Unauthorized);
+            require_keys_eq!(
+                ctx.accounts.user_token_account.mint,
+                poll.token_mint,
+                AmmError::InvalidTokenMint
+            );
+            require_keys_eq!(
+                pool_vault.key(),
+                pool_vault_pda(&poll.key()).0,
+                anchor_lang::error::ErrorCode::ConstraintSeeds
+            );
+            require_keys_eq!(
+                fee_vault.key(),
+                fee_vault_pda(&poll.key()).0,
+                anchor_lang::error::ErrorCode::ConstraintSeeds
+            );
+            let (authority_key, authority_bump) = pool_authority_pda(&poll.key());
+            require_keys_eq!(
+                pool_authority.key(),
+                authority_key,
+                anchor_lang::error::ErrorCode::ConstraintSeeds
+            );
+
+            if poll.status != PollStatus::Resolved
+                || poll.dispute_window_open(now)
+                || poll.claims_expired(now)
+                || poll.parent_poll.is_some()
+                || vote.claimed
+            {
+                continue;
+            }
+            let Some(winning_nft) = poll.winning_nft else {
+                continue;
+            };
+            if poll.outcome_index(&winning_nft) != Some(vote.voted_for_nft as usize - 1) {
+                continue;
+            }
+
+            let payout_amount = poll.payout_for(&vote)?;
+            let claim_fee = bps_of(payout_amount, poll.claim_fee_bps);
+            let net_payout = payout_amount - claim_fee;
+            let vault_balance = Account::<TokenAccount>::try_from(pool_vault)?.amount;
+            poll.pay_out(&mut vote, payout_amount, vault_balance)?;
+
+            let poll_key = poll.key();
+            let seeds = &[POOL_AUTHORITY_SEED, poll_key.as_ref(), &[authority_bump]];
+            let signer = &[&seeds[..]];
+            vault_transfer(
+                ctx.accounts.token_program.to_account_info(),
+                pool_vault.clone(),
+                ctx.accounts.user_token_account.to_account_info(),
+                pool_authority.clone(),
+                signer,
+                net_payout,
+            )?;
+            if claim_fee > 0 {
+                vault_transfer(
+                    ctx.accounts.token_program.to_account_info(),
+                    pool_vault.clone(),
+                    fee_vault.clone(),
+                    pool_authority.clone(),
+                    signer,
+                    claim_fee,
+                )?;
+            }
+
+            vote.exit(&crate::ID)?;
+            poll.exit(&crate::ID)?;
+
+            emit!(WinningsClaimed {
+                poll: poll_key,
+                user,
+                amount: net_payout,
+                gross_amount: payout_amount,
+                claim_fee,
+                entry_price: vote.price_at_transaction,
+                payout_ratio: payout_ratio(payout_amount, vote.value),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn claim_refund(ctx: Context<ClaimRefund>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        let vote = &mut ctx.accounts.vote;
+
+        let now = now()?;
+        // Bets on a disabled outcome are refunded whatever became of the poll
+        let index = vote.voted_for_nft as usize - 1;
+        let disabled = poll.outcome_disabled[index];
+        require!(poll.refunds_open(now) || disabled, AmmError::PollNotCanceled);
+        require!(!vote.claimed, AmmError::AlreadyClaimed);
+        // Refunds of a resolved poll are owed from the same vault balance as
+        // winnings, and expire with them
+        require!(!poll.claims_expired(now), AmmError::ClaimWindowClosed);
+
+        // The stake that reached the pool vault is returned. The network fee
+        // is kept, unless the Config returns the protocol's part of it on
+        // canceled polls; the authority's part may already be withdrawn
+        let refund_amount = vote.stake;
+        let fee_refund = if poll.status == PollStatus::Canceled && ctx.accounts.config.refund_fees_on_cancel {
+            // Fees the admin swept before the cancellation cannot be returned
+            vote.protocol_fee.min(poll.protocol_fees(ctx.accounts.fee_vault.amount))
+        } else {
+            0
+        };
+
+        let pool_auth_bump = ctx.bumps.pool_authority;
+        let binding = poll.key();
+        let seeds = &[
+            POOL_AUTHORITY_SEED,
+            binding.as_ref(),
+            &[pool_auth_bump]
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer,
+        );
+
+        token::transfer(cpi_ctx, refund_amount)?;
+        if fee_refund > 0 {
+            vault_transfer(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.fee_vault.to_account_info(),
+                ctx.accounts.user_token_account.to_account_info(),
+                ctx.accounts.pool_authority.to_account_info(),
+                signer,
+                fee_refund,
+            )?;
+        }
+
+        // Keep the per-outcome totals equal to the stakes still held
+        poll.outcome_pools[index] = poll.outcome_pools[index]
+            .checked_sub(refund_amount)
+            .ok_or(AmmError::MathOverflow)?;
+        // A refunded position leaves its disabled outcome, so the positions
+        // on disabled outcomes are those still waiting for a refund
+        if disabled {
+            poll.positions[index] = poll.positions[index].checked_sub(1).ok_or(AmmError::MathOverflow)?;
+        }
+        poll.vault_liability = poll
+            .vault_liability
+            .checked_sub(refund_amount)
+            .ok_or(AmmError::VaultInsolvent)?;
+        // Only resolved polls count the positions still owed
+        if poll.status == PollStatus::Resolved {
+            poll.settle_position()?;
+        }
+
+        vote.claimed = true;
+
+        emit!(RefundClaimed {
+            poll: poll.key(),
+            user: ctx.accounts.user.key(),
+            amount: refund_amount,
+            fee_refund,
+        });
+
+        Ok(())
+    }
+
+    pub fn close_vote(ctx: Context<CloseVote>) -> Result<()> {
+        // close_poll_accounts only closes a poll once nothing is owed, so a
+        // vote on a closed poll has nothing left to wait for
+        let info = ctx.accounts.poll.to_account_info();
+        if info.data_is_empty() {
+            return Ok(());
+        }
+        require_keys_eq!(*info.owner, crate::ID, anchor_lang::error::ErrorCode::AccountOwnedByWrongProgram);
+        let poll = Poll::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        require!(
+            poll.status == PollStatus::Resolved || poll.status == PollStatus::Canceled,
+            AmmError::PollNotResolved
+        );
+        let vote = &ctx.accounts.vote;
+
+        // A vote is settled once paid out or refunded, or when it lost; an
+        // unclaimed winning vote stays open so its winnings are not forfeited.
+        // Without any winners every vote is owed a refund instead
+        // A vote on a disabled outcome is owed a refund and never loses
+        let index = vote.voted_for_nft as usize - 1;
+        let lost = poll.status == PollStatus::Resolved
+            && poll.winning_side_shares > 0
+            && !poll.outcome_disabled[index]
+            && poll.winning_nft.and_then(|nft| poll.outcome_index(&nft)) != Some(index);
+        // Past the claim deadline nothing is owed any more: sweep_unclaimed
+        // hands what the vault held for it to the treasury
+        let expired = poll.status == PollStatus::Resolved && poll.claims_expired(now()?);
+        require!(vote.claimed || lost || expired, AmmError::VoteNotSettled);
+        // An override within the dispute window could still make it a winner
+        require!(vote.claimed || !poll.dispute_window_open(now()?), AmmError::DisputeWindowOpen);
+
+        // The rent is returned to the user by the `close` constraint
+        Ok(())
+    }
+
+    // Moves what a resolved poll still owes its positions to the treasury
+    // once the claim deadline has passed, so winners who never claim do not
+    // lock the vault forever. The liquidity providers' part of the vault is
+    // left for withdraw_lp, and close_poll_accounts can follow as if every
+    // position had been paid
+    pub fn sweep_unclaimed(ctx: Context<SweepUnclaimed>) -> Result<()> {
+        let poll = &mut ctx.accounts.poll;
+        let now = now()?;
+        require!(poll.status == PollStatus::Resolved, AmmError::PollNotResolved);
+        require!(
+            !poll.dispute_window_open(now) && poll.claims_expired(now),
+            AmmError::ClaimWindowOpen
+        );
+
+        let amount = poll.vault_liability.min(ctx.accounts.pool_vault.amount);
+        let unclaimed_winners = poll.unclaimed_winners;
+        poll.vault_liability = 0;
+        poll.unclaimed_winners = 0;
+
+        let binding = poll.key();
+        let seeds = &[POOL_AUTHORITY_SEED, binding.as_ref(), &[ctx.bumps.pool_authority]];
+        let signer = &[&seeds[..]];
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.pool_vault.to_account_info(),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            amount,
+        )?;
+
+        emit!(UnclaimedSweptEvent {
+            poll: binding,
+            admin: ctx.accounts.admin.key(),
+            treasury: ctx.accounts.treasury.key(),
+            amount,
+            unclaimed_winners,
+        });
+
+        Ok(())
+    }
+
+    // Reclaims the rent of a fully settled poll. Once every position owed a
+    // payout or refund has been paid and the liquidity providers have
+    // withdrawn, the rounding dust left in the pool and fee vaults goes to
+    // the treasury, the authority's unwithdrawn fees go to the authority,
+    // and the vaults and the poll account are closed. Votes still open can
+    // be closed afterwards, see close_vote
+    pub fn close_poll_accounts(ctx: Context<ClosePollAccounts>) -> Result<()> {
+        let poll = &ctx.accounts.poll;
+        let caller = ctx.accounts.caller.key();
+        require!(
+            poll.authority == caller || ctx.accounts.config.admin == caller,
+            AmmError::Unauthorized
+        );
+        require!(poll.status == PollStatus::Resolved, AmmError::PollNotResolved);
+        require!(
+            !poll.dispute_window_open(now()?),
+            AmmError::DisputeWindowOpen
+        );
+        require!(poll.unclaimed_winners == 0, AmmError::UnclaimedWinnings);
+        require!(poll.lp_deposits == 0, AmmError::LiquidityProvidersUnpaid);
+
+        let binding = poll.key();
+        let seeds = &[POOL_AUTHORITY_SEED, binding.as_ref(), &[ctx.bumps.pool_authority]];
+        let signer = &[&seeds[..]];
+        let swept = ctx.accounts.pool_vault.amount;
+        let fees = ctx.accounts.fee_vault.amount;
+        let authority_fees = ctx.accounts.authority_fee_vault.amount;
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.pool_vault.to_account_info(),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            swept,
+        )?;
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.fee_vault.to_account_info(),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            fees,
+        )?;
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.authority_fee_vault.to_account_info(),
+            ctx.accounts.authority_token_account.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            signer,
+            authority_fees,
+        )?;
+
+        // The authority paid the vaults' rent in create_poll
+        for vault in [
+            ctx.accounts.pool_vault.to_account_info(),
+            ctx.accounts.fee_vault.to_account_info(),
+            ctx.accounts.authority_fee_vault.to_account_info(),
+        ] {
+            token::close_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: vault,
+                    destination: ctx.accounts.authority.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                signer,
+            ))?;
+        }
+
+        emit!(PollAccountsClosedEvent {
+            poll: binding,
+            treasury: ctx.accounts.treasury.key(),
+            swept,
+            fees,
+            authority_fees,
+        });
+
+        // The poll's own rent is returned by the `close` constraint
+        Ok(())
+    }
+
+    // Upgrades a poll written in an older layout to POLL_VERSION. A poll of
+    // the first deployment, from before accounts were versioned, is decoded
+    // as that layout was defined, see LegacyPoll, and enters as version 1;
+    // from there Poll::upgrade takes it one version at a time. The account
+    // is resized to the current layout and the payer funds any extra rent
+    pub fn migrate_poll(ctx: Context<MigratePoll>) -> Result<()> {
+        let info = ctx.accounts.poll.to_account_info();
+        let (mut poll, from_version) = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() > 8 && data[..8] == *Poll::DISCRIMINATOR,
+                anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch
+            );
+            // Unversioned polls were all allocated at the fixed size of their
+            // layout, which is smaller than any versioned poll
+            if data.len() == 8 + LegacyPoll::LEN {
+                (LegacyPoll::deserialize(&mut &data[8..])?.into_poll(&clock()?), 0)
+            } else {
+                let version = data[8];
+                require!(version < POLL_VERSION, AmmError::PollAlreadyMigrated);
+                require!(version > 0, anchor_lang::error::ErrorCode::AccountDidNotDeserialize);
+                // Each version only appended fields, all of them vectors or
+                // numbers that zero bytes encode as empty or zero, so an
+                // older account reads in the current layout once its
+                // missing tail is zero-filled
+                let mut fields = data[8..].to_vec();
+                fields.resize(fields.len().max(Poll::LEN), 0);
+                (Poll::deserialize(&mut &fields[..])?, version)
+            }
+        };
+        require_keys_eq!(ctx.accounts.authority.key(), poll.authority, AmmError::Unauthorized);
+        require_keys_eq!(ctx.accounts.token_mint.key(), poll.token_mint, AmmError::InvalidTokenMint);
+        if from_version == 0 {
+            // The first deployment paid winners by its own rule and kept no
+            // record of who was paid, so a poll it resolved cannot be settled
+            // under the current one
+            require!(poll.status != PollStatus::Resolved, AmmError::LegacyPollResolved);
+            // Nor did it keep registries: count the poll now, so resolving or
+            // canceling it has something to release
+            let registry = &mut ctx.accounts.registry;
+            registry.authority = poll.authority;
+            if matches!(poll.status, PollStatus::Active | PollStatus::Closed) {
+                registry.active_poll_count = registry.active_poll_count.checked_add(1).ok_or(AmmError::MathOverflow)?;
+            }
+        }
+        poll.upgrade(from_version.max(1));
+        let space = Poll::space(poll.title.len(), poll.outcomes.len(), poll.metadata_uri.len());
+
+        let rent = Rent::get()?.minimum_balance(space).saturating_sub(info.lamports());
+        if rent > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                rent,
+            )?;
+        }
+        info.realloc(space, true)?;
+        poll.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        emit!(PollMigratedEvent {
+            poll: info.key(),
+            from_version,
+            version: poll.version,
+        });
+
+        Ok(())
+    }
+
+    // Carries a vote of the first deployment over to the current layout once
+    // its poll is migrated. Its stake moves from the vault the vote was paid
+    // into to the poll's pool vault and is booked on the poll as a bet would
+    // be, so the position settles like any other. Such votes were keypair
+    // accounts of 8 + LegacyVote::LEN bytes; they keep their address and
+    // grow in place
+    pub fn migrate_vote(ctx: Context<MigrateVote>) -> Result<()> {
+        let info = ctx.accounts.vote.to_account_info();
+        let legacy = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() > 8 && data[..8] == *Vote::DISCRIMINATOR,
+                anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch
+            );
+            require!(data.len() == 8 + LegacyVote::LEN, AmmError::VoteAlreadyMigrated);
+            LegacyVote::deserialize(&mut &data[8..])?
+        };
+        let poll = &mut ctx.accounts.poll;
+        require_keys_eq!(legacy.poll, poll.key(), AmmError::InvalidVote);
+        require!(poll.status != PollStatus::Resolved, AmmError::LegacyPollResolved);
+        require!(
+            legacy.voted_for_nft >= 1 && legacy.voted_for_nft as usize <= poll.outcomes.len(),
+            AmmError::InvalidNftChoice
+        );
+        let index = legacy.voted_for_nft as usize - 1;
+        // The first deployment kept a flat 3% of every bet as its fee
+        let stake = legacy.value - bps_of(legacy.value, 300);
+
+        let pool_auth_bump = ctx.bumps.pool_authority;
+        let binding = poll.key();
+        let seeds = &[
+            POOL_AUTHORITY_SEED,
+            binding.as_ref(),
+            &[pool_auth_bump]
+        ];
+        vault_transfer(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.legacy_vault.to_account_info(),
+            ctx.accounts.pool_vault.to_account_info(),
+            ctx.accounts.pool_authority.to_account_info(),
+            &[&seeds[..]],
+            stake,
+        )?;
+
+        poll.outcome_pools[index] = poll.outcome_pools[index].checked_add(stake).ok_or(AmmError::MathOverflow)?;
+        poll.vault_liability = poll.vault_liability.checked_add(stake).ok_or(AmmError::MathOverflow)?;
+        poll.vote_shares[index] = poll.vote_shares[index]
+            .checked_add(legacy.amount)
+            .ok_or(AmmError::MathOverflow)?;
+        poll.positions[index] = poll.positions[index].checked_add(1).ok_or(AmmError::MathOverflow)?;
+        poll.total_volume = poll.total_volume.checked_add(legacy.value).ok_or(AmmError::MathOverflow)?;
+        poll.vote_count = poll.vote_count.checked_add(1).ok_or(AmmError::MathOverflow)?;
+
+        // No timestamps, bonus or refundable fee were kept for these votes
+        let vote = Vote {
+            poll: legacy.poll,
+            user: legacy.user,
+            voted_for_nft: legacy.voted_for_nft,
+            amount: legacy.amount,
+            value: legacy.value,
+            price_at_transaction: legacy.price_at_transaction,
+            claimed: false,
+            stake,
+            created_at: 0,
+            bonus: 0,
+            last_vote_at: 0,
+            claimed_amount: 0,
+            protocol_fee: 0,
+        };
+        let space = 8 + Vote::LEN;
+        let rent = Rent::get()?.minimum_balance(space).saturating_sub(info.lamports());
+        if rent > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                rent,
+            )?;
+        }
+        info.realloc(space, true)?;
+        vote.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        emit!(VoteMigratedEvent {
+            poll: poll.key(),
+            vote: info.key(),
+            user: vote.user,
+            stake,
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(init, payer = admin, space = 8 + Config::LEN, seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    // Whoever deployed the program claims the admin seat, so nobody can take
+    // it by initializing the config first
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(admin.key()) @ AmmError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.pending_admin == Some(new_admin.key()) @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub new_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxActivePolls<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeeRounding<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRefundFeesOnCancel<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetDefaultFee<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetClaimWindow<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxTitleLen<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAllowedMints<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    title_bytes: Vec<u8>,
+    closes_at: i64,
+    outcomes: Vec<Pubkey>,
+    initial_shares: Vec<u64>,
+    required_parent_outcome: Option<Pubkey>,
+    claim_fee_bps: u16,
+    resolution_mode: ResolutionMode,
+    fee_bps: u16,
+    min_bet: u64,
+    max_bet: u64,
+    metadata_uri: Vec<u8>
+)]
+pub struct CreatePoll<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = Poll::space(title_bytes.len(), outcomes.len(), metadata_uri.len())
+    )]
+    pub poll: Account<'info, Poll>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + PollRegistry::LEN,
+        seeds = [REGISTRY_SEED, authority.key().as_ref()],
+        bump
+    )]
+    pub registry: Account<'info, PollRegistry>,
+    /// The token mint that will be used for this poll
+    pub token_mint: Account<'info, Mint>,
+    /// Parent poll when creating a conditional market
+    pub parent_poll: Option<Account<'info, Poll>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_authority
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_authority
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_authority
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    nft_choice: u8,
+    amount: u64,
+    min_shares_out: u64,
+    max_price_impact_bps: Option<u16>,
+    beneficiary: Option<Pubkey>
+)]
+pub struct VoteOnPoll<'info> {
+    #[account(
+        mut,
+        constraint = poll.status != PollStatus::Closed @ AmmError::PollClosed,
+        constraint = poll.status == PollStatus::Active @ AmmError::PollNotActive
+    )]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ AmmError::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Vote::LEN,
+        seeds = [VOTE_SEED, poll.key().as_ref(), beneficiary.unwrap_or(user.key()).as_ref()],
+        bump
+    )]
+    pub vote: Account<'info, Vote>,
+    // Required when the bet is credited to a beneficiary other than the user
+    #[account(seeds = [DELEGATION_SEED, beneficiary.unwrap_or(user.key()).as_ref(), user.key().as_ref()], bump)]
+    pub delegation: Option<Account<'info, Delegation>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut, 
+        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        // A frozen vault would reject the stake transfer; fail before the swap
+        constraint = !pool_vault.is_frozen() @ AmmError::VaultFrozen
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(first: SplitLeg, second: SplitLeg)]
+pub struct VoteSplit<'info> {
+    #[account(
+        mut,
+        // A hedge needs a second outcome
+        constraint = first.nft_choice != second.nft_choice @ AmmError::InvalidNftChoice,
+        constraint = poll.status != PollStatus::Closed @ AmmError::PollClosed,
+        constraint = poll.status == PollStatus::Active @ AmmError::PollNotActive
+    )]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ AmmError::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Vote::LEN,
+        seeds = [VOTE_SEED, poll.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub vote: Account<'info, Vote>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Vote::LEN,
+        seeds = [VOTE_SEED, poll.key().as_ref(), user.key().as_ref(), &[second.nft_choice]],
+        bump
+    )]
+    pub second_vote: Account<'info, Vote>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        constraint = !pool_vault.is_frozen() @ AmmError::VaultFrozen
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct QuoteVote<'info> {
+    pub poll: Account<'info, Poll>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct GetOdds<'info> {
+    pub poll: Account<'info, Poll>,
+}
+
+#[derive(Accounts)]
+pub struct GetPosition<'info> {
+    pub poll: Account<'info, Poll>,
+}
+
+#[derive(Accounts)]
+pub struct GetPollState<'info> {
+    pub poll: Account<'info, Poll>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    nft_choice: u8,
+    amount: u64,
+    min_shares_out: u64,
+    max_price_impact_bps: Option<u16>,
+    beneficiary: Option<Pubkey>
+)]
+pub struct VoteSol<'info> {
+    #[account(
+        mut,
+        constraint = poll.status == PollStatus::Active @ AmmError::PollNotActive,
+        constraint = poll.token_mint == native_mint::ID @ AmmError::NotNativeSol
+    )]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ AmmError::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Vote::LEN,
+        seeds = [VOTE_SEED, poll.key().as_ref(), beneficiary.unwrap_or(user.key()).as_ref()],
+        bump
+    )]
+    pub vote: Account<'info, Vote>,
+    // Required when the bet is credited to a beneficiary other than the user
+    #[account(seeds = [DELEGATION_SEED, beneficiary.unwrap_or(user.key()).as_ref(), user.key().as_ref()], bump)]
+    pub delegation: Option<Account<'info, Delegation>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        // A frozen vault would reject the stake transfer; fail before the swap
+        constraint = !pool_vault.is_frozen() @ AmmError::VaultFrozen
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct ApproveDelegate<'info> {
+    #[account(
+        init,
+        payer = beneficiary,
+        space = 8 + Delegation::LEN,
+        seeds = [DELEGATION_SEED, beneficiary.key().as_ref(), delegate.as_ref()],
+        bump
+    )]
+    pub delegation: Account<'info, Delegation>,
+    #[account(mut)]
+    pub beneficiary: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeDelegate<'info> {
+    #[account(
+        mut,
+        close = beneficiary,
+        constraint = delegation.beneficiary == beneficiary.key() @ AmmError::Unauthorized
+    )]
+    pub delegation: Account<'info, Delegation>,
+    #[account(mut)]
+    pub beneficiary: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SellPosition<'info> {
+    #[account(
+        mut,
+        constraint = poll.status != PollStatus::Closed @ AmmError::PollClosed,
+        constraint = poll.status == PollStatus::Active @ AmmError::PollNotActive
+    )]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ AmmError::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        close = user,
+        constraint = vote.poll == poll.key() @ AmmError::InvalidVote,
+        constraint = vote.user == user.key() @ AmmError::Unauthorized
+    )]
+    pub vote: Account<'info, Vote>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ResolvePoll<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, seeds = [REGISTRY_SEED, poll.authority.as_ref()], bump)]
+    pub registry: Account<'info, PollRegistry>,
+    /// Required when resolving a conditional poll
+    pub parent_poll: Option<Account<'info, Poll>>,
+    /// Token account of the resolver holding the winning NFT, required when
+    /// the poll asks for an ownership proof
+    pub ownership_proof: Option<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveByConsensus<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    pub caller: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, seeds = [REGISTRY_SEED, poll.authority.as_ref()], bump)]
+    pub registry: Account<'info, PollRegistry>,
+    /// Required when resolving a conditional poll
+    pub parent_poll: Option<Account<'info, Poll>>,
+}
+
+#[derive(Accounts)]
+pub struct OverrideResolution<'info> {
+    #[account(mut, constraint = poll.status == PollStatus::Resolved @ AmmError::PollNotResolved)]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelPoll<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, seeds = [REGISTRY_SEED, poll.authority.as_ref()], bump)]
+    pub registry: Account<'info, PollRegistry>,
+}
+
+#[derive(Accounts)]
+pub struct ForceCancelStale<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    pub caller: Signer<'info>,
+    #[account(mut, seeds = [REGISTRY_SEED, poll.authority.as_ref()], bump)]
+    pub registry: Account<'info, PollRegistry>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendPoll<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetPollFee<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct DisableOutcome<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(metadata_uri: Vec<u8>)]
+pub struct UpdateMetadata<'info> {
+    #[account(
+        mut,
+        constraint = poll.authority == authority.key() @ AmmError::Unauthorized,
+        realloc = Poll::space(poll.title.len(), poll.outcomes.len(), metadata_uri.len()),
+        realloc::payer = authority,
+        realloc::zero = false
+    )]
+    pub poll: Account<'info, Poll>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePoll<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct BatchClose<'info> {
+    pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ AmmError::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + LpPosition::LEN,
+        seeds = [LP_SEED, poll.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub lp_position: Account<'info, LpPosition>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveLiquidity<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    pub authority: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        constraint = authority_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = authority_token_account.owner == authority.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub authority_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    /// The authority's own position, if it provided liquidity
+    #[account(
+        mut,
+        seeds = [LP_SEED, poll.key().as_ref(), authority.key().as_ref()],
+        bump
+    )]
+    pub lp_position: Option<Account<'info, LpPosition>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    pub poll: Account<'info, Poll>,
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = destination.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = destination.owner == admin.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub destination: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawAuthorityFees<'info> {
+    #[account(constraint = poll.authority == authority.key() @ AmmError::Unauthorized)]
+    pub poll: Account<'info, Poll>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = destination.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = destination.owner == authority.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub destination: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLp<'info> {
+    #[account(
+        mut,
+        constraint = poll.status == PollStatus::Resolved || poll.status == PollStatus::Canceled
+            @ AmmError::PollNotResolved
+    )]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        mut,
+        close = provider,
+        seeds = [LP_SEED, poll.key().as_ref(), provider.key().as_ref()],
+        bump
+    )]
+    pub lp_position: Account<'info, LpPosition>,
+    #[account(mut)]
+    pub provider: Signer<'info>,
+    #[account(
+        mut,
+        constraint = provider_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = provider_token_account.owner == provider.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub provider_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWinnings<'info> {
+    #[account(mut, constraint = poll.status == PollStatus::Resolved @ AmmError::PollNotResolved)]
+    pub poll: Account<'info, Poll>,
+    
+    #[account(
+        mut, 
+        constraint = vote.poll == poll.key() @ AmmError::InvalidVote,
+        constraint = vote.user == user.key() @ AmmError::Unauthorized
+    )]
+    pub vote: Account<'info, Vote>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner,
+        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    // Receives the payout instead of user_token_account when given; any
+    // owner is accepted, only the mint has to match
+    #[account(
+        mut,
+        constraint = destination.mint == poll.token_mint @ AmmError::InvalidTokenMint
+    )]
+    pub destination: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+
+    /// Required when claiming from a conditional poll
+    pub parent_poll: Option<Account<'info, Poll>>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWinningsSol<'info> {
+    #[account(
+        mut,
+        constraint = poll.status == PollStatus::Resolved @ AmmError::PollNotResolved,
+        constraint = poll.token_mint == native_mint::ID @ AmmError::NotNativeSol
+    )]
+    pub poll: Account<'info, Poll>,
+
+    #[account(
+        mut,
+        constraint = vote.poll == poll.key() @ AmmError::InvalidVote,
+        constraint = vote.user == user.key() @ AmmError::Unauthorized
+    )]
+    pub vote: Account<'info, Vote>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    // Temporary wSOL account the payout is unwrapped through, closed again
+    // before the instruction returns
+    #[account(
+        init,
+        payer = user,
+        seeds = [UNWRAP_SEED, poll.key().as_ref(), user.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_authority
+    )]
+    pub unwrap_account: Account<'info, TokenAccount>,
+
+    #[account(address = poll.token_mint @ AmmError::InvalidTokenMint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+
+    /// Required when claiming from a conditional poll
+    pub parent_poll: Option<Account<'info, Poll>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWinningsBatch<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRefund<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+
+    #[account(
+        mut,
+        constraint = vote.poll == poll.key() @ AmmError::InvalidVote,
+        constraint = vote.user == user.key() @ AmmError::Unauthorized
+    )]
+    pub vote: Account<'info, Vote>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.owner == user.key() @ AmmError::InvalidTokenOwner,
+        constraint = user_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVote<'info> {
+    /// CHECK: the vote's poll, or the empty account left once close_poll_accounts closed it; close_vote decodes it
+    pub poll: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        close = user,
+        constraint = vote.poll == poll.key() @ AmmError::InvalidVote,
+        constraint = vote.user == user.key() @ AmmError::Unauthorized
+    )]
+    pub vote: Account<'info, Vote>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SweepUnclaimed<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.admin == admin.key() @ AmmError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    /// Receives the unclaimed winnings
+    #[account(
+        mut,
+        constraint = treasury.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = treasury.owner == config.admin @ AmmError::InvalidTokenOwner
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePollAccounts<'info> {
+    #[account(mut, close = authority)]
+    pub poll: Account<'info, Poll>,
+    pub caller: Signer<'info>,
+    /// CHECK: the poll authority, who receives the rent
+    #[account(mut, address = poll.authority @ AmmError::Unauthorized)]
+    pub authority: UncheckedAccount<'info>,
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    /// Receives the dust and the protocol's fees
+    #[account(
+        mut,
+        constraint = treasury.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = treasury.owner == config.admin @ AmmError::InvalidTokenOwner
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = authority_token_account.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = authority_token_account.owner == poll.authority @ AmmError::InvalidTokenOwner
+    )]
+    pub authority_token_account: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePoll<'info> {
+    /// CHECK: a poll in an older layout, which Account<Poll> cannot decode;
+    /// migrate_poll checks the discriminator itself
+    #[account(mut, owner = crate::ID)]
+    pub poll: UncheckedAccount<'info>,
+    /// CHECK: migrate_poll checks it is the poll's authority
+    pub authority: UncheckedAccount<'info>,
+    // Polls of the first deployment had neither a registry nor PDA vaults;
+    // later ones already have both
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + PollRegistry::LEN,
+        seeds = [REGISTRY_SEED, authority.key().as_ref()],
+        bump
+    )]
+    pub registry: Account<'info, PollRegistry>,
+    /// The poll's mint, checked in migrate_poll
+    pub token_mint: Account<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [POOL_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_authority
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [FEE_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_authority
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [AUTHORITY_FEE_VAULT_SEED, poll.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool_authority
+    )]
+    pub authority_fee_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVote<'info> {
+    #[account(mut)]
+    pub poll: Account<'info, Poll>,
+    /// CHECK: a vote in the first deployment's layout, which Account<Vote>
+    /// cannot decode; migrate_vote checks the discriminator and size itself
+    #[account(mut, owner = crate::ID)]
+    pub vote: UncheckedAccount<'info>,
+    // The first deployment took any vault of the poll's mint, but could only
+    // pay out of one the pool authority owns
+    #[account(
+        mut,
+        constraint = legacy_vault.key() != pool_vault.key() @ AmmError::InvalidLegacyVault,
+        constraint = legacy_vault.mint == poll.token_mint @ AmmError::InvalidTokenMint,
+        constraint = legacy_vault.owner == pool_authority.key() @ AmmError::InvalidLegacyVault
+    )]
+    pub legacy_vault: Account<'info, TokenAccount>,
+    #[account(mut, seeds = [POOL_VAULT_SEED, poll.key().as_ref()], bump)]
+    pub pool_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA that serves as the pool authority
+    #[account(
+        seeds = [POOL_AUTHORITY_SEED, poll.key().as_ref()],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+pub struct Config {
+    pub admin: Pubkey,         // May resolve, cancel and withdraw from any poll
+    pub paused: bool,          // Blocks new votes, sales and liquidity while set
+    pub pending_admin: Option<Pubkey>, // Proposed successor, until they accept
+    pub max_active_polls: u32, // Unsettled polls allowed per authority, 0 for no cap
+    pub allowed_mints: Vec<Pubkey>, // Mints new polls may use, any mint while empty
+    pub round_fee_up: bool,    // Round network fees up instead of down
+    pub default_fee_bps: u16,  // Network fee of polls that ask for the default
+    pub max_title_len: u16,    // Longest title new polls may use, at most MAX_TITLE_LEN
+    pub refund_fees_on_cancel: bool, // Refunds on canceled polls return the protocol fee too
+    pub claim_window: i64,     // Seconds to claim after resolution, 0 for no deadline
+}
+
+impl Config {
+    pub const LEN: usize = 32 + // admin
+                          1 + // paused
+                          33 + // pending_admin option
+                          4 + // max_active_polls
+                          4 + 32 * MAX_ALLOWED_MINTS + // allowed_mints
+                          1 + // round_fee_up
+                          2 + // default_fee_bps
+                          2 + // max_title_len
+                          1 + // refund_fees_on_cancel
+                          8; // claim_window
+}
+
+#[account]
+pub struct Poll {
+    pub version: u8,           // Layout version, POLL_VERSION for current accounts
+    pub authority: Pubkey,
+    pub title: Vec<u8>,        // Using a fixed-size Vec<u8> instead of String
+    pub closes_at: i64,
+    pub outcomes: Vec<Pubkey>, // NFTs the poll is about, at most MAX_OUTCOMES
+    pub shares: Vec<u64>,      // AMM reserve for each outcome
+    pub status: PollStatus,
+    pub winning_nft: Option<Pubkey>,
+    pub token_mint: Pubkey,    // Track which token mint is used for this poll
+    pub parent_poll: Option<Pubkey>,             // Poll this market is conditional on
+    pub required_parent_outcome: Option<Pubkey>, // NFT the parent must resolve to
+    pub claim_fee_bps: u16,    // Fee taken from winnings at claim time
+    pub resolution_mode: ResolutionMode,
+    pub outcome_pools: Vec<u64>, // Tokens staked on each outcome (after fees)
+    pub price_cumulative: Vec<u128>, // Sum of each outcome's price (bps) times slots elapsed
+    pub last_price_update: i64, // Timestamp of the last accumulator update
+    pub last_update_slot: u64,  // Slot of the last accumulator update
+    pub fee_bps: u16,          // Network fee charged on each vote
+    pub vote_shares: Vec<u64>, // Shares held by votes on each outcome
+    pub winning_side_shares: u64, // Shares on the winning side, set at resolution
+    pub liquidity: u64,        // Tokens deposited through add_liquidity, plus sale gains, still held
+    pub total_volume: u64,     // Tokens wagered across all votes, fees included
+    pub vote_count: u64,       // Number of votes cast
+    pub min_bet: u64,          // Smallest amount a single vote may wager
+    pub max_bet: u64,          // Largest amount a single vote may wager
+    pub metadata_uri: Vec<u8>, // Off-chain JSON with the poll's description, images and rules
+    pub resolver: Pubkey,      // May resolve the poll alongside the authority and admin
+    pub dispute_period: i64,   // Seconds after resolution before winnings can be claimed
+    pub resolved_at: i64,      // Timestamp of the resolution, 0 until resolved
+    pub vault_liability: u64,  // Tokens the pool vault owes to positions: stakes, less winnings paid out
+    pub mint_decimals: u8,     // Decimals of token_mint, for displaying amounts
+    pub lp_deposits: u64,      // Tokens deposited by liquidity providers that have not withdrawn
+    pub created_at: i64,       // Timestamp the poll was created
+    pub bonus_enabled: bool,   // Whether early bets earn bonus shares
+    pub closed_at: i64,        // Timestamp the poll was explicitly closed, 0 if it never was
+    pub authority_fee_bps: u16, // Part of fee_bps paid to the poll authority rather than the protocol
+    pub positions: Vec<u32>,   // Open vote accounts on each outcome
+    pub unclaimed_winners: u32, // Positions still owed a payout or refund, counted once the winner is known
+    pub resolution_deadline: i64, // After this anyone may cancel the unresolved poll, 0 for never
+    pub require_ownership_proof: bool, // Whether resolve_poll needs a token account holding the winning NFT
+    pub min_vote_interval: i64, // Seconds a user must wait between bets on this poll, 0 for no limit
+    pub require_votes: bool,   // Whether resolve_poll refuses a poll nobody voted on
+    pub final_prices: Vec<u64>, // Price of each outcome in basis points when the poll resolved
+    pub min_reserve: u64,      // No bet may take a reserve below this, 0 for no floor
+    pub claim_deadline: i64,   // Claims expire and may be swept from this time on, 0 for never
+    pub outcome_disabled: Vec<bool>, // Outcomes taken out of the market by disable_outcome
+    pub lp_fees: u64,          // Fees in the fee vault set aside for liquidity providers
+    pub fee_per_deposit: u128, // Providers' fees charged per deposited token, scaled by FEE_PER_DEPOSIT_SCALE
+}
+
+impl Poll {
+    pub const LEN: usize = 1 + // version
+                          32 + // authority 
+                          4 + MAX_TITLE_LEN + // title
+                          8 + // closes_at
+                          4 + 32 * MAX_OUTCOMES + // outcomes
+                          4 + 8 * MAX_OUTCOMES + // shares
+                          1 + // status enum
+                          33 + // winning_nft option
+                          32 + // token_mint
+                          33 + // parent_poll option
+                          33 + // required_parent_outcome option
+                          2 + // claim_fee_bps
+                          1 + // resolution_mode enum
+                          4 + 8 * MAX_OUTCOMES + // outcome_pools
+                          4 + 16 * MAX_OUTCOMES + // price_cumulative
+                          8 + // last_price_update
+                          8 + // last_update_slot
+                          2 + // fee_bps
+                          4 + 8 * MAX_OUTCOMES + // vote_shares
+                          8 + // winning_side_shares
+                          8 + // liquidity
+                          8 + // total_volume
+                          8 + // vote_count
+                          8 + // min_bet
+                          8 + // max_bet
+                          4 + MAX_METADATA_URI_LEN + // metadata_uri
+                          32 + // resolver
+                          8 + // dispute_period
+                          8 + // resolved_at
+                          8 + // vault_liability
+                          1 + // mint_decimals
+                          8 + // lp_deposits
+                          8 + // created_at
+                          1 + // bonus_enabled
+                          8 + // closed_at
+                          2 + // authority_fee_bps
+                          4 + 4 * MAX_OUTCOMES + // positions
+                          4 + // unclaimed_winners
+                          8 + // resolution_deadline
+                          1 + // require_ownership_proof
+                          8 + // min_vote_interval
+                          1 + // require_votes
+                          4 + 8 * MAX_OUTCOMES + // final_prices
+                          8 + // min_reserve
+                          8 + // claim_deadline
+                          4 + MAX_OUTCOMES + // outcome_disabled
+                          8 + // lp_fees
+                          16; // fee_per_deposit
+
+    // Bytes every outcome takes up: its NFT, reserve, pool, cumulative
+    // price, vote shares, position count, final price and disabled flag
+    const PER_OUTCOME_LEN: usize = 32 + 8 + 8 + 16 + 8 + 4 + 8 + 1;
+
+    // Account size, discriminator included, for a poll with these
+    // variable-length fields; LEN reserves room for the largest ones
+    pub fn space(title_len: usize, outcome_count: usize, metadata_uri_len: usize) -> usize {
+        let fixed = Self::LEN - MAX_TITLE_LEN - MAX_OUTCOMES * Self::PER_OUTCOME_LEN - MAX_METADATA_URI_LEN;
+        8 + fixed + title_len + outcome_count * Self::PER_OUTCOME_LEN + metadata_uri_len
+    }
+
+    // Splits the network fee charged on `amount` into the protocol's and the
+    // authority's parts; rounding favors the protocol
+    pub fn split_fee(&self, amount: u64, fee: u64) -> (u64, u64) {
+        let authority_fee = bps_of(amount, self.authority_fee_bps).min(fee);
+        (fee - authority_fee, authority_fee)
+    }
+
+    // Sets aside the providers' part of a protocol fee just charged, spread
+    // over the deposits invested at that moment, and returns the part left
+    // to the protocol. A deposit made later earns nothing from this fee
+    pub fn book_lp_fee(&mut self, protocol_fee: u64) -> Result<u64> {
+        if self.lp_deposits == 0 {
+            return Ok(protocol_fee);
+        }
+        let lp_fee = bps_of(protocol_fee, LP_FEE_SHARE_BPS);
+        let per_deposit = (lp_fee as u128)
+            .checked_mul(FEE_PER_DEPOSIT_SCALE)
+            .ok_or(AmmError::MathOverflow)?
+            / self.lp_deposits as u128;
+        self.fee_per_deposit = self.fee_per_deposit.checked_add(per_deposit).ok_or(AmmError::MathOverflow)?;
+        self.lp_fees = self.lp_fees.checked_add(lp_fee).ok_or(AmmError::MathOverflow)?;
+        Ok(protocol_fee - lp_fee)
+    }
+
+    // Part of the fee vault's balance that belongs to the protocol
+    pub fn protocol_fees(&self, fee_vault_balance: u64) -> u64 {
+        fee_vault_balance.saturating_sub(self.lp_fees)
+    }
+
+    // Tokens staked across all outcomes
+    pub fn total_pool(&self) -> Result<u64> {
+        self.outcome_pools
+            .iter()
+            .try_fold(0u64, |total, &stake| total.checked_add(stake))
+            .ok_or(error!(AmmError::MathOverflow))
+    }
+
+    // Stakes the winners split: all of them but those owed back to voters
+    // on disabled outcomes
+    pub fn prize_pool(&self) -> Result<u64> {
+        Ok(self.total_pool()? - self.refunds_owed())
+    }
+
+    pub fn outcome_index(&self, nft: &Pubkey) -> Option<usize> {
+        self.outcomes.iter().position(|outcome| outcome == nft)
+    }
+
+    // Fee, shares received and reserves after a bet of `amount` on the
+    // 1-based `nft_choice`, without changing the poll. Shared by `vote` and
+    // `quote_vote` so quotes always match what a vote would do
+    pub fn quote_bet(&self, nft_choice: u8, amount: u64, round_fee_up: bool) -> Result<(u64, u64, Vec<u64>)> {
+        self.quote_bet_with_fee(nft_choice, amount, network_fee(amount, self.fee_bps, round_fee_up))
+    }
+
+    // quote_bet for a bet whose network fee was already worked out, such as
+    // a leg's share of the fee on a split vote
+    pub fn quote_bet_with_fee(&self, nft_choice: u8, amount: u64, fee: u64) -> Result<(u64, u64, Vec<u64>)> {
+        // nft_choice is the 1-based position of the outcome in poll.outcomes
+        require!(
+            nft_choice >= 1 && nft_choice as usize <= self.outcomes.len(),
+            AmmError::InvalidNftChoice
+        );
+        // Bet limits keep out dust that rounds to no shares and whale bets
+        // that drain the reserves
+        require!(amount >= self.min_bet, AmmError::BetTooSmall);
+        require!(amount <= self.max_bet, AmmError::BetTooLarge);
+        // Deduct the network fee. Even rounded up it never exceeds the
+        // amount, as fee_bps is capped at MAX_FEE_BPS
+        let amount_after_fee = amount.checked_sub(fee).ok_or(AmmError::MathOverflow)?;
+        // AMM swap logic: buy shares of the chosen outcome with every other reserve
+        let swap = self.buy(nft_choice as usize - 1, amount_after_fee)?;
+        self.require_reserve_floor(&swap.reserves)?;
+        Ok((fee, swap.amount_out, swap.reserves))
+    }
+
+    // On a thin pool a small trade could swing the odds to near-certainty,
+    // so neither a bet nor a sale may leave a reserve below the poll's floor
+    pub fn require_reserve_floor(&self, reserves: &[u64]) -> Result<()> {
+        require!(
+            self.live_outcomes().all(|i| reserves[i] >= self.min_reserve),
+            AmmError::NotEnoughLiquidity
+        );
+        Ok(())
+    }
+
+    // Outcomes still in the market. A disabled outcome's reserve is empty
+    // and takes no part in prices or swaps
+    pub fn live_outcomes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.outcomes.len()).filter(|&i| !self.outcome_disabled[i])
+    }
+
+    // compute_prices over the live outcomes of `reserves`; disabled
+    // outcomes are priced at zero
+    pub fn prices_of(&self, reserves: &[u64]) -> Result<Vec<u64>> {
+        Ok(self.scatter(compute_prices(&self.gather(reserves))?))
+    }
+
+    // compute_odds over the live outcomes, which share the whole 10000
+    pub fn odds_of(&self, reserves: &[u64]) -> Result<Vec<u64>> {
+        Ok(self.scatter(compute_odds(&self.gather(reserves))?))
+    }
+
+    // swap_shares against the live reserves only
+    fn buy(&self, index: usize, amount_in: u64) -> Result<SwapResult> {
+        self.trade(index, amount_in, swap_shares)
+    }
+
+    // sell_shares against the live reserves only
+    fn sell(&self, index: usize, shares_in: u64) -> Result<SwapResult> {
+        self.trade(index, shares_in, sell_shares)
+    }
+
+    fn trade(&self, index: usize, amount: u64, swap: fn(&[u64], usize, u64) -> Result<SwapResult>) -> Result<SwapResult> {
+        require!(!self.outcome_disabled[index], AmmError::OutcomeDisabled);
+        let live_index = self.live_outcomes().position(|i| i == index).unwrap();
+        let result = swap(&self.gather(&self.shares), live_index, amount)?;
+        let mut reserves = self.shares.clone();
+        for (i, reserve) in self.live_outcomes().zip(result.reserves) {
+            reserves[i] = reserve;
+        }
+        Ok(SwapResult { amount_out: result.amount_out, reserves })
+    }
+
+    // The live outcomes' entries of a per-outcome vector
+    fn gather(&self, values: &[u64]) -> Vec<u64> {
+        self.live_outcomes().map(|i| values[i]).collect()
+    }
+
+    // Inverse of gather, with zero for every disabled outcome
+    fn scatter(&self, values: Vec<u64>) -> Vec<u64> {
+        let mut all = vec![0; self.outcomes.len()];
+        for (i, value) in self.live_outcomes().zip(values) {
+            all[i] = value;
+        }
+        all
+    }
+
+    // Stakes on disabled outcomes not yet refunded. They are owed back to
+    // their voters and not to the winners
+    pub fn refunds_owed(&self) -> u64 {
+        self.outcomes_disabled().map(|i| self.outcome_pools[i]).sum()
+    }
+
+    // Positions on disabled outcomes still waiting for their refund
+    pub fn disabled_positions(&self) -> u32 {
+        self.outcomes_disabled().map(|i| self.positions[i]).sum()
+    }
+
+    fn outcomes_disabled(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.outcomes.len()).filter(|&i| self.outcome_disabled[i])
+    }
+
+    // A poll is resolved once: a second resolution, or resolving a canceled
+    // poll, is rejected with its own error
+    pub fn require_unresolved(&self) -> Result<()> {
+        match self.status {
+            PollStatus::Resolved => err!(AmmError::PollAlreadyResolved),
+            PollStatus::Canceled => err!(AmmError::PollCanceled),
+            PollStatus::Active | PollStatus::Closed => Ok(()),
+        }
+    }
+
+    // Keeps the odds the poll settled at, so they stay on record whatever
+    // later happens to the reserves. Like market_state, an empty reserve
+    // leaves them zero
+    pub fn record_final_prices(&mut self) {
+        self.final_prices = self.prices_of(&self.shares).unwrap_or_else(|_| vec![0; self.outcomes.len()]);
+    }
+
+    // Settlement record of a poll being resolved to winning_nft. A closed
+    // poll's liquidity may have been withdrawn down to an empty reserve,
+    // which has no price, so the probabilities are then left empty
+    pub fn market_state(&self, poll: Pubkey, winning_nft: Pubkey) -> MarketStateEvent {
+        MarketStateEvent {
+            poll,
+            winning_nft,
+            outcome_pools: self.outcome_pools.clone(),
+            reserves: self.shares.clone(),
+            probabilities: self.odds_of(&self.shares).unwrap_or_default(),
+            total_volume: self.total_volume,
+            vote_count: self.vote_count,
+        }
+    }
+
+    // Flat copy of the poll's scalar fields: enums become their variant
+    // index and unset optional keys Pubkey::default()
+    pub fn state(&self, poll: Pubkey) -> PollStateEvent {
+        PollStateEvent {
+            poll,
+            version: self.version,
+            authority: self.authority,
+            closes_at: self.closes_at,
+            status: self.status.clone() as u8,
+            reserves: self.shares.clone(),
+            winning_nft: self.winning_nft.unwrap_or_default(),
+            token_mint: self.token_mint,
+            parent_poll: self.parent_poll.unwrap_or_default(),
+            required_parent_outcome: self.required_parent_outcome.unwrap_or_default(),
+            claim_fee_bps: self.claim_fee_bps,
+            resolution_mode: self.resolution_mode.clone() as u8,
+            last_price_update: self.last_price_update,
+            last_update_slot: self.last_update_slot,
+            fee_bps: self.fee_bps,
+            winning_side_shares: self.winning_side_shares,
+            liquidity: self.liquidity,
+            total_volume: self.total_volume,
+            vote_count: self.vote_count,
+            min_bet: self.min_bet,
+            max_bet: self.max_bet,
+            resolver: self.resolver,
+            dispute_period: self.dispute_period,
+            resolved_at: self.resolved_at,
+            vault_liability: self.vault_liability,
+            mint_decimals: self.mint_decimals,
+            lp_deposits: self.lp_deposits,
+            created_at: self.created_at,
+            bonus_enabled: self.bonus_enabled,
+            closed_at: self.closed_at,
+            authority_fee_bps: self.authority_fee_bps,
+            unclaimed_winners: self.unclaimed_winners,
+            resolution_deadline: self.resolution_deadline,
+            require_ownership_proof: self.require_ownership_proof,
+            min_vote_interval: self.min_vote_interval,
+            require_votes: self.require_votes,
+            min_reserve: self.min_reserve,
+            claim_deadline: self.claim_deadline,
+            lp_fees: self.lp_fees,
+            fee_per_deposit: self.fee_per_deposit,
+        }
+    }
+
+    // Winnings stay locked until the dispute period after resolution has
+    // passed, leaving time to contest a wrong outcome
+    pub fn dispute_window_open(&self, now: i64) -> bool {
+        now < self.resolved_at.saturating_add(self.dispute_period)
+    }
+
+    // Starts the time winners have to claim. It counts from the end of the
+    // dispute window, as claims are locked until then
+    pub fn open_claim_window(&mut self, claim_window: i64) {
+        self.claim_deadline = if claim_window == 0 {
+            0
+        } else {
+            self.resolved_at.saturating_add(self.dispute_period).saturating_add(claim_window)
+        };
+    }
+
+    pub fn claims_expired(&self, now: i64) -> bool {
+        self.claim_deadline != 0 && now >= self.claim_deadline
+    }
+
+    // Stakes are refunded when the poll is canceled, or when it resolved to
+    // an outcome nobody holds shares in: there are no winners to split the
+    // pool, which would otherwise be stranded. The latter waits out the
+    // dispute window, as an override could still name a backed outcome
+    pub fn refunds_open(&self, now: i64) -> bool {
+        match self.status {
+            PollStatus::Canceled => true,
+            PollStatus::Resolved => self.winning_side_shares == 0 && !self.dispute_window_open(now),
+            _ => false,
+        }
+    }
+
+    // Books a gross payout to `vote` against the pool's liability. Payouts
+    // never add up to more than was staked, and the vault must still hold
+    // the tokens: otherwise the last winners to claim would be shorted. The
+    // position is settled once it has been paid everything it was owed
+    pub fn pay_out(&mut self, vote: &mut Vote, payout: u64, vault_balance: u64) -> Result<()> {
+        let owed = self.payout_for(vote)?;
+        require!(payout <= owed, AmmError::InvalidClaimAmount);
+        require!(payout <= vault_balance, AmmError::VaultInsolvent);
+        self.vault_liability = self
+            .vault_liability
+            .checked_sub(payout)
+            .ok_or(AmmError::VaultInsolvent)?;
+        vote.claimed_amount = vote.claimed_amount.checked_add(payout).ok_or(AmmError::MathOverflow)?;
+        if payout == owed {
+            vote.claimed = true;
+            self.settle_position()?;
+        }
+        Ok(())
+    }
+
+    // Positions owed something once the poll resolved: the winners and the
+    // unrefunded positions on disabled outcomes, or every position when
+    // nobody holds winning shares and all are refunded
+    pub fn count_unclaimed(&mut self, winning_index: usize) {
+        self.unclaimed_winners = if self.winning_side_shares > 0 {
+            self.positions[winning_index] + self.disabled_positions()
+        } else {
+            self.positions.iter().sum()
+        };
+    }
+
+    // One of the positions counted at resolution was paid out or refunded
+    pub fn settle_position(&mut self) -> Result<()> {
+        self.unclaimed_winners = self.unclaimed_winners.checked_sub(1).ok_or(AmmError::MathOverflow)?;
+        Ok(())
+    }
+
+    // Gross payout owed to a winning position: winners split the prize
+    // pool in proportion to their shares
+    pub fn winnings_for(&self, shares: u64) -> Result<u64> {
+        (shares as u128)
+            .checked_mul(self.prize_pool()? as u128)
+            .and_then(|value| value.checked_div(self.winning_side_shares as u128))
+            .and_then(|value| u64::try_from(value).ok())
+            .ok_or(error!(AmmError::MathOverflow))
+    }
+
+    // Brings a poll read in the layout of `version` up to POLL_VERSION, one
+    // version at a time; each step gives the fields its version added their
+    // starting value
+    pub fn upgrade(&mut self, version: u8) {
+        for from in version..POLL_VERSION {
+            match from {
+                // An unresolved poll's final prices stay zero until it settles,
+                // and one that already settled has no record of them
+                1 => self.final_prices = vec![0; self.outcomes.len()],
+                // Polls from before the floor take bets down to any reserve
+                2 => self.min_reserve = 0,
+                // Polls resolved before claims could expire keep that promise
+                3 => self.claim_deadline = 0,
+                // Every outcome of an older poll is still traded
+                4 => self.outcome_disabled = vec![false; self.outcomes.len()],
+                // Older polls paid providers out of the whole fee vault; the
+                // fees they already hold become the protocol's
+                5 => {
+                    self.lp_fees = 0;
+                    self.fee_per_deposit = 0;
+                }
+                _ => unreachable!("no upgrade step from version {from}"),
+            }
+        }
+        self.version = POLL_VERSION;
+    }
+
+    // Gross payout still owed to a winning position, net of its partial
+    // claims. Proportional payouts round down, so the last winner left
+    // unpaid takes whatever the others left of the stakes instead and the
+    // stakes are paid out exactly. Refunds still owed are not theirs
+    pub fn payout_for(&self, vote: &Vote) -> Result<u64> {
+        let winners_left = self
+            .unclaimed_winners
+            .checked_sub(self.disabled_positions())
+            .ok_or(AmmError::MathOverflow)?;
+        if winners_left == 1 {
+            return self
+                .vault_liability
+                .checked_sub(self.refunds_owed())
+                .ok_or(error!(AmmError::VaultInsolvent));
+        }
+        self.winnings_for(vote.claim_shares()?)?
+            .checked_sub(vote.claimed_amount)
+            .ok_or(error!(AmmError::MathOverflow))
+    }
+
+    // TWAP accumulator: weight each outcome's pre-trade price by the slots
+    // it was in effect. Further trades in the same slot add nothing, so
+    // packing trades into one slot cannot skew the time-weighted price
+    pub fn accumulate_prices(&mut self, clock: &Clock) -> Result<()> {
+        let elapsed_slots = clock.slot.saturating_sub(self.last_update_slot);
+        if elapsed_slots > 0 {
+            let prices = self.prices_of(&self.shares)?;
+            for (cumulative, price) in self.price_cumulative.iter_mut().zip(prices) {
+                *cumulative = (price as u128)
+                    .checked_mul(elapsed_slots as u128)
+                    .and_then(|weighted| cumulative.checked_add(weighted))
+                    .ok_or(AmmError::MathOverflow)?;
+            }
+            self.last_update_slot = clock.slot;
+            self.last_price_update = clock.unix_timestamp;
+        }
+        Ok(())
+    }
+}
+
+// Poll as the first deployment laid it out: a two-outcome pool with its
+// reserves in fixed fields and their product k stored beside them. Those
+// accounts carry Poll's discriminator but no version byte, and were all
+// allocated at 8 + LegacyPoll::LEN
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyPoll {
+    pub authority: Pubkey,
+    pub title: Vec<u8>,
+    pub closes_at: i64,
+    pub nft1: Pubkey,
+    pub nft2: Pubkey,
+    pub nft1_shares: u64,
+    pub nft2_shares: u64,
+    pub k: u64,
+    pub status: PollStatus,
+    pub winning_nft: Option<Pubkey>,
+    pub token_mint: Pubkey,
+}
+
+impl LegacyPoll {
+    pub const LEN: usize = 32 + // authority
+                          4 + 64 + // title
+                          8 + // closes_at
+                          32 + // nft1
+                          32 + // nft2
+                          8 + // nft1_shares
+                          8 + // nft2_shares
+                          8 + // k
+                          1 + // status enum
+                          33 + // winning_nft option
+                          32; // token_mint
+
+    // The same market in the version 1 layout, for Poll::upgrade to take
+    // further. The two NFTs and their reserves become the outcome vectors
+    // and k, being their product, is dropped. The old layout kept no stakes,
+    // fees or limits, so totals start at zero, bets are unbounded and the
+    // authority resolves
+    pub fn into_poll(self, clock: &Clock) -> Poll {
+        let outcome_count = 2;
+        Poll {
+            version: 1,
+            authority: self.authority,
+            title: self.title,
+            closes_at: self.closes_at,
+            outcomes: vec![self.nft1, self.nft2],
+            shares: vec![self.nft1_shares, self.nft2_shares],
+            status: self.status,
+            winning_nft: self.winning_nft,
+            token_mint: self.token_mint,
+            parent_poll: None,
+            required_parent_outcome: None,
+            claim_fee_bps: 0,
+            resolution_mode: ResolutionMode::Resolver,
+            outcome_pools: vec![0; outcome_count],
+            price_cumulative: vec![0; outcome_count],
+            // Prices accumulate from the migration on
+            last_price_update: clock.unix_timestamp,
+            last_update_slot: clock.slot,
+            fee_bps: 0,
+            vote_shares: vec![0; outcome_count],
+            winning_side_shares: 0,
+            liquidity: 0,
+            total_volume: 0,
+            vote_count: 0,
+            min_bet: 0,
+            max_bet: u64::MAX,
+            metadata_uri: Vec::new(),
+            resolver: self.authority,
+            dispute_period: 0,
+            resolved_at: 0,
+            vault_liability: 0,
+            mint_decimals: 0,
+            lp_deposits: 0,
+            created_at: 0,
+            bonus_enabled: false,
+            closed_at: 0,
+            authority_fee_bps: 0,
+            positions: vec![0; outcome_count],
+            unclaimed_winners: 0,
+            resolution_deadline: 0,
+            require_ownership_proof: false,
+            min_vote_interval: 0,
+            require_votes: false,
+            // Fields of later versions, which Poll::upgrade fills in
+            final_prices: Vec::new(),
+            min_reserve: 0,
+            claim_deadline: 0,
+            outcome_disabled: Vec::new(),
+            lp_fees: 0,
+            fee_per_deposit: 0,
+        }
+    }
+}
+
+// Vote as the first deployment laid it out, before stakes, timestamps and
+// fees were tracked per position. Those accounts carry Vote's discriminator
+// and were all allocated at 8 + LegacyVote::LEN
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyVote {
+    pub poll: Pubkey,
+    pub user: Pubkey,
+    pub voted_for_nft: u8,
+    pub amount: u64,
+    pub value: u64,
+    pub price_at_transaction: u64,
+    pub claimed: bool,
+}
+
+impl LegacyVote {
+    pub const LEN: usize = 32 + // poll
+                          32 + // user
+                          1 + // voted_for_nft
+                          8 + // amount
+                          8 + // value
+                          8 + // price_at_transaction
+                          1; // claimed
+}
+
+#[account]
+pub struct Vote {
+    pub poll: Pubkey,
+    pub user: Pubkey,
+    pub voted_for_nft: u8,     // 1-based position in poll.outcomes
+    pub amount: u64,
+    pub value: u64,
+    pub price_at_transaction: u64,
+    pub claimed: bool,         // Track if the vote has been claimed
+    pub stake: u64,            // Tokens that reached the pool vault (value minus fee)
+    pub created_at: i64,       // Timestamp of the first bet in this position
+    pub bonus: u64,            // Early-bird shares on top of amount; they count towards winnings only
+    pub last_vote_at: i64,     // Timestamp of the latest bet in this position
+    pub claimed_amount: u64,   // Gross winnings paid out so far through partial claims
+    pub protocol_fee: u64,     // Protocol's part of the network fees paid into this position
+}
+
+impl Vote {
+    pub const LEN: usize = 32 + // poll
+                          32 + // user
+                          1 + // voted_for_nft
+                          8 + // amount
+                          8 + // value
+                          8 + // price_at_transaction
+                          1 + // claimed
+                          8 + // stake
+                          8 + // created_at
+                          8 + // bonus
+                          8 + // last_vote_at
+                          8 + // claimed_amount
+                          8; // protocol_fee
+
+    // Shares the position is paid out on if its outcome wins
+    pub fn claim_shares(&self) -> Result<u64> {
+        self.amount.checked_add(self.bonus).ok_or(error!(AmmError::MathOverflow))
+    }
+}
+
+#[account]
+pub struct LpPosition {
+    pub poll: Pubkey,
+    pub provider: Pubkey,
+    pub deposited: u64,        // Tokens added through add_liquidity and not yet removed
+    pub fee_per_deposit_paid: u128, // Poll::fee_per_deposit when fees_owed was last brought up to date
+    pub fees_owed: u64,        // Fees earned by the deposit and not yet withdrawn
+}
+
+impl LpPosition {
+    pub const LEN: usize = 32 + // poll
+                          32 + // provider
+                          8 + // deposited
+                          16 + // fee_per_deposit_paid
+                          8; // fees_owed
+
+    // Books the fees the deposit earned since the last snapshot of the
+    // poll's accumulator; call it before the deposit changes
+    pub fn accrue_fees(&mut self, fee_per_deposit: u128) -> Result<()> {
+        let earned = fee_per_deposit
+            .checked_sub(self.fee_per_deposit_paid)
+            .and_then(|delta| delta.checked_mul(self.deposited as u128))
+            .map(|value| value / FEE_PER_DEPOSIT_SCALE)
+            .and_then(|value| u64::try_from(value).ok())
+            .ok_or(AmmError::MathOverflow)?;
+        self.fees_owed = self.fees_owed.checked_add(earned).ok_or(AmmError::MathOverflow)?;
+        self.fee_per_deposit_paid = fee_per_deposit;
+        Ok(())
+    }
+}
+
+#[account]
+pub struct PollRegistry {
+    pub authority: Pubkey,
+    pub active_poll_count: u32, // Polls created by the authority that are not yet resolved or canceled
+}
+
+impl PollRegistry {
+    pub const LEN: usize = 32 + // authority
+                          4; // active_poll_count
+
+    // Stops counting a poll that has just been resolved or canceled
+    pub fn release(&mut self) -> Result<()> {
+        self.active_poll_count = self.active_poll_count.checked_sub(1).ok_or(AmmError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+// A beneficiary's approval of a delegate, who may then credit bets to the
+// beneficiary's positions
+#[account]
+pub struct Delegation {
+    pub beneficiary: Pubkey,
+    pub delegate: Pubkey,
+}
+
+impl Delegation {
+    pub const LEN: usize = 32 + // beneficiary
+                          32; // delegate
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum PollStatus {
+    Active,
+    Closed,
+    Resolved,
+    Canceled,
+}
+
+// One side of a vote_split
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SplitLeg {
+    pub nft_choice: u8,        // 1-based position in poll.outcomes
+    pub amount: u64,           // Tokens spent on this leg, including its part of the fee
+    pub min_shares_out: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum ResolutionMode {
+    Resolver,  // Resolved by the poll authority or admin
+    Consensus, // Resolved to the side with the most tokens staked
+}
+
+#[error_code]
+pub enum AmmError {
+    #[msg("Poll is not active")]
+    PollNotActive,
+    #[msg("Poll is closed")]
+    PollClosed,
+    #[msg("Poll is not resolved yet")]
+    PollNotResolved,
+    #[msg("Invalid NFT choice")]
+    InvalidNftChoice,
+    #[msg("Not enough liquidity")]
+    NotEnoughLiquidity,
+    #[msg("Unauthorized action")]
+    Unauthorized,
+    #[msg("Title longer than the configured limit")]
+    TitleTooLong,
+    #[msg("Invalid share amounts")]
+    InvalidShares,
+    #[msg("Invalid token mint")]
+    InvalidTokenMint,
+    #[msg("Invalid token owner")]
+    InvalidTokenOwner,
+    #[msg("Invalid vote record")]
+    InvalidVote,
+    #[msg("Winnings already claimed")]
+    AlreadyClaimed,
+    #[msg("Vote did not win")]
+    NotWinner,
+    #[msg("Invalid parent poll")]
+    InvalidParentPoll,
+    #[msg("Parent poll is not resolved yet")]
+    ParentPollNotResolved,
+    #[msg("Parent poll condition not met")]
+    ParentConditionUnmet,
+    #[msg("Fee too high (max 10%)")]
+    FeeTooHigh,
+    #[msg("Not allowed for this poll's resolution mode")]
+    InvalidResolutionMode,
+    #[msg("Poll is still open")]
+    PollStillOpen,
+    #[msg("Math overflow")]
+    MathOverflow,
+    #[msg("Bet too small")]
+    BetTooSmall,
+    #[msg("Poll is not canceled and has winners to pay")]
+    PollNotCanceled,
+    #[msg("Slippage exceeded")]
+    SlippageExceeded,
+    #[msg("Invalid number of outcomes")]
+    InvalidOutcomeCount,
+    #[msg("Vote is not settled yet")]
+    VoteNotSettled,
+    #[msg("Bet too large")]
+    BetTooLarge,
+    #[msg("Invalid bet limits")]
+    InvalidBetLimits,
+    #[msg("Program is paused")]
+    ProgramPaused,
+    #[msg("Not enough fees in the vault")]
+    InsufficientFees,
+    #[msg("Close time must be in the future")]
+    InvalidCloseTime,
+    #[msg("Already voted for another outcome")]
+    ConflictingVote,
+    #[msg("Metadata URI too long (max 200 bytes)")]
+    UriTooLong,
+    #[msg("Pool reserves are empty")]
+    EmptyPool,
+    #[msg("Batch accounts must come in complete groups")]
+    InvalidBatchAccounts,
+    #[msg("Poll is not denominated in SOL")]
+    NotNativeSol,
+    #[msg("Dispute period cannot be negative")]
+    InvalidDisputePeriod,
+    #[msg("Winnings are locked until the dispute window closes")]
+    DisputeWindowOpen,
+    #[msg("The dispute window has closed")]
+    DisputeWindowClosed,
+    #[msg("Pool vault cannot cover the payout")]
+    VaultInsolvent,
+    #[msg("Token mint does not have the expected decimals")]
+    UnexpectedDecimals,
+    #[msg("Liquidity providers have not withdrawn their share of the fees")]
+    LiquidityProvidersUnpaid,
+    #[msg("Vote would move the price more than allowed")]
+    PriceImpactTooHigh,
+    #[msg("Title is not valid UTF-8")]
+    InvalidTitleEncoding,
+    #[msg("Authority already has the maximum number of active polls")]
+    TooManyActivePolls,
+    #[msg("Authority fee exceeds the network fee")]
+    InvalidFeeSplit,
+    #[msg("Winning votes are still unclaimed")]
+    UnclaimedWinnings,
+    #[msg("Token mint is not allowed")]
+    MintNotAllowed,
+    #[msg("Too many allowed mints")]
+    TooManyAllowedMints,
+    #[msg("Resolution grace period cannot be negative")]
+    InvalidGracePeriod,
+    #[msg("Poll can still be resolved")]
+    PollNotStale,
+    #[msg("Poll is already resolved")]
+    PollAlreadyResolved,
+    #[msg("Poll is canceled")]
+    PollCanceled,
+    #[msg("Outcomes must be distinct")]
+    DuplicateOutcome,
+    #[msg("Vote account listed more than once")]
+    DuplicateVote,
+    #[msg("Resolver must prove they hold the winning NFT")]
+    OwnershipProofRequired,
+    #[msg("Vote interval must not be negative")]
+    InvalidVoteInterval,
+    #[msg("Voting too fast, wait for the poll's vote interval")]
+    VotingTooFast,
+    #[msg("Clock sysvar unavailable")]
+    ClockUnavailable,
+    #[msg("Liquidity must be added in proportion to the current reserves")]
+    ImbalancedLiquidity,
+    #[msg("Initial shares must exceed the locked minimum liquidity")]
+    InsufficientInitialLiquidity,
+    #[msg("No votes were cast on this poll")]
+    NoVotesCast,
+    #[msg("Claim amount must be positive and within the remaining winnings")]
+    InvalidClaimAmount,
+    #[msg("Poll already uses the current account layout")]
+    PollAlreadyMigrated,
+    #[msg("Pool vault is frozen")]
+    VaultFrozen,
+    #[msg("Title limit exceeds the hard cap")]
+    TitleLimitTooHigh,
+    #[msg("Claim window cannot be negative")]
+    InvalidClaimWindow,
+    #[msg("Claim window is still open")]
+    ClaimWindowOpen,
+    #[msg("Claim window has closed")]
+    ClaimWindowClosed,
+    #[msg("Outcome is disabled")]
+    OutcomeDisabled,
+    #[msg("Beneficiary has not approved this delegate")]
+    NotDelegated,
+    #[msg("Positions of the first deployment cannot be migrated once their poll is resolved")]
+    LegacyPollResolved,
+    #[msg("Vote already uses the current account layout")]
+    VoteAlreadyMigrated,
+    #[msg("Legacy vault must be owned by the pool authority and differ from the pool vault")]
+    InvalidLegacyVault,
+}
+
+// Events for better UX and indexing
+#[event]
+pub struct AdminTransferProposed {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+}
+
+#[event]
+pub struct AdminTransferred {
+    pub previous_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
+
+#[event]
+pub struct PausedEvent {
+    pub admin: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct PollCreatedEvent {
+    pub poll: Pubkey,
+    pub authority: Pubkey, 
+    pub outcomes: Vec<Pubkey>,
+    pub closes_at: i64,
+}
+
+#[event]
+pub struct VoteCastEvent {
+    pub poll: Pubkey,
+    pub user: Pubkey,
+    pub voted_for_nft: u8,
+    pub value: u64,            // Tokens spent, including the network fee
+    pub fee: u64,              // Network fee charged, protocol and authority parts together
+    pub received: u64,         // Shares bought
+    pub bonus: u64,            // Early-bird shares granted on top
+    pub price_at_transaction: u64,
+    pub new_shares: Vec<u64>,  // Reserves after the swap
+    pub created_at: i64,
+}
+
+#[event]
+pub struct QuoteEvent {
+    pub poll: Pubkey,
+    pub voted_for_nft: u8,
+    pub value: u64,            // Tokens the vote would spend, including the network fee
+    pub received: u64,         // Shares the vote would buy
+    pub price_at_transaction: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct MarketStateEvent {
+    pub poll: Pubkey,
+    pub winning_nft: Pubkey,
+    pub outcome_pools: Vec<u64>, // Tokens staked on each outcome (after fees)
+    pub reserves: Vec<u64>,      // Final AMM reserve of each outcome
+    pub probabilities: Vec<u64>, // Final implied probability of each outcome in basis points
+    pub total_volume: u64,
+    pub vote_count: u64,
+}
+
+#[event]
+pub struct PollStateEvent {
+    pub poll: Pubkey,
+    pub version: u8,
+    pub authority: Pubkey,
+    pub closes_at: i64,
+    pub status: u8,            // 0 Active, 1 Closed, 2 Resolved, 3 Canceled
+    pub reserves: Vec<u64>,    // AMM reserve of each outcome
+    pub winning_nft: Pubkey,   // Pubkey::default() until resolved
+    pub token_mint: Pubkey,
+    pub parent_poll: Pubkey,   // Pubkey::default() unless conditional
+    pub required_parent_outcome: Pubkey, // Pubkey::default() unless conditional
+    pub claim_fee_bps: u16,
+    pub resolution_mode: u8,   // 0 Resolver, 1 Consensus
+    pub last_price_update: i64,
+    pub last_update_slot: u64,
+    pub fee_bps: u16,
+    pub winning_side_shares: u64,
+    pub liquidity: u64,
+    pub total_volume: u64,
+    pub vote_count: u64,
+    pub min_bet: u64,
+    pub max_bet: u64,
+    pub resolver: Pubkey,
+    pub dispute_period: i64,
+    pub resolved_at: i64,
+    pub vault_liability: u64,
+    pub mint_decimals: u8,
+    pub lp_deposits: u64,
+    pub created_at: i64,
+    pub bonus_enabled: bool,
+    pub closed_at: i64,
+    pub authority_fee_bps: u16,
+    pub unclaimed_winners: u32,
+    pub resolution_deadline: i64,
+    pub require_ownership_proof: bool,
+    pub min_vote_interval: i64,
+    pub require_votes: bool,
+    pub min_reserve: u64,
+    pub claim_deadline: i64,
+    pub lp_fees: u64,
+    pub fee_per_deposit: u128,
+}
+
+#[event]
+pub struct OddsEvent {
+    pub poll: Pubkey,
+    pub probabilities: Vec<u64>, // Implied probability of each outcome in basis points, summing to 10000
+}
+
+#[event]
+pub struct PositionSummaryEvent {
+    pub poll: Pubkey,
+    pub user: Pubkey,
+    pub shares: Vec<u64>,      // Shares held on each outcome, early-bird bonus included
+    pub total_value: u64,      // Tokens wagered, including network fees
+    pub total_stake: u64,      // Tokens that reached the pool vault
+}
+
+#[event]
+pub struct PositionSold {
+    pub poll: Pubkey,
+    pub user: Pubkey,
+    pub voted_for_nft: u8,
+    pub shares: u64,       // Shares returned to the pool
+    pub amount: u64,       // Net amount received by the seller
+    pub fee: u64,
+}
+
+// Reserve snapshot after any trade or liquidity change, for price charts
+#[event]
+pub struct PriceUpdateEvent {
+    pub poll: Pubkey,
+    pub shares: Vec<u64>,      // Reserves after the change
+    pub prices: Vec<u64>,      // Price of each outcome in basis points
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidityAddedEvent {
+    pub poll: Pubkey,
+    pub provider: Pubkey,
+    pub amounts: Vec<u64>,     // Deposit added to each outcome's reserve
+    pub new_shares: Vec<u64>,  // Reserves after the deposit
+    pub liquidity: u64,        // Total deposited liquidity in the pool
+    pub deposited: u64,        // Provider's position after the deposit
+}
+
+#[event]
+pub struct PollResolvedEvent {
+    pub poll: Pubkey,
+    pub authority: Pubkey,
+    pub winning_nft: Pubkey,
+    pub final_prices: Vec<u64>, // Price of each outcome in basis points at resolution
+}
+
+#[event]
+pub struct ResolutionOverriddenEvent {
+    pub poll: Pubkey,
+    pub admin: Pubkey,
+    pub previous_winning_nft: Pubkey,
+    pub winning_nft: Pubkey,
+}
+
+#[event]
+pub struct PollCanceledEvent {
+    pub poll: Pubkey,
+    pub authority: Pubkey,
+    pub reason_code: u8,
+    pub total_volume: u64,     // Tokens wagered before the poll was canceled
+    pub vote_count: u64,       // Votes cast before the poll was canceled
+}
+
+#[event]
+pub struct PollExtendedEvent {
+    pub poll: Pubkey,
+    pub authority: Pubkey,
+    pub previous_closes_at: i64,
+    pub closes_at: i64,
+}
+
+#[event]
+pub struct FeeChangedEvent {
+    pub poll: Pubkey,
+    pub authority: Pubkey,
+    pub previous_fee_bps: u16,
+    pub fee_bps: u16,
+}
+
+#[event]
+pub struct DelegationChangedEvent {
+    pub beneficiary: Pubkey,
+    pub delegate: Pubkey,
+    pub approved: bool,        // False when the approval was revoked
+}
+
+#[event]
+pub struct OutcomeDisabled {
+    pub poll: Pubkey,
+    pub authority: Pubkey,
+    pub outcome: Pubkey,
+    pub outcome_index: u8,     // 0-based position of the outcome in poll.outcomes
+    pub reserve: u64,          // AMM reserve taken out of the market
+    pub refunds_owed: u64,     // Stakes on the outcome, now refundable
+    pub positions: u32,        // Votes on the outcome owed those refunds
 }
 
-#[account]
-pub struct Poll {
-    pub authority: Pubkey,
-    pub title: Vec<u8>,        // Using a fixed-size Vec<u8> instead of String
-    pub closes_at: i64,
-    pub nft1: Pubkey,
-    pub nft2: Pubkey,
-    pub nft1_shares: u64,
-    pub nft2_shares: u64,
-    pub k: u64,
-    pub status: PollStatus,
-    pub winning_nft: Option<Pubkey>,
-    pub token_mint: Pubkey,    // Track which token mint is used for this poll
+#[event]
+pub struct PollClosedEvent {
+    pub poll: Pubkey,
+    pub closed_by: Pubkey,
+    pub auto: bool,            // Closed by someone other than the authority once betting ended
 }
 
-impl Poll {
-    pub const LEN: usize = 32 + // authority 
-                          4 + 64 + // title (vec with max 64 bytes)
-                          8 + // closes_at
-                          32 + // nft1
-                          32 + // nft2
-                          8 + // nft1_shares
-                          8 + // nft2_shares
-                          8 + // k
-                          1 + // status enum
-                          33 + // winning_nft option
-                          32; // token_mint
+#[event]
+pub struct PollVoidedEvent {
+    pub poll: Pubkey,
+    pub parent_poll: Pubkey,
 }
 
-#[account]
-pub struct Vote {
+#[event]
+pub struct WinningsClaimed {
     pub poll: Pubkey,
     pub user: Pubkey,
-    pub voted_for_nft: u8,
+    pub amount: u64,       // Net amount received by the winner
+    pub gross_amount: u64, // Payout before the claim fee
+    pub claim_fee: u64,
+    pub entry_price: u64,  // Price of the outcome in basis points after the vote's latest bet
+    pub payout_ratio: u64, // Gross payout per token wagered, in basis points
+}
+
+#[event]
+pub struct FeesWithdrawn {
+    pub poll: Pubkey,
+    pub destination: Pubkey,
     pub amount: u64,
-    pub value: u64,
-    pub price_at_transaction: u64,
-    pub claimed: bool,         // Track if the vote has been claimed
 }
 
-impl Vote {
-    pub const LEN: usize = 32 + // poll
-                          32 + // user
-                          1 + // voted_for_nft
-                          8 + // amount
-                          8 + // value
-                          8 + // price_at_transaction
-                          1; // claimed
+#[event]
+pub struct UnclaimedSweptEvent {
+    pub poll: Pubkey,
+    pub admin: Pubkey,
+    pub treasury: Pubkey,
+    pub amount: u64,           // Winnings and refunds nobody claimed in time
+    pub unclaimed_winners: u32, // Positions that were still owed
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
-pub enum PollStatus {
-    Active,
-    Closed,
-    Resolved,
-    Canceled,
+#[event]
+pub struct PollAccountsClosedEvent {
+    pub poll: Pubkey,
+    pub treasury: Pubkey,
+    pub swept: u64,            // Dust left in the pool vault
+    pub fees: u64,             // Protocol fees left in the fee vault
+    pub authority_fees: u64,   // Authority fees returned to the authority
 }
 
-#[error_code]
-pub enum AmmError {
-    #[msg("Poll is not active")]
-    PollNotActive,
-    #[msg("Poll is closed")]
-    PollClosed,
-    #[msg("Poll is not resolved yet")]
-    PollNotResolved,
-    #[msg("Invalid NFT choice")]
-    InvalidNftChoice,
-    #[msg("Not enough liquidity")]
-    NotEnoughLiquidity,
-    #[msg("Unauthorized action")]
-    Unauthorized,
-    #[msg("Title too long (max 64 bytes)")]
-    TitleTooLong,
-    #[msg("Invalid share amounts")]
-    InvalidShares,
-    #[msg("Invalid token mint")]
-    InvalidTokenMint,
-    #[msg("Invalid token owner")]
-    InvalidTokenOwner,
-    #[msg("Invalid vote record")]
-    InvalidVote,
-    #[msg("Winnings already claimed")]
-    AlreadyClaimed,
-    #[msg("Vote did not win")]
-    NotWinner,
+#[event]
+pub struct LpWithdrawn {
+    pub poll: Pubkey,
+    pub provider: Pubkey,
+    pub deposited: u64,        // The provider's deposit the payout was sized by
+    pub liquidity: u64,        // Paid from the pool vault
+    pub fees: u64,             // Paid from the fee vault
 }
 
-// Events for better UX and indexing
 #[event]
-pub struct PollCreatedEvent {
+pub struct RefundClaimed {
     pub poll: Pubkey,
-    pub authority: Pubkey, 
-    pub nft1: Pubkey,
-    pub nft2: Pubkey,
-    pub closes_at: i64,
+    pub user: Pubkey,
+    pub amount: u64,           // Stake returned from the pool vault
+    pub fee_refund: u64,       // Network fee returned from the fee vault
 }
 
 #[event]
-pub struct PollResolvedEvent {
+pub struct MetadataUpdatedEvent {
     pub poll: Pubkey,
-    pub authority: Pubkey,
-    pub winning_nft: Pubkey,
+    pub metadata_uri: Vec<u8>,
 }
 
 #[event]
-pub struct PollCanceledEvent {
+pub struct PollMigratedEvent {
     pub poll: Pubkey,
-    pub authority: Pubkey,
+    pub from_version: u8,      // 0 for a poll of the unversioned first layout
+    pub version: u8,
 }
 
 #[event]
-pub struct WinningsClaimed {
+pub struct VoteMigratedEvent {
     pub poll: Pubkey,
+    pub vote: Pubkey,
     pub user: Pubkey,
-    pub amount: u64,
+    pub stake: u64,            // Tokens moved into the pool vault and booked on the poll
+}
+
+// Canonical PDA derivations for clients and SDKs, kept in sync with the
+// seeds used by the program
+pub fn config_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], &crate::ID)
+}
+
+pub fn pool_authority_pda(poll: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POOL_AUTHORITY_SEED, poll.as_ref()], &crate::ID)
+}
+
+pub fn pool_vault_pda(poll: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POOL_VAULT_SEED, poll.as_ref()], &crate::ID)
+}
+
+pub fn fee_vault_pda(poll: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FEE_VAULT_SEED, poll.as_ref()], &crate::ID)
+}
+
+pub fn authority_fee_vault_pda(poll: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[AUTHORITY_FEE_VAULT_SEED, poll.as_ref()], &crate::ID)
+}
+
+pub fn vote_pda(poll: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VOTE_SEED, poll.as_ref(), user.as_ref()], &crate::ID)
+}
+
+pub fn split_vote_pda(poll: &Pubkey, user: &Pubkey, nft_choice: u8) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VOTE_SEED, poll.as_ref(), user.as_ref(), &[nft_choice]], &crate::ID)
+}
+
+pub fn unwrap_pda(poll: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[UNWRAP_SEED, poll.as_ref(), user.as_ref()], &crate::ID)
+}
+
+pub fn lp_position_pda(poll: &Pubkey, provider: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LP_SEED, poll.as_ref(), provider.as_ref()], &crate::ID)
+}
+
+pub fn registry_pda(authority: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REGISTRY_SEED, authority.as_ref()], &crate::ID)
+}
+
+pub fn delegation_pda(beneficiary: &Pubkey, delegate: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[DELEGATION_SEED, beneficiary.as_ref(), delegate.as_ref()], &crate::ID)
+}
+
+// Whether a conditional poll's parent resolved to the required outcome.
+// Unconditional polls always pass; a canceled parent never satisfies the
+// condition, while a parent that is still open, or whose resolution can
+// still be overridden, is an error.
+fn parent_condition_met(poll: &Poll, parent: Option<&Account<Poll>>) -> Result<bool> {
+    let Some(parent_key) = poll.parent_poll else {
+        return Ok(true);
+    };
+    let parent = parent.ok_or(AmmError::InvalidParentPoll)?;
+    require_keys_eq!(parent.key(), parent_key, AmmError::InvalidParentPoll);
+    match parent.status {
+        // The parent's outcome may still be overridden within its dispute window
+        PollStatus::Resolved if parent.dispute_window_open(now()?) => err!(AmmError::ParentPollNotResolved),
+        PollStatus::Resolved => Ok(parent.winning_nft == poll.required_parent_outcome),
+        PollStatus::Canceled => Ok(false),
+        _ => err!(AmmError::ParentPollNotResolved),
+    }
+}
+
+// The account a bet is credited to: the signer, or a beneficiary that has
+// approved the signer as its delegate. Credits nobody asked for would let
+// anyone fix the side of another user's position or hold its rate limit
+fn credited_user(signer: Pubkey, beneficiary: Option<Pubkey>, delegation: Option<&Account<Delegation>>) -> Result<Pubkey> {
+    match beneficiary {
+        Some(beneficiary) if beneficiary != signer => {
+            require!(
+                delegation.is_some_and(|d| d.beneficiary == beneficiary && d.delegate == signer),
+                AmmError::NotDelegated
+            );
+            Ok(beneficiary)
+        }
+        _ => Ok(signer),
+    }
+}
+
+// Shared bookkeeping of `vote`, `vote_sol` and `vote_split`: validates the
+// bet, runs the AMM swap and records the position, charging `fee` as the
+// network fee. Returns the stake and fee the caller must move into the pool
+// and fee vaults.
+#[allow(clippy::too_many_arguments)]
+fn place_bet(
+    poll: &mut Account<Poll>,
+    vote: &mut Account<Vote>,
+    user: Pubkey,
+    nft_choice: u8,
+    amount: u64,
+    min_shares_out: u64,
+    max_price_impact_bps: Option<u16>,
+    fee: u64,
+) -> Result<(u64, u64, u64)> {
+    // Rejected up front, even on polls without a minimum bet
+    require!(amount > 0, AmmError::BetTooSmall);
+    let clock = clock()?;
+    require!(poll.status == PollStatus::Active, AmmError::PollNotActive);
+    require!(
+        clock.unix_timestamp < poll.closes_at,
+        AmmError::PollClosed
+    );
+    let (fee, received, new_shares) = poll.quote_bet_with_fee(nft_choice, amount, fee)?;
+    let amount_after_fee = amount - fee;
+    let index = nft_choice as usize - 1;
+    // Each user holds a single position per poll, so further bets must
+    // back the same outcome. voted_for_nft is 0 only on a new account
+    let is_new = vote.voted_for_nft == 0;
+    require!(is_new || vote.voted_for_nft == nft_choice, AmmError::ConflictingVote);
+    // Rate limit: a position takes no further bets until the poll's
+    // interval has passed since its latest one
+    require!(
+        is_new || clock.unix_timestamp - vote.last_vote_at >= poll.min_vote_interval,
+        AmmError::VotingTooFast
+    );
+
+    poll.accumulate_prices(&clock)?;
+    // Slippage protection: the price may have moved since the voter quoted it
+    require!(received >= min_shares_out, AmmError::SlippageExceeded);
+    // Optionally cap how far the bet may move its outcome's price
+    if let Some(max_impact) = max_price_impact_bps {
+        let price_before = poll.prices_of(&poll.shares)?[index];
+        let price_after = poll.prices_of(&new_shares)?[index];
+        require!(
+            price_after.abs_diff(price_before) <= max_impact as u64,
+            AmmError::PriceImpactTooHigh
+        );
+    }
+    poll.shares = new_shares;
+    let bonus = if poll.bonus_enabled {
+        early_bird_bonus(received, poll.created_at, poll.closes_at, clock.unix_timestamp)
+    } else {
+        0
+    };
+    // Track the tokens staked and shares sold on each outcome
+    poll.outcome_pools[index] = poll.outcome_pools[index]
+        .checked_add(amount_after_fee)
+        .ok_or(AmmError::MathOverflow)?;
+    poll.vault_liability = poll
+        .vault_liability
+        .checked_add(amount_after_fee)
+        .ok_or(AmmError::MathOverflow)?;
+    poll.vote_shares[index] = poll.vote_shares[index]
+        .checked_add(received)
+        .and_then(|shares| shares.checked_add(bonus))
+        .ok_or(AmmError::MathOverflow)?;
+    // Activity counters for analytics
+    poll.total_volume = poll.total_volume.checked_add(amount).ok_or(AmmError::MathOverflow)?;
+    poll.vote_count = poll.vote_count.checked_add(1).ok_or(AmmError::MathOverflow)?;
+    // Record the vote, accumulating into an existing position
+    if is_new {
+        poll.positions[index] = poll.positions[index].checked_add(1).ok_or(AmmError::MathOverflow)?;
+        vote.poll = poll.key();
+        vote.user = user;
+        vote.voted_for_nft = nft_choice;
+        vote.created_at = clock.unix_timestamp;
+    }
+    vote.amount = vote.amount.checked_add(received).ok_or(AmmError::MathOverflow)?;
+    vote.bonus = vote.bonus.checked_add(bonus).ok_or(AmmError::MathOverflow)?;
+    vote.value = vote.value.checked_add(amount).ok_or(AmmError::MathOverflow)?;
+    vote.stake = vote.stake.checked_add(amount_after_fee).ok_or(AmmError::MathOverflow)?;
+    vote.last_vote_at = clock.unix_timestamp;
+    vote.price_at_transaction = poll.prices_of(&poll.shares)?[index];
+    let (protocol_fee, authority_fee) = poll.split_fee(amount, fee);
+    // The providers' part is paid into the fee vault as well, but is
+    // theirs: only the rest is the protocol's to refund
+    let kept_fee = poll.book_lp_fee(protocol_fee)?;
+    vote.protocol_fee = vote.protocol_fee.checked_add(kept_fee).ok_or(AmmError::MathOverflow)?;
+
+    emit!(VoteCastEvent {
+        poll: poll.key(),
+        user: vote.user,
+        voted_for_nft: nft_choice,
+        value: amount,
+        fee,
+        received,
+        bonus,
+        price_at_transaction: vote.price_at_transaction,
+        new_shares: poll.shares.clone(),
+        created_at: clock.unix_timestamp,
+    });
+    emit!(PriceUpdateEvent {
+        poll: poll.key(),
+        shares: poll.shares.clone(),
+        prices: poll.prices_of(&poll.shares)?,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok((amount_after_fee, protocol_fee, authority_fee))
+}
+
+// Shared settlement of `claim_winnings` and `claim_winnings_sol`: checks the
+// vote won, marks it claimed and returns the net payout and claim fee the
+// caller must move out of the pool vault
+fn settle_winnings(
+    poll: &mut Account<Poll>,
+    vote: &mut Account<Vote>,
+    user: Pubkey,
+    parent_poll: Option<&Account<Poll>>,
+    vault_balance: u64,
+    amount: Option<u64>,
+) -> Result<(u64, u64)> {
+    // Check if poll is resolved
+    require!(poll.status == PollStatus::Resolved, AmmError::PollNotResolved);
+    
+    // Check if this vote belongs to the correct user
+    require!(vote.user == user, AmmError::Unauthorized);
+    
+    // Check if vote is already claimed
+    require!(!vote.claimed, AmmError::AlreadyClaimed);
+
+    let now = now()?;
+    require!(
+        !poll.dispute_window_open(now),
+        AmmError::DisputeWindowOpen
+    );
+    require!(!poll.claims_expired(now), AmmError::ClaimWindowClosed);
+    
+    // Check if vote is for the winning NFT
+    let winning_nft = poll.winning_nft.ok_or(AmmError::PollNotResolved)?;
+    let voted_for_winner =
+        poll.outcome_index(&winning_nft) == Some(vote.voted_for_nft as usize - 1);
+    
+    require!(voted_for_winner, AmmError::NotWinner);
+
+    // Conditional polls only pay out while the parent condition holds
+    require!(
+        parent_condition_met(poll, parent_poll)?,
+        AmmError::ParentConditionUnmet
+    );
+    
+    let owed = poll.payout_for(vote)?;
+    require!(
+        !matches!(amount, Some(amount) if amount == 0 || amount > owed),
+        AmmError::InvalidClaimAmount
+    );
+    let payout_amount = amount.unwrap_or(owed);
+    // The poll's claim fee is taken out of the payout and sent to the fee vault
+    let claim_fee = bps_of(payout_amount, poll.claim_fee_bps);
+    let net_payout = payout_amount - claim_fee;
+    poll.pay_out(vote, payout_amount, vault_balance)?;
+
+    emit!(WinningsClaimed {
+        poll: poll.key(),
+        user,
+        amount: net_payout,
+        gross_amount: payout_amount,
+        claim_fee,
+        entry_price: vote.price_at_transaction,
+        payout_ratio: payout_ratio(payout_amount, vote.value),
+    });
+
+    Ok((net_payout, claim_fee))
+}
+
+// Wraps `lamports` of the user's SOL into a wSOL vault: the lamports are
+// sent to the token account and sync_native credits them to its balance
+fn wrap_sol<'info>(
+    system_program: &Program<'info, System>,
+    token_program: &Program<'info, Token>,
+    user: &Signer<'info>,
+    vault: &Account<'info, TokenAccount>,
+    lamports: u64,
+) -> Result<()> {
+    system_program::transfer(
+        CpiContext::new(
+            system_program.to_account_info(),
+            system_program::Transfer {
+                from: user.to_account_info(),
+                to: vault.to_account_info(),
+            },
+        ),
+        lamports,
+    )?;
+    token::sync_native(CpiContext::new(
+        token_program.to_account_info(),
+        SyncNative { account: vault.to_account_info() },
+    ))
+}
+
+// Token transfer out of a program-owned vault, signed by the pool authority
+fn vault_transfer<'info>(
+    token_program: AccountInfo<'info>,
+    from: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    signer: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program,
+        Transfer { from, to, authority },
+        signer,
+    );
+    token::transfer(cpi_ctx, amount)
+}
+
+// Portion of `amount` represented by `bps` basis points, rounded down
+fn bps_of(amount: u64, bps: u16) -> u64 {
+    // bps never exceeds 10000, so the result always fits in a u64
+    ((amount as u128 * bps as u128) / 10000) as u64
 }
 
-fn get_price(nft1_shares: u64, nft2_shares: u64, nft_choice: u8) -> u64 {
-    let total = nft1_shares + nft2_shares;
-    if nft_choice == 1 {
-        ((nft2_shares as u128 * 10000) / total as u128) as u64
+// Network fee charged on `amount`: rounded down like bps_of, which favors
+// the trader, or up when the config asks for fees to favor the protocol
+fn network_fee(amount: u64, fee_bps: u16, round_up: bool) -> u64 {
+    if round_up {
+        (amount as u128 * fee_bps as u128).div_ceil(10000) as u64
     } else {
-        ((nft1_shares as u128 * 10000) / total as u128) as u64
+        bps_of(amount, fee_bps)
+    }
+}
+
+// Every instruction reads the clock through these, so a failed sysvar read
+// surfaces as ClockUnavailable instead of a bare program error
+fn clock() -> Result<Clock> {
+    Clock::get().map_err(|_| error!(AmmError::ClockUnavailable))
+}
+
+fn now() -> Result<i64> {
+    Ok(clock()?.unix_timestamp)
+}
+
+// Gross payout per token wagered, in basis points: 10000 breaks even,
+// 20000 doubles the wager. Saturates rather than failing a claim
+fn payout_ratio(payout: u64, value: u64) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+    u64::try_from(payout as u128 * 10000 / value as u128).unwrap_or(u64::MAX)
+}
+
+// Whether `amounts` grow every reserve by the same proportion, to within
+// LIQUIDITY_RATIO_TOLERANCE_BPS: amounts[i] * shares[0] ~ amounts[0] * shares[i]
+fn liquidity_is_balanced(shares: &[u64], amounts: &[u64]) -> bool {
+    (1..shares.len()).all(|i| {
+        let lhs = amounts[i] as u128 * shares[0] as u128;
+        let rhs = amounts[0] as u128 * shares[i] as u128;
+        lhs.abs_diff(rhs) * 10000 <= lhs.max(rhs) * LIQUIDITY_RATIO_TOLERANCE_BPS as u128
+    })
+}
+
+// Early-bird bonus shares for `received` shares bought at `now`: up to
+// EARLY_BIRD_BONUS_BPS of them, scaled by the share of the betting window
+// still left, (closes_at - now) / (closes_at - created_at)
+fn early_bird_bonus(received: u64, created_at: i64, closes_at: i64, now: i64) -> u64 {
+    let window = closes_at.saturating_sub(created_at);
+    if window <= 0 {
+        return 0;
+    }
+    // Capping remaining at the window keeps the bonus within
+    // EARLY_BIRD_BONUS_BPS of `received`
+    let remaining = closes_at.saturating_sub(now).clamp(0, window);
+    (received as u128 * EARLY_BIRD_BONUS_BPS as u128 * remaining as u128 / (10000 * window as u128)) as u64
+}
+
+// Outcome of a swap against the poll's reserves
+#[derive(Debug, PartialEq)]
+struct SwapResult {
+    amount_out: u64,   // Shares bought, or tokens paid out for a sale
+    reserves: Vec<u64>, // Every reserve after the swap
+}
+
+// Multi-reserve constant-product swap: `amount_in` is added to every other
+// reserve and the chosen reserve shrinks so the product of all reserves is
+// preserved. The chosen reserve is scaled by r / (r + amount_in) for each
+// other reserve r, rounding up at every step so the product never drops.
+// Returns the shares received and the new reserves. Bets that round down to
+// zero shares are rejected rather than recorded.
+fn swap_shares(reserves: &[u64], index: usize, amount_in: u64) -> Result<SwapResult> {
+    let mut new_reserves = reserves.to_vec();
+    let mut new_reserve_out = reserves[index] as u128;
+    for (i, reserve) in new_reserves.iter_mut().enumerate() {
+        if i == index {
+            continue;
+        }
+        require!(amount_in <= *reserve, AmmError::NotEnoughLiquidity);
+        let reserve_in = reserve.checked_add(amount_in).ok_or(AmmError::MathOverflow)?;
+        new_reserve_out = (new_reserve_out * *reserve as u128).div_ceil(reserve_in as u128);
+        *reserve = reserve_in;
+    }
+    // Every step scales by a ratio of at most one, so this fits in a u64
+    new_reserves[index] = new_reserve_out as u64;
+    let received = reserves[index]
+        .checked_sub(new_reserves[index])
+        .ok_or(AmmError::MathOverflow)?;
+    require!(received > 0, AmmError::BetTooSmall);
+    Ok(SwapResult { amount_out: received, reserves: new_reserves })
+}
+
+// Reverse of swap_shares: `shares_in` are returned to the chosen reserve and
+// the same token amount is taken out of every other reserve. Finds the
+// largest amount that keeps the product of all reserves from dropping,
+// rounding down at every step. Returns the tokens paid out and the new
+// reserves. A sale the other reserves cannot cover without being emptied is
+// rejected rather than capped.
+fn sell_shares(reserves: &[u64], index: usize, shares_in: u64) -> Result<SwapResult> {
+    let reserve_in = reserves[index].checked_add(shares_in).ok_or(AmmError::MathOverflow)?;
+    // The product holds if reserve_in * prod((r - amount_out) / r) over the
+    // other reserves r is still at least the chosen reserve
+    let keeps_product = |amount_out: u64| {
+        let mut scaled = reserve_in as u128;
+        for (i, &reserve) in reserves.iter().enumerate() {
+            if i != index {
+                scaled = scaled * (reserve - amount_out) as u128 / reserve as u128;
+            }
+        }
+        scaled >= reserves[index] as u128
+    };
+    // Every other reserve must keep at least one share
+    let max_out = reserves
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, &reserve)| reserve.saturating_sub(1))
+        .min()
+        .unwrap_or(0);
+    require!(!keeps_product(max_out), AmmError::NotEnoughLiquidity);
+    // keeps_product(low) always holds and keeps_product(high + 1) never does
+    let (mut low, mut high) = (0, max_out - 1);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        if keeps_product(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    require!(low > 0, AmmError::BetTooSmall);
+    let new_reserves = reserves
+        .iter()
+        .enumerate()
+        .map(|(i, &reserve)| if i == index { reserve_in } else { reserve - low })
+        .collect();
+    Ok(SwapResult { amount_out: low, reserves: new_reserves })
+}
+
+// Scale for reserve reciprocals in compute_prices; keeps precision for any
+// u64 reserve while the sum over MAX_OUTCOMES reciprocals fits in a u128
+const PRICE_SCALE: u128 = 1_000_000_000_000_000_000_000_000_000_000;
+
+// Price of every outcome in basis points, in one pass. Under the constant
+// product an outcome's price is proportional to the reciprocal of its
+// reserve, so the scarcest outcome is the most expensive and prices sum to
+// ~10000. An empty reserve has no finite price and is rejected.
+fn compute_prices(shares: &[u64]) -> Result<Vec<u64>> {
+    require!(shares.iter().all(|&reserve| reserve > 0), AmmError::EmptyPool);
+    let inverses: Vec<u128> = shares.iter().map(|&reserve| PRICE_SCALE / reserve as u128).collect();
+    let total: u128 = inverses.iter().sum();
+    Ok(inverses.iter().map(|&inverse| (inverse * 10000 / total) as u64).collect())
+}
+
+// compute_prices with the basis points lost to rounding handed out one each
+// to the outcomes with the largest remainders, so the odds sum to 10000
+fn compute_odds(shares: &[u64]) -> Result<Vec<u64>> {
+    let mut odds = compute_prices(shares)?;
+    let inverses: Vec<u128> = shares.iter().map(|&reserve| PRICE_SCALE / reserve as u128).collect();
+    let total: u128 = inverses.iter().sum();
+    let mut by_remainder: Vec<usize> = (0..odds.len()).collect();
+    by_remainder.sort_by_key(|&i| std::cmp::Reverse(inverses[i] * 10000 % total));
+    let missing = 10000 - odds.iter().sum::<u64>() as usize;
+    for &i in by_remainder.iter().take(missing) {
+        odds[i] += 1;
+    }
+    Ok(odds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pda_helpers_match_program_seeds() {
+        let poll = Pubkey::new_unique();
+
+        let (config, bump) = config_pda();
+        let expected = Pubkey::create_program_address(&[b"config", &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), config);
+
+        let (authority, bump) = pool_authority_pda(&poll);
+        let expected = Pubkey::create_program_address(&[b"pool", poll.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), authority);
+
+        let (pool_vault, bump) = pool_vault_pda(&poll);
+        let expected = Pubkey::create_program_address(&[b"pool_vault", poll.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), pool_vault);
+
+        let (fee_vault, bump) = fee_vault_pda(&poll);
+        let expected = Pubkey::create_program_address(&[b"fee_vault", poll.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), fee_vault);
+
+        let (authority_fee_vault, bump) = authority_fee_vault_pda(&poll);
+        let expected =
+            Pubkey::create_program_address(&[b"authority_fee_vault", poll.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), authority_fee_vault);
+
+        let user = Pubkey::new_unique();
+        let (vote, bump) = vote_pda(&poll, &user);
+        let expected =
+            Pubkey::create_program_address(&[b"vote", poll.as_ref(), user.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), vote);
+
+        let (leg, bump) = split_vote_pda(&poll, &user, 2);
+        let expected =
+            Pubkey::create_program_address(&[b"vote", poll.as_ref(), user.as_ref(), &[2], &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), leg);
+
+        let (unwrap_account, bump) = unwrap_pda(&poll, &user);
+        let expected =
+            Pubkey::create_program_address(&[b"unwrap", poll.as_ref(), user.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), unwrap_account);
+
+        let (lp_position, bump) = lp_position_pda(&poll, &user);
+        let expected = Pubkey::create_program_address(&[b"lp", poll.as_ref(), user.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), lp_position);
+
+        let (registry, bump) = registry_pda(&user);
+        let expected = Pubkey::create_program_address(&[b"registry", user.as_ref(), &[bump]], &crate::ID);
+        assert_eq!(expected.unwrap(), registry);
+    }
+
+    #[test]
+    fn swap_moves_reserves_along_the_curve() {
+        let swap = swap_shares(&[1_000_000_000, 1_000_000_000], 0, 97_000_000).unwrap();
+        assert_eq!(swap.reserves, vec![911_577_029, 1_097_000_000]);
+        assert_eq!(swap.amount_out, 1_000_000_000 - 911_577_029);
+        assert!(swap.reserves[0] as u128 * swap.reserves[1] as u128 >= 1_000_000_000_000_000_000);
+    }
+
+    #[test]
+    fn swap_buys_any_outcome_of_a_multi_outcome_poll() {
+        let reserves = [1_000_000, 1_000_000, 1_000_000];
+        let swap = swap_shares(&reserves, 1, 100_000).unwrap();
+        // 1_000_000 * (1_000_000 / 1_100_000)^2, rounded up at each step
+        assert_eq!(swap.reserves, vec![1_100_000, 826_447, 1_100_000]);
+        assert_eq!(swap.amount_out, 1_000_000 - 826_447);
+
+        let product = |shares: &[u64]| shares.iter().map(|&r| r as u128).product::<u128>();
+        assert!(product(&swap.reserves) >= product(&reserves));
+    }
+
+    #[test]
+    fn early_bird_bonus_shrinks_towards_the_close() {
+        assert_eq!(early_bird_bonus(1_000_000, 0, 1000, 0), 200_000);
+        assert_eq!(early_bird_bonus(1_000_000, 0, 1000, 750), 50_000);
+        assert_eq!(early_bird_bonus(1_000_000, 0, 1000, 1000), 0);
+        // No bet earns more than the full bonus, nor any on an empty window
+        assert_eq!(early_bird_bonus(1_000_000, 500, 1000, 0), 200_000);
+        assert_eq!(early_bird_bonus(1_000_000, 1000, 1000, 0), 0);
+    }
+
+    #[test]
+    fn swap_rejects_bets_that_round_to_zero_shares() {
+        assert_eq!(swap_shares(&[1000, 1000], 0, 1).unwrap_err(), AmmError::BetTooSmall.into());
+    }
+
+    #[test]
+    fn swap_rejects_reserves_that_would_overflow() {
+        // The product is never materialized, but a reserve can still outgrow a u64
+        assert_eq!(
+            swap_shares(&[u64::MAX, u64::MAX], 0, u64::MAX).unwrap_err(),
+            AmmError::MathOverflow.into()
+        );
+        // Reserves whose product is far beyond a u64 still swap exactly
+        let swap = swap_shares(&[u64::MAX / 2, u64::MAX / 2], 1, 1 << 40).unwrap();
+        assert!(swap.amount_out > 0);
+        assert!(swap.reserves[0] as u128 * swap.reserves[1] as u128 >= (u64::MAX / 2) as u128 * (u64::MAX / 2) as u128);
+    }
+
+    #[test]
+    fn swap_accepts_amounts_up_to_the_reserve() {
+        assert_eq!(
+            swap_shares(&[1_000_000_000, 1_000_000_000], 0, 1_000_000_000).unwrap(),
+            SwapResult { amount_out: 500_000_000, reserves: vec![500_000_000, 2_000_000_000] }
+        );
+
+        assert_eq!(
+            swap_shares(&[1_000_000_000, 1_000_000_000], 0, 1_000_000_001).unwrap_err(),
+            AmmError::NotEnoughLiquidity.into()
+        );
+    }
+
+    #[test]
+    fn swaps_never_empty_the_outgoing_reserve() {
+        // The largest bets the other reserves accept still leave a share
+        // behind, since the new reserve is rounded up
+        for reserves in [vec![1_000, 1_000], vec![2, 1_000_000_000], vec![3, 1_000, 1_000_000]] {
+            let max_in = *reserves[1..].iter().min().unwrap();
+            let swap = swap_shares(&reserves, 0, max_in).unwrap_or_else(|_| panic!("{reserves:?}"));
+            assert!(swap.reserves[0] > 0, "{reserves:?}");
+            assert_eq!(swap_shares(&reserves, 0, max_in + 1).unwrap_err(), AmmError::NotEnoughLiquidity.into());
+        }
+        // A reserve down to its last share has nothing left to sell
+        assert_eq!(swap_shares(&[1, 1_000_000_000], 0, 1_000_000_000).unwrap_err(), AmmError::BetTooSmall.into());
+    }
+
+    #[test]
+    fn selling_reverses_a_swap_minus_rounding() {
+        let reserves = [1_000_000_000, 1_000_000_000, 1_000_000_000];
+        let bought = swap_shares(&reserves, 2, 97_000_000).unwrap();
+        let sold = sell_shares(&bought.reserves, 2, bought.amount_out).unwrap();
+        assert!((97_000_000 - 2..97_000_000).contains(&sold.amount_out), "{}", sold.amount_out);
+        assert_eq!(sold.reserves[2], reserves[2]);
+
+        let product = |shares: &[u64]| shares.iter().map(|&r| r as u128).product::<u128>();
+        assert!(product(&sold.reserves) >= product(&bought.reserves));
+    }
+
+    #[test]
+    fn selling_rejects_dust_and_reserve_draining_sales() {
+        assert_eq!(sell_shares(&[1000, 1000], 0, 1).unwrap_err(), AmmError::BetTooSmall.into());
+        assert_eq!(
+            sell_shares(&[1000, 1000], 0, 1_000_000_000).unwrap_err(),
+            AmmError::NotEnoughLiquidity.into()
+        );
+    }
+
+    #[test]
+    fn swaps_and_sales_keep_the_product_and_balance_the_trade() {
+        let product = |shares: &[u64]| shares.iter().map(|&r| r as u128).product::<u128>();
+        for reserves in [vec![10_000, 10_000], vec![1_000_000, 3_000_000, 500_000], vec![7_777_777; 4]] {
+            for index in 0..reserves.len() {
+                for amount_in in [100, 2_500, 9_999] {
+                    let swap = swap_shares(&reserves, index, amount_in).unwrap();
+                    assert!(product(&swap.reserves) >= product(&reserves));
+                    // Tokens go into every other reserve, shares come out of the chosen one
+                    for (i, (&before, &after)) in reserves.iter().zip(&swap.reserves).enumerate() {
+                        if i == index {
+                            assert_eq!(before - after, swap.amount_out);
+                        } else {
+                            assert_eq!(after - before, amount_in);
+                        }
+                    }
+
+                    // Selling straight back never pays out more than went in
+                    let sale = sell_shares(&swap.reserves, index, swap.amount_out).unwrap();
+                    assert!(sale.amount_out <= amount_in);
+                    assert!(product(&sale.reserves) >= product(&swap.reserves));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn payout_ratios_compare_payouts_to_wagers() {
+        assert_eq!(payout_ratio(20_000_000, 10_000_000), 20000);
+        assert_eq!(payout_ratio(9_700_000, 10_000_000), 9700);
+        assert_eq!(payout_ratio(1, 3), 3333);
+        assert_eq!(payout_ratio(u64::MAX, 1), u64::MAX);
+        assert_eq!(payout_ratio(100, 0), 0);
+    }
+
+    #[test]
+    fn fees_round_down_unless_asked_to_round_up() {
+        // 3% of 1001 is 30.03
+        assert_eq!(network_fee(1001, 300, false), 30);
+        assert_eq!(network_fee(1001, 300, true), 31);
+        // Amounts that divide evenly agree either way
+        assert_eq!(network_fee(1000, 300, false), 30);
+        assert_eq!(network_fee(1000, 300, true), 30);
+        // A rounded-up fee never exceeds the amount, even on dust
+        assert_eq!(network_fee(1, MAX_FEE_BPS, true), 1);
+        assert_eq!(network_fee(1, 0, true), 0);
+    }
+
+    #[test]
+    fn prices_favor_the_scarcest_outcome() {
+        assert_eq!(compute_prices(&[1_000, 4_000]).unwrap(), vec![8000, 2000]);
+        assert_eq!(compute_prices(&[500, 1_000, 1_000]).unwrap(), vec![5000, 2500, 2500]);
+    }
+
+    #[test]
+    fn balanced_reserves_split_the_price_evenly() {
+        assert_eq!(compute_prices(&[1_000_000, 1_000_000]).unwrap(), vec![5000, 5000]);
+    }
+
+    #[test]
+    fn odds_always_sum_to_10000() {
+        assert_eq!(compute_odds(&[3000, 7000]).unwrap(), vec![7000, 3000]);
+        // Thirds round down to 3333 each; the leftover goes to one outcome
+        let odds = compute_odds(&[1_000, 1_000, 1_000]).unwrap();
+        assert_eq!(odds.iter().sum::<u64>(), 10000);
+        assert_eq!(odds.iter().filter(|&&bps| bps == 3334).count(), 1);
+        let odds = compute_odds(&[7, 1_000_003, 333_333_333, 42]).unwrap();
+        assert_eq!(odds.iter().sum::<u64>(), 10000);
+        assert_eq!(compute_odds(&[0, 1_000]).unwrap_err(), AmmError::EmptyPool.into());
+    }
+
+    #[test]
+    fn prices_hold_for_reserves_summing_past_u64_max() {
+        assert_eq!(compute_prices(&[u64::MAX, u64::MAX]).unwrap(), vec![5000, 5000]);
+        assert_eq!(compute_prices(&[u64::MAX / 3, u64::MAX]).unwrap(), vec![7500, 2500]);
+        let odds = compute_odds(&[u64::MAX; MAX_OUTCOMES]).unwrap();
+        assert_eq!(odds.iter().sum::<u64>(), 10000);
+    }
+
+    #[test]
+    fn prices_reject_an_empty_reserve() {
+        assert_eq!(compute_prices(&[0, 1_000]).unwrap_err(), AmmError::EmptyPool.into());
+        assert_eq!(compute_prices(&[0, 0]).unwrap_err(), AmmError::EmptyPool.into());
+    }
+
+    #[test]
+    fn pda_helpers_are_distinct_per_poll() {
+        let poll = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+
+        assert_ne!(pool_authority_pda(&poll).0, pool_authority_pda(&other).0);
+        assert_ne!(pool_vault_pda(&poll).0, fee_vault_pda(&poll).0);
+        assert_ne!(fee_vault_pda(&poll).0, authority_fee_vault_pda(&poll).0);
+        assert_ne!(pool_authority_pda(&poll).0, pool_vault_pda(&poll).0);
+
+        let user = Pubkey::new_unique();
+        assert_ne!(vote_pda(&poll, &user).0, vote_pda(&other, &user).0);
+        assert_ne!(vote_pda(&poll, &user).0, vote_pda(&poll, &Pubkey::new_unique()).0);
+    }
+
+    #[test]
+    fn a_missing_clock_sysvar_is_reported() {
+        // Off-chain there is no clock sysvar unless a test installs one
+        assert_eq!(clock().unwrap_err(), AmmError::ClockUnavailable.into());
+        assert_eq!(now().unwrap_err(), AmmError::ClockUnavailable.into());
+    }
+
+    #[test]
+    fn liquidity_must_follow_the_reserve_ratio() {
+        assert!(liquidity_is_balanced(&[1_000, 3_000], &[10, 30]));
+        assert!(liquidity_is_balanced(&[1_000, 3_000, 2_000], &[100, 300, 200]));
+        // Within 1% of the ratio is close enough
+        assert!(liquidity_is_balanced(&[1_000, 3_000], &[1_000, 3_029]));
+        assert!(!liquidity_is_balanced(&[1_000, 3_000], &[1_000, 3_031]));
+        assert!(!liquidity_is_balanced(&[1_000, 3_000], &[20, 30]));
+        assert!(!liquidity_is_balanced(&[1_000, 3_000], &[0, 30]));
+        assert!(liquidity_is_balanced(&[1_000, 3_000], &[0, 0]));
+    }
+
+    #[test]
+    fn poll_space_fits_the_largest_poll() {
+        assert_eq!(Poll::space(MAX_TITLE_LEN, MAX_OUTCOMES, MAX_METADATA_URI_LEN), 8 + Poll::LEN);
+        // Every outcome and URI byte below the maximum is rent not paid
+        let small = Poll::space(10, 2, 0);
+        assert_eq!(
+            8 + Poll::LEN - small,
+            MAX_TITLE_LEN - 10 + (MAX_OUTCOMES - 2) * Poll::PER_OUTCOME_LEN + MAX_METADATA_URI_LEN
+        );
     }
 }
@@ -0,0 +1,3477 @@
+//! Randomized accounting invariant harness.
+//!
+//! Each case runs the program in-process under `solana-program-test` on a
+//! two- or three-outcome poll and drives a pseudo-random sequence of
+//! `create_poll`, `vote`, `sell_position`, `add_liquidity`,
+//! `remove_liquidity` and `set_paused`, then either `resolve_poll` and
+//! `claim_winnings` or `cancel_poll` and `claim_refund`, and finally
+//! withdraws the remaining liquidity and fees, mixing dust-sized and regular
+//! amounts. After every step it checks:
+//!
+//! - token conservation: user balances + pool vault + fee vault always equal
+//!   the minted supply, so tokens are never created or lost
+//! - vault accounting: the pool vault holds exactly the stakes and liquidity
+//!   deposited minus sales and payouts, and the fee vault exactly the fees
+//!   charged, with sale fees at the poll's rate
+//! - activity: the poll's volume and vote counters match the accepted votes
+//! - reserves: every reserve stays positive while the poll is active, and
+//!   their product only decreases when liquidity is withdrawn
+//! - backing: until resolution the vault holds exactly the per-side stakes
+//!   plus the liquidity recorded on the poll
+//! - solvency: once resolved, everything still owed to winners fits in the
+//!   pool vault, and the poll counts exactly the votes still owed a payout
+//! - settlement: after all claims or refunds and withdrawals no stakes are
+//!   left, the last winner taking the rounding dust; every settled vote can
+//!   then be closed
+//!
+//! While the program is paused no vote, sale or deposit may be accepted.
+//! Rejected transactions are allowed (the program may refuse an operation),
+//! but they must leave every balance untouched.
+
+use anchor_lang::error::ErrorCode;
+use anchor_lang::prelude::borsh;
+use anchor_lang::{AccountDeserialize, AnchorDeserialize, AnchorSerialize, Discriminator, InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use solana_contracts::{
+    accounts, instruction, AdminTransferProposed, AdminTransferred, AmmError, Config, FeeChangedEvent, FeesWithdrawn, LegacyVote,
+    MarketStateEvent, OutcomeDisabled, PausedEvent, Poll, PollClosedEvent, PollRegistry, PollResolvedEvent, PollStateEvent, PollStatus,
+    PositionSummaryEvent, PriceUpdateEvent, RefundClaimed, ResolutionMode, ResolutionOverriddenEvent, SplitLeg,
+    UnclaimedSweptEvent, Vote, WinningsClaimed, DEFAULT_MAX_TITLE_LEN, MAX_FEE_BPS, MAX_METADATA_URI_LEN, MAX_TITLE_LEN,
+    FEE_PER_DEPOSIT_SCALE, LP_FEE_SHARE_BPS, MINIMUM_LIQUIDITY, POLL_VERSION, USE_DEFAULT_FEE,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::Keypair,
+    signer::Signer,
+    system_instruction, system_program,
+    transaction::Transaction,
+};
+use std::sync::{Once, OnceLock};
+
+const CASES: u64 = 12;
+const STEPS: usize = 24;
+const USERS: usize = 5;
+const USER_BALANCE: u64 = 1_000_000_000;
+const INITIAL_SHARES: u64 = 1_000_000_000;
+const EVENT_LOG_PREFIX: &str = "data: ";
+
+// The Anchor entrypoint ties account lifetimes together, which the
+// program-test processor signature does not allow
+fn entry(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    solana_contracts::entry(program_id, accounts, data)
+}
+
+// The program-test processor runs the program natively, without the
+// upgradeable loader's ProgramData account that names the upgrade authority
+fn program_data_address() -> Pubkey {
+    Pubkey::find_program_address(&[solana_contracts::ID.as_ref()], &bpf_loader_upgradeable::ID).0
+}
+
+fn program_data_account(authority: &Pubkey) -> Account {
+    let state = UpgradeableLoaderState::ProgramData { slot: 0, upgrade_authority_address: Some(*authority) };
+    let len = UpgradeableLoaderState::size_of_programdata_metadata();
+    let account = Account::new_data_with_space(Rent::default().minimum_balance(len), &state, len, &bpf_loader_upgradeable::ID);
+    account.unwrap()
+}
+
+fn initialize_config_ix(admin: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: solana_contracts::ID,
+        accounts: accounts::InitializeConfig {
+            config: solana_contracts::config_pda().0,
+            admin: *admin,
+            program_data: program_data_address(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::InitializeConfig {}.data(),
+    }
+}
+
+// Natively built programs print `emit!` data to stdout instead of the
+// transaction logs, so route it through the program-test logger to let
+// tests read events back from the transaction metadata
+struct EventLogStubs;
+
+static TEST_STUBS: OnceLock<Box<dyn SyscallStubs>> = OnceLock::new();
+
+fn capture_event_logs() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let test_stubs = program_stubs::set_syscall_stubs(Box::new(EventLogStubs));
+        assert!(TEST_STUBS.set(test_stubs).is_ok());
+    });
+}
+
+impl SyscallStubs for EventLogStubs {
+    fn sol_log(&self, message: &str) {
+        TEST_STUBS.wait().sol_log(message)
+    }
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let fields: Vec<_> = fields.iter().map(|field| BASE64_STANDARD.encode(field)).collect();
+        self.sol_log(&format!("{EVENT_LOG_PREFIX}{}", fields.join(" ")))
+    }
+    fn sol_invoke_signed(&self, instruction: &Instruction, account_infos: &[AccountInfo], signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+        TEST_STUBS.wait().sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        TEST_STUBS.wait().sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        TEST_STUBS.wait().sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        TEST_STUBS.wait().sol_get_epoch_rewards_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        TEST_STUBS.wait().sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        TEST_STUBS.wait().sol_get_rent_sysvar(var_addr)
+    }
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        TEST_STUBS.wait().sol_get_last_restart_slot(var_addr)
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        TEST_STUBS.wait().sol_get_return_data()
+    }
+    fn sol_set_return_data(&self, data: &[u8]) {
+        TEST_STUBS.wait().sol_set_return_data(data)
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        TEST_STUBS.wait().sol_get_stack_height()
+    }
+}
+
+// xorshift64*, enough to make every case reproducible from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn range(&mut self, lo: u64, hi: u64) -> u64 {
+        lo + self.next() % (hi - lo + 1)
+    }
+
+    fn one_in(&mut self, n: u64) -> bool {
+        self.next().is_multiple_of(n)
+    }
+
+    // Mostly regular bets, with dust amounts mixed in to exercise rounding
+    fn amount(&mut self) -> u64 {
+        if self.one_in(4) {
+            self.range(1, 100)
+        } else {
+            self.range(100_000, 50_000_000)
+        }
+    }
+}
+
+// Balances the harness expects the vaults to hold
+#[derive(Default)]
+struct Model {
+    pool_vault: u64,
+    fee_vault: u64,
+    authority_fee_vault: u64,
+    liquidity: u64,
+    volume: u64,
+    vote_count: u64,
+    reserve_product: u128,
+    // Lamports vote_sol wrapped straight into the vaults, net of the
+    // payouts claim_winnings_sol unwrapped again
+    wrapped_sol: u64,
+    // Every liquidity provider with an open position
+    lp_positions: Vec<LpShare>,
+    // Fees in the fee vault set aside for the providers, and their amount
+    // per deposited token, scaled like the program's accumulator
+    lp_fees: u64,
+    fee_per_deposit: u128,
+}
+
+// A provider's outstanding deposit and the fees it earned, keyed by
+// provider index where USERS stands for the authority
+#[derive(Clone, Copy)]
+struct LpShare {
+    provider: usize,
+    deposited: u64,
+    fee_per_deposit_paid: u128,
+    fees_owed: u64,
+}
+
+impl Model {
+    fn lp_deposits(&self) -> u64 {
+        self.lp_positions.iter().map(|share| share.deposited).sum()
+    }
+
+    // The provider's position with the fees earned so far booked on it
+    fn lp_position(&mut self, provider: usize) -> Option<&mut LpShare> {
+        let fee_per_deposit = self.fee_per_deposit;
+        let share = self.lp_positions.iter_mut().find(|share| share.provider == provider)?;
+        let earned = (fee_per_deposit - share.fee_per_deposit_paid) * share.deposited as u128 / FEE_PER_DEPOSIT_SCALE;
+        share.fees_owed += earned as u64;
+        share.fee_per_deposit_paid = fee_per_deposit;
+        Some(share)
+    }
+
+    // Splits the providers' part off a protocol fee, spread over the
+    // deposits invested when it was charged
+    fn book_lp_fee(&mut self, protocol_fee: u64) {
+        let lp_deposits = self.lp_deposits();
+        if lp_deposits > 0 {
+            let lp_fee = (protocol_fee as u128 * LP_FEE_SHARE_BPS as u128 / 10000) as u64;
+            self.fee_per_deposit += lp_fee as u128 * FEE_PER_DEPOSIT_SCALE / lp_deposits as u128;
+            self.lp_fees += lp_fee;
+        }
+    }
+
+    fn protocol_fees(&self) -> u64 {
+        self.fee_vault - self.lp_fees
+    }
+}
+
+// Less common poll settings, off by default
+#[derive(Default)]
+struct PollOptions {
+    // Run the poll on wrapped SOL instead of a fresh 6-decimal mint; user
+    // balances are then funded by wrapping the payer's lamports
+    native: bool,
+    dispute_period: i64,
+    bonus_enabled: bool,
+    // Part of the network fee paid to the poll authority
+    authority_fee_bps: u16,
+    // Seconds after closes_at before anyone may cancel the unresolved poll
+    resolution_grace_period: i64,
+    // Make resolve_poll ask for a token account holding the winning NFT
+    require_ownership_proof: bool,
+    // Seconds a user must wait between two bets
+    min_vote_interval: i64,
+    // Reserve every outcome starts with, INITIAL_SHARES unless set
+    initial_shares: Option<u64>,
+    // Make resolve_poll refuse a poll without votes
+    require_votes: bool,
+    // Smallest reserve a bet may leave
+    min_reserve: u64,
+    // Poll title, "Invariant harness" unless set
+    title: Option<Vec<u8>>,
+}
+
+struct Harness {
+    ctx: ProgramTestContext,
+    mint: Pubkey,
+    users: Vec<(Keypair, Pubkey)>,
+    authority_token_account: Pubkey,
+    poll: Pubkey,
+    closes_at: i64,
+    pool_authority: Pubkey,
+    pool_vault: Pubkey,
+    fee_vault: Pubkey,
+    authority_fee_vault: Pubkey,
+    outcomes: Vec<Pubkey>,
+    votes: Vec<(Pubkey, usize)>,
+    // Approved (beneficiary, delegate) pairs of users
+    delegations: Vec<(usize, usize)>,
+    paused: bool,
+    round_fee_up: bool,
+    refund_fees_on_cancel: bool,
+    // Event data logged by the last transaction sent
+    event_data: Vec<Vec<u8>>,
+    model: Model,
+}
+
+impl Harness {
+    async fn new(fee_bps: u16, claim_fee_bps: u16, outcome_count: usize) -> Self {
+        Self::with_options(fee_bps, claim_fee_bps, outcome_count, PollOptions::default()).await
+    }
+
+    async fn with_options(fee_bps: u16, claim_fee_bps: u16, outcome_count: usize, options: PollOptions) -> Self {
+        let native = options.native;
+        let mut program = ProgramTest::new("solana_contracts", solana_contracts::ID, processor!(entry));
+        if native {
+            // program-test does not ship the native mint account
+            let mut data = vec![0; spl_token::state::Mint::LEN];
+            let mint = spl_token::state::Mint { decimals: 9, is_initialized: true, ..Default::default() };
+            spl_token::state::Mint::pack(mint, &mut data).unwrap();
+            program.add_account(
+                spl_token::native_mint::ID,
+                Account {
+                    lamports: Rent::default().minimum_balance(data.len()),
+                    data,
+                    owner: spl_token::ID,
+                    ..Default::default()
+                },
+            );
+        }
+        let ctx = program.start_with_context().await;
+        capture_event_logs();
+        let mut harness = Harness {
+            ctx,
+            mint: Pubkey::default(),
+            users: Vec::new(),
+            authority_token_account: Pubkey::default(),
+            poll: Pubkey::default(),
+            closes_at: 0,
+            pool_authority: Pubkey::default(),
+            pool_vault: Pubkey::default(),
+            fee_vault: Pubkey::default(),
+            authority_fee_vault: Pubkey::default(),
+            outcomes: (0..outcome_count).map(|_| Pubkey::new_unique()).collect(),
+            votes: Vec::new(),
+            delegations: Vec::new(),
+            paused: false,
+            round_fee_up: false,
+            refund_fees_on_cancel: false,
+            event_data: Vec::new(),
+            model: Model::default(),
+        };
+
+        let payer = harness.ctx.payer.insecure_clone();
+        let rent = harness.ctx.banks_client.get_rent().await.unwrap();
+        if native {
+            harness.mint = spl_token::native_mint::ID;
+        } else {
+            let mint = Keypair::new();
+            harness
+                .send(
+                    &[
+                        system_instruction::create_account(
+                            &payer.pubkey(),
+                            &mint.pubkey(),
+                            rent.minimum_balance(spl_token::state::Mint::LEN),
+                            spl_token::state::Mint::LEN as u64,
+                            &spl_token::ID,
+                        ),
+                        spl_token::instruction::initialize_mint(
+                            &spl_token::ID,
+                            &mint.pubkey(),
+                            &payer.pubkey(),
+                            None,
+                            6,
+                        )
+                        .unwrap(),
+                    ],
+                    &[&mint],
+                )
+                .await
+                .unwrap();
+            harness.mint = mint.pubkey();
+        }
+
+        for _ in 0..USERS {
+            let user = Keypair::new();
+            harness
+                .send(
+                    &[system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000)],
+                    &[],
+                )
+                .await
+                .unwrap();
+            let token_account = harness.create_token_account(&user.pubkey()).await;
+            harness.fund(token_account).await;
+            harness.users.push((user, token_account));
+        }
+        // The authority can provide liquidity too, so it gets a balance of its own
+        harness.authority_token_account = harness.create_token_account(&payer.pubkey()).await;
+        harness.fund(harness.authority_token_account).await;
+
+        harness.ctx.set_account(&program_data_address(), &program_data_account(&payer.pubkey()).into());
+        harness.send(&[initialize_config_ix(&payer.pubkey())], &[]).await.unwrap();
+
+        let poll = Keypair::new();
+        harness.poll = poll.pubkey();
+        harness.pool_authority = solana_contracts::pool_authority_pda(&harness.poll).0;
+        harness.pool_vault = solana_contracts::pool_vault_pda(&harness.poll).0;
+        harness.fee_vault = solana_contracts::fee_vault_pda(&harness.poll).0;
+        harness.authority_fee_vault = solana_contracts::authority_fee_vault_pda(&harness.poll).0;
+        let clock: Clock = harness.ctx.banks_client.get_sysvar().await.unwrap();
+        harness.closes_at = clock.unix_timestamp + 3600;
+
+        harness.create_poll(&poll, fee_bps, claim_fee_bps, &options).await.unwrap();
+        let initial_shares = options.initial_shares.unwrap_or(INITIAL_SHARES);
+        harness.model.reserve_product = (initial_shares as u128).pow(outcome_count as u32);
+
+        harness
+    }
+
+    // Creates a poll on the harness's mint and outcomes, closing at closes_at
+    async fn create_poll(
+        &mut self,
+        poll: &Keypair,
+        fee_bps: u16,
+        claim_fee_bps: u16,
+        options: &PollOptions,
+    ) -> Result<(), String> {
+        let payer = self.ctx.payer.pubkey();
+        let poll_key = poll.pubkey();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::CreatePoll {
+                poll: poll_key,
+                authority: payer,
+                config: solana_contracts::config_pda().0,
+                registry: solana_contracts::registry_pda(&payer).0,
+                token_mint: self.mint,
+                parent_poll: None,
+                pool_vault: solana_contracts::pool_vault_pda(&poll_key).0,
+                fee_vault: solana_contracts::fee_vault_pda(&poll_key).0,
+                authority_fee_vault: solana_contracts::authority_fee_vault_pda(&poll_key).0,
+                pool_authority: solana_contracts::pool_authority_pda(&poll_key).0,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::CreatePoll {
+                title_bytes: options.title.clone().unwrap_or_else(|| b"Invariant harness".to_vec()),
+                closes_at: self.closes_at,
+                outcomes: self.outcomes.clone(),
+                initial_shares: vec![options.initial_shares.unwrap_or(INITIAL_SHARES); self.outcomes.len()],
+                required_parent_outcome: None,
+                claim_fee_bps,
+                resolution_mode: ResolutionMode::Resolver,
+                fee_bps,
+                min_bet: 1,
+                max_bet: u64::MAX,
+                metadata_uri: Vec::new(),
+                resolver: Pubkey::default(),
+                dispute_period: options.dispute_period,
+                expected_decimals: Some(if options.native { 9 } else { 6 }),
+                bonus_enabled: options.bonus_enabled,
+                authority_fee_bps: options.authority_fee_bps,
+                resolution_grace_period: options.resolution_grace_period,
+                require_ownership_proof: options.require_ownership_proof,
+                min_vote_interval: options.min_vote_interval,
+                require_votes: options.require_votes,
+                min_reserve: options.min_reserve,
+            }
+            .data(),
+        };
+        self.send(&[ix], &[poll]).await
+    }
+
+    async fn send(&mut self, ixs: &[Instruction], signers: &[&Keypair]) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let mut all_signers = vec![&payer];
+        all_signers.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(
+            ixs,
+            Some(&payer.pubkey()),
+            &all_signers,
+            self.ctx.last_blockhash,
+        );
+        let processed = self
+            .ctx
+            .banks_client
+            .process_transaction_with_metadata(tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        // Keep the events of the last transaction for tests to inspect
+        self.event_data = processed
+            .metadata
+            .map(|metadata| metadata.log_messages)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|log| log.strip_prefix("Program log: ")?.strip_prefix(EVENT_LOG_PREFIX))
+            .filter_map(|data| BASE64_STANDARD.decode(data).ok())
+            .collect();
+        processed.result.map_err(|e| e.to_string())
+    }
+
+    // Events of type T emitted by the last transaction sent
+    fn events<T: AnchorDeserialize + Discriminator>(&self) -> Vec<T> {
+        self.event_data
+            .iter()
+            .filter_map(|data| data.strip_prefix(T::DISCRIMINATOR))
+            .map(|mut payload| T::deserialize(&mut payload).unwrap())
+            .collect()
+    }
+
+    async fn create_token_account(&mut self, owner: &Pubkey) -> Pubkey {
+        let account = Keypair::new();
+        let payer = self.ctx.payer.pubkey();
+        let rent = self.ctx.banks_client.get_rent().await.unwrap();
+        self.send(
+            &[
+                system_instruction::create_account(
+                    &payer,
+                    &account.pubkey(),
+                    rent.minimum_balance(spl_token::state::Account::LEN),
+                    spl_token::state::Account::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_account3(
+                    &spl_token::ID,
+                    &account.pubkey(),
+                    &self.mint,
+                    owner,
+                )
+                .unwrap(),
+            ],
+            &[&account],
+        )
+        .await
+        .unwrap();
+        account.pubkey()
+    }
+
+    // Gives a token account USER_BALANCE, minted or wrapped from the payer
+    // Token account of owner holding amount of an outcome's NFT. The
+    // outcomes are not real mints, so the account is written directly
+    fn hold_nft(&mut self, nft: Pubkey, owner: Pubkey, amount: u64) -> Pubkey {
+        let token_account = Pubkey::new_unique();
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        let state = spl_token::state::Account {
+            mint: nft,
+            owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        };
+        spl_token::state::Account::pack(state, &mut data).unwrap();
+        let account = Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: spl_token::ID,
+            ..Default::default()
+        };
+        self.ctx.set_account(&token_account, &account.into());
+        token_account
+    }
+
+    async fn update_metadata(&mut self, metadata_uri: Vec<u8>) -> Result<(), String> {
+        let payer = self.ctx.payer.pubkey();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::UpdateMetadata { poll: self.poll, authority: payer, system_program: system_program::ID }
+                .to_account_metas(None),
+            data: instruction::UpdateMetadata { metadata_uri }.data(),
+        };
+        self.send(&[ix], &[]).await
+    }
+
+    async fn migrate_poll(&mut self) -> Result<(), String> {
+        let payer = self.ctx.payer.pubkey();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::MigratePoll {
+                poll: self.poll,
+                authority: payer,
+                registry: solana_contracts::registry_pda(&payer).0,
+                token_mint: self.mint,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                authority_fee_vault: self.authority_fee_vault,
+                pool_authority: self.pool_authority,
+                payer,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::MigratePoll {}.data(),
+        };
+        self.send(&[ix], &[]).await
+    }
+
+    // Migrates a first-deployment vote of `user`, whose stake sits in
+    // `legacy_vault`, and books it like a bet
+    async fn migrate_vote(&mut self, vote: Pubkey, user: usize, legacy_vault: Pubkey) -> Result<(), String> {
+        let data = self.ctx.banks_client.get_account(vote).await.unwrap().unwrap().data;
+        let payer = self.ctx.payer.pubkey();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::MigrateVote {
+                poll: self.poll,
+                vote,
+                legacy_vault,
+                pool_vault: self.pool_vault,
+                pool_authority: self.pool_authority,
+                payer,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::MigrateVote {}.data(),
+        };
+        self.send(&[ix], &[]).await?;
+        let legacy = LegacyVote::deserialize(&mut &data[8..]).unwrap();
+        self.model.pool_vault += legacy.value - legacy.value * 3 / 100;
+        self.model.volume += legacy.value;
+        self.model.vote_count += 1;
+        self.votes.push((vote, user));
+        Ok(())
+    }
+
+    async fn fund(&mut self, token_account: Pubkey) {
+        let payer = self.ctx.payer.pubkey();
+        let ixs = if self.mint == spl_token::native_mint::ID {
+            vec![
+                system_instruction::transfer(&payer, &token_account, USER_BALANCE),
+                spl_token::instruction::sync_native(&spl_token::ID, &token_account).unwrap(),
+            ]
+        } else {
+            vec![spl_token::instruction::mint_to(&spl_token::ID, &self.mint, &token_account, &payer, &[], USER_BALANCE)
+                .unwrap()]
+        };
+        self.send(&ixs, &[]).await.unwrap();
+    }
+
+    async fn balance(&mut self, token_account: Pubkey) -> u64 {
+        let account = self.ctx.banks_client.get_account(token_account).await.unwrap().unwrap();
+        spl_token::state::Account::unpack(&account.data).unwrap().amount
+    }
+
+    async fn poll(&mut self) -> Poll {
+        self.poll_account(self.poll).await
+    }
+
+    async fn poll_account(&mut self, poll: Pubkey) -> Poll {
+        let account = self.ctx.banks_client.get_account(poll).await.unwrap().unwrap();
+        Poll::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    async fn vote_account(&mut self, vote: Pubkey) -> Vote {
+        let account = self.ctx.banks_client.get_account(vote).await.unwrap().unwrap();
+        Vote::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    async fn vote(&mut self, user: usize, nft_choice: u8, amount: u64) {
+        self.vote_for(user, user, nft_choice, amount).await;
+    }
+
+    // A bet paid by `funder` and credited to `beneficiary`'s position
+    async fn vote_for(&mut self, funder: usize, beneficiary: usize, nft_choice: u8, amount: u64) {
+        let _ = self.try_vote_for(funder, beneficiary, nft_choice, amount).await;
+    }
+
+    async fn try_vote(&mut self, user: usize, nft_choice: u8, amount: u64) -> Result<(), String> {
+        self.try_vote_for(user, user, nft_choice, amount).await
+    }
+
+    async fn try_vote_for(
+        &mut self,
+        funder: usize,
+        beneficiary: usize,
+        nft_choice: u8,
+        amount: u64,
+    ) -> Result<(), String> {
+        let (wallet, token_account) = (self.users[funder].0.insecure_clone(), self.users[funder].1);
+        let beneficiary_key = self.users[beneficiary].0.pubkey();
+        let vote = solana_contracts::vote_pda(&self.poll, &beneficiary_key).0;
+        // The delegation is only passed when the beneficiary approved it
+        let delegated = self.delegations.contains(&(beneficiary, funder));
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::VoteOnPoll {
+                poll: self.poll,
+                config: solana_contracts::config_pda().0,
+                vote,
+                delegation: delegated.then(|| solana_contracts::delegation_pda(&beneficiary_key, &wallet.pubkey()).0),
+                user: wallet.pubkey(),
+                user_token_account: token_account,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                authority_fee_vault: self.authority_fee_vault,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Vote {
+                nft_choice,
+                amount,
+                min_shares_out: 0,
+                max_price_impact_bps: None,
+                beneficiary: (funder != beneficiary).then_some(beneficiary_key),
+            }
+            .data(),
+        };
+        let poll = self.poll().await;
+        let result = self.send(&[ix], &[&wallet]).await;
+        if result.is_ok() {
+            assert!(!self.paused, "vote accepted while paused");
+            assert!(funder == beneficiary || delegated, "bet credited without the beneficiary's approval");
+            let fee = self.network_fee(amount, poll.fee_bps);
+            self.model.pool_vault += amount - fee;
+            self.book_fee(&poll, amount, fee);
+            self.model.volume += amount;
+            self.model.vote_count += 1;
+            // Further bets by the same user add to their existing position
+            if !self.votes.contains(&(vote, beneficiary)) {
+                self.votes.push((vote, beneficiary));
+            }
+        } else {
+            // Without a fresh vote keypair the same bet could be retried as
+            // a duplicate of the rejected transaction
+            self.ctx.get_new_latest_blockhash().await.unwrap();
+        }
+        result
+    }
+
+    // Lets `delegate` credit bets to `beneficiary`'s positions
+    async fn approve_delegate(&mut self, beneficiary: usize, delegate: usize) -> Result<(), String> {
+        let wallet = self.users[beneficiary].0.insecure_clone();
+        let delegate_key = self.users[delegate].0.pubkey();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ApproveDelegate {
+                delegation: solana_contracts::delegation_pda(&wallet.pubkey(), &delegate_key).0,
+                beneficiary: wallet.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ApproveDelegate { delegate: delegate_key }.data(),
+        };
+        self.send(&[ix], &[&wallet]).await?;
+        self.delegations.push((beneficiary, delegate));
+        Ok(())
+    }
+
+    async fn revoke_delegate(&mut self, beneficiary: usize, delegate: usize) -> Result<(), String> {
+        let wallet = self.users[beneficiary].0.insecure_clone();
+        let delegate_key = self.users[delegate].0.pubkey();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::RevokeDelegate {
+                delegation: solana_contracts::delegation_pda(&wallet.pubkey(), &delegate_key).0,
+                beneficiary: wallet.pubkey(),
+            }
+            .to_account_metas(None),
+            data: instruction::RevokeDelegate {}.data(),
+        };
+        self.send(&[ix], &[&wallet]).await?;
+        self.delegations.retain(|&pair| pair != (beneficiary, delegate));
+        Ok(())
+    }
+
+    // Hedges user's stake across two outcomes with vote_split; each leg is a
+    // (nft_choice, amount) pair. The first goes into the user's regular
+    // position
+    async fn vote_split(&mut self, user: usize, first: (u8, u64), second: (u8, u64)) -> Result<(), String> {
+        let (wallet, token_account) = (self.users[user].0.insecure_clone(), self.users[user].1);
+        let legs = [first, second];
+        let first_vote = solana_contracts::vote_pda(&self.poll, &wallet.pubkey()).0;
+        let second_vote = solana_contracts::split_vote_pda(&self.poll, &wallet.pubkey(), second.0).0;
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::VoteSplit {
+                poll: self.poll,
+                config: solana_contracts::config_pda().0,
+                vote: first_vote,
+                second_vote,
+                user: wallet.pubkey(),
+                user_token_account: token_account,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                authority_fee_vault: self.authority_fee_vault,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::VoteSplit {
+                first: SplitLeg { nft_choice: first.0, amount: first.1, min_shares_out: 0 },
+                second: SplitLeg { nft_choice: second.0, amount: second.1, min_shares_out: 0 },
+            }
+            .data(),
+        };
+        let poll = self.poll().await;
+        if let Err(err) = self.send(&[ix], &[&wallet]).await {
+            self.ctx.get_new_latest_blockhash().await.unwrap();
+            return Err(err);
+        }
+        assert!(!self.paused, "split vote accepted while paused");
+        // One fee on the total, shared in proportion to the legs
+        let total = first.1 + second.1;
+        let fee = self.network_fee(total, poll.fee_bps);
+        let first_fee = (fee as u128 * first.1 as u128 / total as u128) as u64;
+        let leg_fees = [(first_vote, first_fee), (second_vote, fee - first_fee)];
+        for ((_, amount), (vote, leg_fee)) in legs.into_iter().zip(leg_fees) {
+            self.model.pool_vault += amount - leg_fee;
+            self.book_fee(&poll, amount, leg_fee);
+            self.model.vote_count += 1;
+            if !self.votes.contains(&(vote, user)) {
+                self.votes.push((vote, user));
+            }
+        }
+        self.model.volume += total;
+        Ok(())
+    }
+
+    // Network fee the program charges on `amount` under the current rounding
+    fn network_fee(&self, amount: u64, fee_bps: u16) -> u64 {
+        let scaled = amount as u128 * fee_bps as u128;
+        (if self.round_fee_up { scaled.div_ceil(10000) } else { scaled / 10000 }) as u64
+    }
+
+    // Splits a network fee between the protocol and authority fee vaults
+    fn book_fee(&mut self, poll: &Poll, amount: u64, fee: u64) {
+        let authority_fee = ((amount as u128 * poll.authority_fee_bps as u128 / 10000) as u64).min(fee);
+        self.model.fee_vault += fee - authority_fee;
+        self.model.authority_fee_vault += authority_fee;
+        self.model.book_lp_fee(fee - authority_fee);
+    }
+
+    async fn lamports(&mut self, account: Pubkey) -> u64 {
+        self.ctx.banks_client.get_balance(account).await.unwrap()
+    }
+
+    // Bets lamports on a wrapped-SOL poll; the user wallet pays the stake,
+    // the fee and the rent of a new vote account
+    async fn vote_sol(&mut self, user: usize, nft_choice: u8, amount: u64) {
+        let wallet = self.users[user].0.insecure_clone();
+        let vote = solana_contracts::vote_pda(&self.poll, &wallet.pubkey()).0;
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::VoteSol {
+                poll: self.poll,
+                config: solana_contracts::config_pda().0,
+                vote,
+                delegation: None,
+                user: wallet.pubkey(),
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                authority_fee_vault: self.authority_fee_vault,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::VoteSol {
+                nft_choice,
+                amount,
+                min_shares_out: 0,
+                max_price_impact_bps: None,
+                beneficiary: None,
+            }
+            .data(),
+        };
+        let poll = self.poll().await;
+        let before = self.lamports(wallet.pubkey()).await;
+        self.send(&[ix], &[&wallet]).await.unwrap();
+        let rent = self.lamports(vote).await;
+        assert_eq!(before - self.lamports(wallet.pubkey()).await, amount + rent, "bet not paid in lamports");
+
+        let fee = self.network_fee(amount, poll.fee_bps);
+        self.model.pool_vault += amount - fee;
+        self.book_fee(&poll, amount, fee);
+        self.model.volume += amount;
+        self.model.vote_count += 1;
+        self.model.wrapped_sol += amount;
+        self.votes.push((vote, user));
+    }
+
+    // Claims a wrapped-SOL poll's winnings as lamports and returns how many
+    // the user wallet received
+    async fn claim_sol(&mut self, vote: Pubkey, user: usize) -> Result<u64, String> {
+        let poll = self.poll().await;
+        let vote_account = self.vote_account(vote).await;
+        let wallet = self.users[user].0.insecure_clone();
+        let unwrap_account = solana_contracts::unwrap_pda(&self.poll, &wallet.pubkey()).0;
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ClaimWinningsSol {
+                poll: self.poll,
+                vote,
+                user: wallet.pubkey(),
+                unwrap_account,
+                token_mint: self.mint,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                pool_authority: self.pool_authority,
+                parent_poll: None,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ClaimWinningsSol {}.data(),
+        };
+        let before = self.lamports(wallet.pubkey()).await;
+        self.send(&[ix], &[&wallet]).await?;
+        assert_eq!(self.lamports(unwrap_account).await, 0, "temporary wSOL account left open");
+
+        let payout = payout(&poll, &vote_account);
+        let claim_fee = (payout as u128 * poll.claim_fee_bps as u128 / 10000) as u64;
+        self.model.pool_vault -= payout;
+        self.model.fee_vault += claim_fee;
+        self.model.wrapped_sol -= payout - claim_fee;
+        Ok(self.lamports(wallet.pubkey()).await - before)
+    }
+
+    // Sells an open position back to the pool, closing its vote
+    async fn sell(&mut self, position: usize) {
+        let _ = self.try_sell(position).await;
+    }
+
+    async fn try_sell(&mut self, position: usize) -> Result<(), String> {
+        let (vote, user) = self.votes[position];
+        let vote_account = self.vote_account(vote).await;
+        let poll = self.poll().await;
+        let (wallet, token_account) = (self.users[user].0.insecure_clone(), self.users[user].1);
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SellPosition {
+                poll: self.poll,
+                config: solana_contracts::config_pda().0,
+                vote,
+                user: wallet.pubkey(),
+                user_token_account: token_account,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                authority_fee_vault: self.authority_fee_vault,
+                pool_authority: self.pool_authority,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::SellPosition { min_tokens_out: 0 }.data(),
+        };
+        let balance_before = self.balance(token_account).await;
+        let result = self.send(&[ix], &[&wallet]).await;
+        if result.is_ok() {
+            assert!(!self.paused, "sale accepted while paused");
+            let proceeds = self.balance(token_account).await - balance_before;
+            let fee = self.balance(self.fee_vault).await - self.model.fee_vault
+                + self.balance(self.authority_fee_vault).await
+                - self.model.authority_fee_vault;
+            let tokens_out = proceeds + fee;
+            assert_eq!(fee, self.network_fee(tokens_out, poll.fee_bps), "sale fee");
+            self.model.pool_vault -= tokens_out;
+            self.book_fee(&poll, tokens_out, fee);
+            self.model.liquidity = self.model.liquidity + vote_account.stake - tokens_out;
+            self.votes.remove(position);
+            let account = self.ctx.banks_client.get_account(vote).await.unwrap();
+            assert!(account.is_none(), "sold vote account left open");
+            // Selling a reopened position sends the same bytes again, which
+            // the bank would answer from its cache of processed transactions
+            self.ctx.get_new_latest_blockhash().await.unwrap();
+        } else {
+            // The position may be sold again later; a fresh blockhash keeps
+            // that retry from being a duplicate of the rejected transaction
+            self.ctx.get_new_latest_blockhash().await.unwrap();
+        }
+        result
+    }
+
+    // A user's wallet and token account, or the authority's for USERS
+    fn provider(&self, provider: usize) -> (Keypair, Pubkey) {
+        match self.users.get(provider) {
+            Some((wallet, token_account)) => (wallet.insecure_clone(), *token_account),
+            None => (self.ctx.payer.insecure_clone(), self.authority_token_account),
+        }
+    }
+
+    async fn add_liquidity(&mut self, provider: usize, amounts: Vec<u64>) -> Result<(), String> {
+        let (wallet, token_account) = self.provider(provider);
+        let total: u64 = amounts.iter().sum();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::AddLiquidity {
+                poll: self.poll,
+                config: solana_contracts::config_pda().0,
+                user: wallet.pubkey(),
+                user_token_account: token_account,
+                pool_vault: self.pool_vault,
+                lp_position: solana_contracts::lp_position_pda(&self.poll, &wallet.pubkey()).0,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::AddLiquidity { amounts }.data(),
+        };
+        // The payer already signs every transaction
+        let signers: &[&Keypair] = if provider == USERS { &[] } else { &[&wallet] };
+        let result = self.send(&[ix], signers).await;
+        if result.is_ok() {
+            assert!(!self.paused, "liquidity accepted while paused");
+            self.model.pool_vault += total;
+            self.model.liquidity += total;
+            let fee_per_deposit = self.model.fee_per_deposit;
+            match self.model.lp_position(provider) {
+                Some(share) => share.deposited += total,
+                None => self.model.lp_positions.push(LpShare {
+                    provider,
+                    deposited: total,
+                    fee_per_deposit_paid: fee_per_deposit,
+                    fees_owed: 0,
+                }),
+            }
+        } else {
+            self.ctx.get_new_latest_blockhash().await.unwrap();
+        }
+        result
+    }
+
+    async fn remove_liquidity(&mut self, amounts: Vec<u64>) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let total: u64 = amounts.iter().sum();
+        let owned = self.model.lp_position(USERS).map(|share| share.deposited);
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::RemoveLiquidity {
+                poll: self.poll,
+                authority: payer.pubkey(),
+                config: solana_contracts::config_pda().0,
+                authority_token_account: self.authority_token_account,
+                pool_vault: self.pool_vault,
+                pool_authority: self.pool_authority,
+                lp_position: owned.map(|_| solana_contracts::lp_position_pda(&self.poll, &payer.pubkey()).0),
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::RemoveLiquidity { amounts }.data(),
+        };
+        if let Err(err) = self.send(&[ix], &[]).await {
+            self.ctx.get_new_latest_blockhash().await.unwrap();
+            return Err(err);
+        }
+        // Beyond its own deposit the authority may only sweep liquidity no
+        // other provider is invested in
+        let owned = owned.unwrap_or(0);
+        assert!(
+            total <= owned || self.model.lp_deposits() == owned,
+            "authority withdrew another provider's liquidity"
+        );
+        if let Some(share) = self.model.lp_position(USERS) {
+            share.deposited -= total.min(owned);
+        }
+        self.model.pool_vault -= total;
+        self.model.liquidity -= total;
+        // Withdrawing liquidity is the one operation that shrinks the product
+        let poll = self.poll().await;
+        self.model.reserve_product = reserve_product(&poll);
+        Ok(())
+    }
+
+    // Pays a provider out of the settled poll and checks they received
+    // their deposit's share of the liquidity still held and the fees it
+    // earned while invested
+    async fn withdraw_lp(&mut self, provider: usize) {
+        let (wallet, token_account) = self.provider(provider);
+        let lp_position = solana_contracts::lp_position_pda(&self.poll, &wallet.pubkey()).0;
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::WithdrawLp {
+                poll: self.poll,
+                lp_position,
+                provider: wallet.pubkey(),
+                provider_token_account: token_account,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                pool_authority: self.pool_authority,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::WithdrawLp {}.data(),
+        };
+        let before = self.balance(token_account).await;
+        let signers: &[&Keypair] = if provider == USERS { &[] } else { &[&wallet] };
+        self.send(&[ix], signers).await.unwrap();
+        let received = self.balance(token_account).await - before;
+
+        let lp_deposits = self.model.lp_deposits();
+        let share = *self.model.lp_position(provider).unwrap();
+        let liquidity = match share.deposited {
+            0 => 0,
+            deposited => (self.model.liquidity as u128 * deposited as u128 / lp_deposits as u128) as u64,
+        };
+        assert_eq!(received, liquidity + share.fees_owed, "provider {provider} paid the wrong share");
+        self.model.pool_vault -= liquidity;
+        self.model.liquidity -= liquidity;
+        self.model.fee_vault -= share.fees_owed;
+        self.model.lp_fees -= share.fees_owed;
+        self.model.lp_positions.retain(|share| share.provider != provider);
+        assert!(self.ctx.banks_client.get_account(lp_position).await.unwrap().is_none());
+    }
+
+    // Pays out every liquidity provider once the poll is settled, then has
+    // the authority sweep whatever liquidity nobody is invested in, taking
+    // it from the reserves in order down to the locked minimum
+    async fn withdraw_all_liquidity(&mut self, seed: u64) {
+        if self.model.lp_fees > 0 {
+            let overdraw = self.model.protocol_fees() + 1;
+            assert!(self.withdraw_fees(Some(overdraw)).await.is_err(), "seed {seed}: fees taken from providers");
+            self.ctx.get_new_latest_blockhash().await.unwrap();
+        }
+        for share in self.model.lp_positions.clone() {
+            self.withdraw_lp(share.provider).await;
+            self.check_invariants(&format!("seed {seed} withdraw_lp")).await;
+        }
+        if self.model.liquidity == 0 {
+            return;
+        }
+
+        let poll = self.poll().await;
+        let mut remaining = poll.liquidity;
+        let amounts = poll
+            .shares
+            .iter()
+            .map(|&reserve| {
+                let amount = (reserve - MINIMUM_LIQUIDITY).min(remaining);
+                remaining -= amount;
+                amount
+            })
+            .collect();
+        assert_eq!(remaining, 0, "seed {seed}: reserves cannot cover the deposited liquidity");
+        self.remove_liquidity(amounts).await.unwrap();
+        self.check_invariants(&format!("seed {seed} remove_liquidity")).await;
+        assert_eq!(self.model.liquidity, 0);
+    }
+
+    async fn set_paused(&mut self, paused: bool) {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SetPaused { config: solana_contracts::config_pda().0, admin: payer.pubkey() }
+                .to_account_metas(None),
+            data: instruction::SetPaused { paused }.data(),
+        };
+        self.send(&[ix], &[]).await.unwrap();
+        self.paused = paused;
+        // Toggling back and forth would otherwise repeat an earlier transaction
+        self.ctx.get_new_latest_blockhash().await.unwrap();
+    }
+
+    async fn set_fee_rounding(&mut self, round_fee_up: bool) {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SetFeeRounding { config: solana_contracts::config_pda().0, admin: payer.pubkey() }
+                .to_account_metas(None),
+            data: instruction::SetFeeRounding { round_fee_up }.data(),
+        };
+        self.send(&[ix], &[]).await.unwrap();
+        self.round_fee_up = round_fee_up;
+    }
+
+    async fn set_refund_fees_on_cancel(&mut self, refund_fees_on_cancel: bool) {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SetRefundFeesOnCancel {
+                config: solana_contracts::config_pda().0,
+                admin: payer.pubkey(),
+            }
+            .to_account_metas(None),
+            data: instruction::SetRefundFeesOnCancel { refund_fees_on_cancel }.data(),
+        };
+        self.send(&[ix], &[]).await.unwrap();
+        self.refund_fees_on_cancel = refund_fees_on_cancel;
+    }
+
+    async fn set_claim_window(&mut self, claim_window: i64) {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SetClaimWindow { config: solana_contracts::config_pda().0, admin: payer.pubkey() }
+                .to_account_metas(None),
+            data: instruction::SetClaimWindow { claim_window }.data(),
+        };
+        self.send(&[ix], &[]).await.unwrap();
+    }
+
+    async fn set_default_fee(&mut self, default_fee_bps: u16) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SetDefaultFee { config: solana_contracts::config_pda().0, admin: payer.pubkey() }
+                .to_account_metas(None),
+            data: instruction::SetDefaultFee { default_fee_bps }.data(),
+        };
+        self.send(&[ix], &[]).await
+    }
+
+    // Changes the poll's network fee, signed by `authority`
+    async fn set_poll_fee(&mut self, authority: &Keypair, fee_bps: u16) -> Result<(), String> {
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SetPollFee {
+                poll: self.poll,
+                authority: authority.pubkey(),
+                config: solana_contracts::config_pda().0,
+            }
+            .to_account_metas(None),
+            data: instruction::SetPollFee { fee_bps }.data(),
+        };
+        self.send(&[ix], &[authority]).await
+    }
+
+    async fn disable_outcome(&mut self, outcome_index: u8) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::DisableOutcome {
+                poll: self.poll,
+                authority: payer.pubkey(),
+                config: solana_contracts::config_pda().0,
+            }
+            .to_account_metas(None),
+            data: instruction::DisableOutcome { outcome_index }.data(),
+        };
+        self.send(&[ix], &[]).await?;
+        // The outcome's reserve leaves the product, which the others keep
+        self.model.reserve_product = reserve_product(&self.poll().await);
+        Ok(())
+    }
+
+    async fn set_max_title_len(&mut self, max_title_len: u16) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SetMaxTitleLen { config: solana_contracts::config_pda().0, admin: payer.pubkey() }
+                .to_account_metas(None),
+            data: instruction::SetMaxTitleLen { max_title_len }.data(),
+        };
+        self.send(&[ix], &[]).await
+    }
+
+    async fn add_allowed_mint(&mut self, mint: Pubkey) {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::UpdateAllowedMints { config: solana_contracts::config_pda().0, admin: payer.pubkey() }
+                .to_account_metas(None),
+            data: instruction::AddAllowedMint { mint }.data(),
+        };
+        self.send(&[ix], &[]).await.unwrap();
+    }
+
+    async fn warp_to(&mut self, unix_timestamp: i64) {
+        let mut clock: Clock = self.ctx.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = unix_timestamp;
+        self.ctx.set_sysvar(&clock);
+    }
+
+    async fn resolve(&mut self, winning_nft: Pubkey) {
+        self.try_resolve(winning_nft).await.unwrap();
+    }
+
+    async fn try_resolve(&mut self, winning_nft: Pubkey) -> Result<(), String> {
+        self.try_resolve_with_proof(winning_nft, None).await
+    }
+
+    async fn try_resolve_with_proof(&mut self, winning_nft: Pubkey, ownership_proof: Option<Pubkey>) -> Result<(), String> {
+        // Betting has to be over before the poll can be resolved
+        self.warp_to(self.closes_at).await;
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ResolvePoll {
+                poll: self.poll,
+                authority: payer.pubkey(),
+                config: solana_contracts::config_pda().0,
+                registry: solana_contracts::registry_pda(&payer.pubkey()).0,
+                parent_poll: None,
+                ownership_proof,
+            }
+            .to_account_metas(None),
+            data: instruction::ResolvePoll { winning_nft }.data(),
+        };
+        self.send(&[ix], &[]).await
+    }
+
+    async fn override_resolution(&mut self, winning_nft: Pubkey) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::OverrideResolution {
+                poll: self.poll,
+                config: solana_contracts::config_pda().0,
+                admin: payer.pubkey(),
+            }
+            .to_account_metas(None),
+            data: instruction::OverrideResolution { winning_nft }.data(),
+        };
+        self.send(&[ix], &[]).await
+    }
+
+    // Permissionless once betting has ended
+    async fn close_poll(&mut self, caller: &Keypair) -> Result<(), String> {
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ClosePoll { poll: self.poll, caller: caller.pubkey() }.to_account_metas(None),
+            data: instruction::ClosePoll {}.data(),
+        };
+        self.send(&[ix], &[caller]).await
+    }
+
+    async fn cancel(&mut self) {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::CancelPoll {
+                poll: self.poll,
+                authority: payer.pubkey(),
+                config: solana_contracts::config_pda().0,
+                registry: solana_contracts::registry_pda(&payer.pubkey()).0,
+            }
+            .to_account_metas(None),
+            data: instruction::CancelPoll { reason_code: 0 }.data(),
+        };
+        self.send(&[ix], &[]).await.unwrap();
+    }
+
+    // Permissionless: any user may cancel a stale poll
+    async fn force_cancel_stale(&mut self, caller: usize) -> Result<(), String> {
+        let wallet = self.users[caller].0.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ForceCancelStale {
+                poll: self.poll,
+                caller: wallet.pubkey(),
+                registry: solana_contracts::registry_pda(&self.ctx.payer.pubkey()).0,
+            }
+            .to_account_metas(None),
+            data: instruction::ForceCancelStale {}.data(),
+        };
+        self.send(&[ix], &[&wallet]).await
+    }
+
+    async fn refund(&mut self, vote: Pubkey, user: usize) -> Result<(), String> {
+        let poll = self.poll().await;
+        let vote_account = self.vote_account(vote).await;
+        let (wallet, token_account) = (self.users[user].0.insecure_clone(), self.users[user].1);
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ClaimRefund {
+                poll: self.poll,
+                vote,
+                user: wallet.pubkey(),
+                user_token_account: token_account,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                config: solana_contracts::config_pda().0,
+                pool_authority: self.pool_authority,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ClaimRefund {}.data(),
+        };
+        self.send(&[ix], &[&wallet]).await?;
+        self.model.pool_vault -= vote_account.stake;
+        // Only canceled polls may return fees, and only what the vault still holds
+        if poll.status == PollStatus::Canceled && self.refund_fees_on_cancel {
+            self.model.fee_vault -= vote_account.protocol_fee.min(self.model.protocol_fees());
+        }
+        Ok(())
+    }
+
+    async fn claim(&mut self, vote: Pubkey, user: usize) -> Result<(), String> {
+        self.claim_to(vote, user, None).await
+    }
+
+    // Claims user's winnings into destination, or their own account if None
+    async fn claim_to(&mut self, vote: Pubkey, user: usize, destination: Option<Pubkey>) -> Result<(), String> {
+        self.claim_part(vote, user, destination, None).await
+    }
+
+    // Claims `amount` of the remaining gross winnings, all of them if None
+    async fn claim_part(
+        &mut self,
+        vote: Pubkey,
+        user: usize,
+        destination: Option<Pubkey>,
+        amount: Option<u64>,
+    ) -> Result<(), String> {
+        let poll = self.poll().await;
+        let vote_account = self.vote_account(vote).await;
+        let (wallet, token_account) = (self.users[user].0.insecure_clone(), self.users[user].1);
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ClaimWinnings {
+                poll: self.poll,
+                vote,
+                user: wallet.pubkey(),
+                user_token_account: token_account,
+                destination,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                pool_authority: self.pool_authority,
+                parent_poll: None,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ClaimWinnings { amount }.data(),
+        };
+        self.send(&[ix], &[&wallet]).await?;
+        let payout = amount.unwrap_or_else(|| payout(&poll, &vote_account));
+        let claim_fee = (payout as u128 * poll.claim_fee_bps as u128 / 10000) as u64;
+        self.model.pool_vault -= payout;
+        self.model.fee_vault += claim_fee;
+        Ok(())
+    }
+
+    // Claims through claim_winnings_batch, listing the position twice: the
+    // repeat must be skipped as already claimed, and a losing position must
+    // be skipped without failing the transaction
+    async fn claim_batch(&mut self, vote: Pubkey, user: usize) {
+        let poll = self.poll().await;
+        let vote_account = self.vote_account(vote).await;
+        let (wallet, token_account) = (self.users[user].0.insecure_clone(), self.users[user].1);
+        let mut accounts = accounts::ClaimWinningsBatch {
+            user: wallet.pubkey(),
+            user_token_account: token_account,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None);
+        for _ in 0..2 {
+            accounts.extend([
+                AccountMeta::new(self.poll, false),
+                AccountMeta::new(vote, false),
+                AccountMeta::new(self.pool_vault, false),
+                AccountMeta::new(self.fee_vault, false),
+                AccountMeta::new_readonly(self.pool_authority, false),
+            ]);
+        }
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts,
+            data: instruction::ClaimWinningsBatch {}.data(),
+        };
+        let before = self.balance(token_account).await;
+        self.send(&[ix], &[&wallet]).await.unwrap();
+        let received = self.balance(token_account).await - before;
+
+        let winner = poll.winning_nft == Some(poll.outcomes[vote_account.voted_for_nft as usize - 1]);
+        let clock: Clock = self.ctx.banks_client.get_sysvar().await.unwrap();
+        if winner && !vote_account.claimed && !poll.dispute_window_open(clock.unix_timestamp) {
+            let payout = payout(&poll, &vote_account);
+            let claim_fee = (payout as u128 * poll.claim_fee_bps as u128 / 10000) as u64;
+            assert_eq!(received, payout - claim_fee, "batch paid the wrong amount");
+            self.model.pool_vault -= payout;
+            self.model.fee_vault += claim_fee;
+        } else {
+            assert_eq!(received, 0, "batch paid out a position it should have skipped");
+        }
+    }
+
+    async fn withdraw_fees(&mut self, amount: Option<u64>) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::WithdrawFees {
+                poll: self.poll,
+                admin: payer.pubkey(),
+                config: solana_contracts::config_pda().0,
+                fee_vault: self.fee_vault,
+                destination: self.authority_token_account,
+                pool_authority: self.pool_authority,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::WithdrawFees { amount }.data(),
+        };
+        self.send(&[ix], &[]).await?;
+        self.model.fee_vault -= amount.unwrap_or(self.model.protocol_fees());
+        Ok(())
+    }
+
+    // The harness's payer is both the admin and the poll authority
+    async fn withdraw_authority_fees(&mut self, amount: Option<u64>) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::WithdrawAuthorityFees {
+                poll: self.poll,
+                authority: payer.pubkey(),
+                authority_fee_vault: self.authority_fee_vault,
+                destination: self.authority_token_account,
+                pool_authority: self.pool_authority,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::WithdrawAuthorityFees { amount }.data(),
+        };
+        self.send(&[ix], &[]).await?;
+        self.model.authority_fee_vault -= amount.unwrap_or(self.model.authority_fee_vault);
+        Ok(())
+    }
+
+    // Collects every fee charged, after checking the vault cannot be overdrawn
+    async fn collect_fees(&mut self, seed: u64) {
+        let overdraw = self.model.protocol_fees() + 1;
+        assert!(self.withdraw_fees(Some(overdraw)).await.is_err(), "seed {seed}: fee vault overdrawn");
+        self.check_invariants(&format!("seed {seed} withdraw_fees overdraw")).await;
+        self.withdraw_fees(None).await.unwrap();
+        self.check_invariants(&format!("seed {seed} withdraw_fees")).await;
+        // Only the rounding dust of the providers' fees may be left
+        assert_eq!(self.model.protocol_fees(), 0);
+        self.withdraw_authority_fees(None).await.unwrap();
+        self.check_invariants(&format!("seed {seed} withdraw_authority_fees")).await;
+        assert_eq!(self.model.authority_fee_vault, 0);
+    }
+
+    // Closes every vote once the poll is settled, reclaiming their rent
+    async fn close_votes(&mut self, seed: u64) {
+        for (vote, user) in self.votes.clone() {
+            self.close_vote(vote, user).await.unwrap();
+            let account = self.ctx.banks_client.get_account(vote).await.unwrap();
+            assert!(account.is_none(), "seed {seed}: vote account left open");
+        }
+    }
+
+    async fn close_vote(&mut self, vote: Pubkey, user: usize) -> Result<(), String> {
+        let wallet = self.users[user].0.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::CloseVote { poll: self.poll, vote, user: wallet.pubkey() }.to_account_metas(None),
+            data: instruction::CloseVote {}.data(),
+        };
+        self.send(&[ix], &[&wallet]).await
+    }
+
+    // Sweeps the unclaimed winnings to the payer, the treasury owner
+    async fn sweep_unclaimed(&mut self) -> Result<(), String> {
+        let poll = self.poll().await;
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::SweepUnclaimed {
+                poll: self.poll,
+                config: solana_contracts::config_pda().0,
+                admin: payer.pubkey(),
+                pool_vault: self.pool_vault,
+                treasury: self.authority_token_account,
+                pool_authority: self.pool_authority,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::SweepUnclaimed {}.data(),
+        };
+        self.send(&[ix], &[]).await?;
+        self.model.pool_vault -= poll.vault_liability;
+        Ok(())
+    }
+
+    // Sweeps what is left in the vaults to the payer, which is both the
+    // treasury owner and the authority, and closes them and the poll
+    async fn close_poll_accounts(&mut self) -> Result<(), String> {
+        let payer = self.ctx.payer.insecure_clone();
+        let ix = Instruction {
+            program_id: solana_contracts::ID,
+            accounts: accounts::ClosePollAccounts {
+                poll: self.poll,
+                caller: payer.pubkey(),
+                authority: payer.pubkey(),
+                config: solana_contracts::config_pda().0,
+                pool_vault: self.pool_vault,
+                fee_vault: self.fee_vault,
+                authority_fee_vault: self.authority_fee_vault,
+                treasury: self.authority_token_account,
+                authority_token_account: self.authority_token_account,
+                pool_authority: self.pool_authority,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ClosePollAccounts {}.data(),
+        };
+        self.send(&[ix], &[]).await?;
+        self.model.pool_vault = 0;
+        self.model.fee_vault = 0;
+        self.model.lp_fees = 0;
+        self.model.authority_fee_vault = 0;
+        Ok(())
+    }
+
+    // Gross payouts still owed to unclaimed winning votes
+    async fn outstanding_winnings(&mut self) -> u64 {
+        let poll = self.poll().await;
+        let mut owed = 0;
+        for (vote, _) in self.votes.clone() {
+            let vote = self.vote_account(vote).await;
+            let winning_index = poll.outcome_index(&poll.winning_nft.unwrap()).unwrap();
+            if vote.voted_for_nft as usize == winning_index + 1 && !vote.claimed {
+                owed += payout(&poll, &vote);
+            }
+        }
+        owed
+    }
+
+    // Votes a resolved poll still owes something: the unclaimed winners and
+    // unrefunded votes on disabled outcomes, or every unrefunded vote when
+    // nobody won
+    async fn unclaimed_positions(&mut self) -> u32 {
+        let poll = self.poll().await;
+        let winning_index = poll.outcome_index(&poll.winning_nft.unwrap()).unwrap();
+        let mut unclaimed = 0;
+        for (vote, _) in self.votes.clone() {
+            let vote = self.vote_account(vote).await;
+            let index = vote.voted_for_nft as usize - 1;
+            let won = poll.winning_side_shares == 0 || index == winning_index || poll.outcome_disabled[index];
+            if won && !vote.claimed {
+                unclaimed += 1;
+            }
+        }
+        unclaimed
+    }
+
+    async fn check_invariants(&mut self, step: &str) {
+        let mut total = 0;
+        let mut token_accounts: Vec<Pubkey> = self.users.iter().map(|(_, account)| *account).collect();
+        token_accounts.push(self.authority_token_account);
+        for token_account in token_accounts {
+            total += self.balance(token_account).await;
+        }
+        let pool_vault = self.balance(self.pool_vault).await;
+        let fee_vault = self.balance(self.fee_vault).await;
+        let authority_fee_vault = self.balance(self.authority_fee_vault).await;
+        total += pool_vault + fee_vault + authority_fee_vault;
+        let supply = USER_BALANCE * (USERS as u64 + 1) + self.model.wrapped_sol;
+        assert_eq!(total, supply, "{step}: tokens not conserved");
+        assert_eq!(pool_vault, self.model.pool_vault, "{step}: pool vault drifted");
+        assert_eq!(fee_vault, self.model.fee_vault, "{step}: fee vault drifted");
+        assert_eq!(authority_fee_vault, self.model.authority_fee_vault, "{step}: authority fee vault drifted");
+
+        let poll = self.poll().await;
+        // Every token in the pool vault is either owed to a position or
+        // belongs to the liquidity
+        assert_eq!(
+            poll.vault_liability + poll.liquidity,
+            pool_vault,
+            "{step}: vault liability out of step with the vault"
+        );
+        assert_eq!(poll.total_volume, self.model.volume, "{step}: volume drifted");
+        assert_eq!(poll.vote_count, self.model.vote_count, "{step}: vote count drifted");
+        assert_eq!(poll.liquidity, self.model.liquidity, "{step}: liquidity drifted");
+        assert_eq!(poll.lp_deposits, self.model.lp_deposits(), "{step}: provider deposits drifted");
+        assert_eq!(poll.lp_fees, self.model.lp_fees, "{step}: provider fees drifted");
+        if poll.status == PollStatus::Active {
+            assert!(poll.live_outcomes().all(|i| poll.shares[i] > 0), "{step}: reserve emptied");
+        }
+        let product = reserve_product(&poll);
+        assert!(product >= self.model.reserve_product, "{step}: reserve product decreased");
+        self.model.reserve_product = product;
+
+        let clock: Clock = self.ctx.banks_client.get_sysvar().await.unwrap();
+        if poll.status == PollStatus::Resolved {
+            // Expired claims are no longer owed, whether swept yet or not
+            if !poll.claims_expired(clock.unix_timestamp) {
+                let owed = self.outstanding_winnings().await;
+                assert!(owed <= pool_vault, "{step}: vault cannot cover {owed} owed to winners");
+                let unclaimed = self.unclaimed_positions().await;
+                assert_eq!(poll.unclaimed_winners, unclaimed, "{step}: unclaimed positions miscounted");
+            }
+        } else {
+            let backing = poll.total_pool().unwrap() + poll.liquidity;
+            assert_eq!(backing, pool_vault, "{step}: stakes and liquidity not backed");
+        }
+    }
+}
+
+// Gross payout still owed to a winning vote: its share of the stakes on
+// live outcomes less its partial claims, or what is left of them if it is
+// the last winner unpaid
+fn payout(poll: &Poll, vote: &Vote) -> u64 {
+    let refunds_owed: u64 = (0..poll.outcomes.len())
+        .filter(|&i| poll.outcome_disabled[i])
+        .map(|i| poll.outcome_pools[i])
+        .sum();
+    let refunds_pending: u32 = (0..poll.outcomes.len())
+        .filter(|&i| poll.outcome_disabled[i])
+        .map(|i| poll.positions[i])
+        .sum();
+    if poll.unclaimed_winners - refunds_pending == 1 {
+        return poll.vault_liability - refunds_owed;
+    }
+    let prize_pool = poll.total_pool().unwrap() - refunds_owed;
+    let shares = vote.claim_shares().unwrap() as u128;
+    (shares * prize_pool as u128 / poll.winning_side_shares as u128) as u64 - vote.claimed_amount
+}
+
+// Product of the reserves still traded; at most three reserves of a few
+// billion each, so this fits in a u128
+fn reserve_product(poll: &Poll) -> u128 {
+    poll.live_outcomes().map(|i| poll.shares[i] as u128).product()
+}
+
+async fn run_case(seed: u64) {
+    let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1);
+    let outcome_count = rng.range(2, 3) as usize;
+    // Bonus shares must be paid out of the same pool as bought ones
+    let fee_bps = rng.range(0, 1000) as u16;
+    let options = PollOptions {
+        bonus_enabled: rng.one_in(2),
+        authority_fee_bps: rng.range(0, fee_bps as u64) as u16,
+        ..PollOptions::default()
+    };
+    let mut harness = Harness::with_options(fee_bps, rng.range(0, 1000) as u16, outcome_count, options).await;
+    if rng.one_in(2) {
+        harness.set_fee_rounding(true).await;
+    }
+    harness.check_invariants("create_poll").await;
+    // Every user trusts the next one to bet on their behalf
+    for user in 0..USERS {
+        harness.approve_delegate(user, (user + 1) % USERS).await.unwrap();
+    }
+
+    for step in 0..STEPS {
+        let user = rng.range(0, USERS as u64 - 1) as usize;
+        if rng.one_in(10) {
+            // Pausing blocks every trade until it is lifted, and a poll left
+            // paused must still settle
+            let paused = !harness.paused;
+            harness.set_paused(paused).await;
+            harness.check_invariants(&format!("seed {seed} step {step} set_paused")).await;
+        } else if rng.one_in(5) {
+            // The authority is one of the possible providers
+            let provider = rng.range(0, USERS as u64) as usize;
+            // Mostly in proportion to the reserves, sometimes skewed
+            let reserves = harness.poll().await.shares;
+            let base = rng.amount();
+            let amounts = if rng.one_in(4) {
+                (0..outcome_count).map(|_| rng.amount()).collect()
+            } else {
+                reserves.iter().map(|&reserve| (base as u128 * reserve as u128 / reserves[0] as u128) as u64).collect()
+            };
+            let _ = harness.add_liquidity(provider, amounts).await;
+            harness.check_invariants(&format!("seed {seed} step {step} add_liquidity")).await;
+        } else if rng.one_in(8) {
+            // Withdrawals beyond the deposited liquidity must be rejected
+            let cap = harness.model.liquidity / outcome_count as u64 + 1;
+            let amounts = (0..outcome_count).map(|_| rng.range(0, cap)).collect();
+            let _ = harness.remove_liquidity(amounts).await;
+            harness.check_invariants(&format!("seed {seed} step {step} remove_liquidity")).await;
+        } else if rng.one_in(12) {
+            // Hedges between two distinct outcomes of the poll
+            let first = rng.range(1, outcome_count as u64) as u8;
+            let second = first % outcome_count as u8 + 1;
+            let legs = ((first, rng.amount()), (second, rng.amount()));
+            let _ = harness.vote_split(user, legs.0, legs.1).await;
+            harness.check_invariants(&format!("seed {seed} step {step} vote_split")).await;
+        } else if !harness.votes.is_empty() && rng.one_in(6) {
+            let position = rng.range(0, harness.votes.len() as u64 - 1) as usize;
+            harness.sell(position).await;
+            harness.check_invariants(&format!("seed {seed} step {step} sell_position")).await;
+        } else {
+            let nft_choice = rng.range(1, outcome_count as u64) as u8;
+            // Some bets are paid by another user on the voter's behalf
+            let funder = if rng.one_in(8) { rng.range(0, USERS as u64 - 1) as usize } else { user };
+            harness.vote_for(funder, user, nft_choice, rng.amount()).await;
+            harness.check_invariants(&format!("seed {seed} step {step} vote")).await;
+        }
+    }
+
+    if rng.one_in(4) {
+        harness.cancel().await;
+        harness.check_invariants(&format!("seed {seed} cancel_poll")).await;
+        if rng.one_in(2) {
+            harness.set_refund_fees_on_cancel(true).await;
+        }
+
+        for (vote, user) in harness.votes.clone() {
+            harness.refund(vote, user).await.unwrap();
+            harness.check_invariants(&format!("seed {seed} claim_refund")).await;
+        }
+        harness.withdraw_all_liquidity(seed).await;
+        assert_eq!(harness.model.pool_vault, 0, "seed {seed}: stakes left in the vault after all refunds");
+        harness.collect_fees(seed).await;
+        harness.close_votes(seed).await;
+        return;
+    }
+
+    let winning_nft = harness.outcomes[rng.range(0, outcome_count as u64 - 1) as usize];
+    harness.resolve(winning_nft).await;
+    harness.check_invariants(&format!("seed {seed} resolve_poll")).await;
+    if !harness.votes.is_empty() {
+        let err = harness.close_poll_accounts().await.unwrap_err();
+        let code = format!("{:#x}", 6000 + AmmError::UnclaimedWinnings as u32);
+        assert!(err.contains(&code), "seed {seed}: closed an unsettled poll: {err}");
+        harness.ctx.get_new_latest_blockhash().await.unwrap();
+    }
+
+    // Resolving to an outcome nobody holds shares in refunds every stake
+    let no_winners = harness.poll().await.winning_side_shares == 0;
+    let batch_claims = rng.one_in(2);
+    for (vote, user) in harness.votes.clone() {
+        if no_winners {
+            harness.refund(vote, user).await.unwrap();
+            harness.check_invariants(&format!("seed {seed} claim_refund")).await;
+        } else if batch_claims {
+            harness.claim_batch(vote, user).await;
+            harness.check_invariants(&format!("seed {seed} claim_winnings_batch")).await;
+        } else {
+            // Losing votes are rejected, which must leave balances unchanged
+            // Some winners direct the payout to another user's account
+            let destination = rng.one_in(8).then(|| harness.users[rng.range(0, USERS as u64 - 1) as usize].1);
+            // and some take their winnings in two parts
+            let owed = payout(&harness.poll().await, &harness.vote_account(vote).await);
+            if owed > 1 && rng.one_in(6) {
+                let _ = harness.claim_part(vote, user, destination, Some(owed / 2)).await;
+                harness.check_invariants(&format!("seed {seed} partial claim_winnings")).await;
+            }
+            let _ = harness.claim_to(vote, user, destination).await;
+            harness.check_invariants(&format!("seed {seed} claim_winnings")).await;
+        }
+    }
+    assert_eq!(harness.outstanding_winnings().await, 0, "seed {seed}: winnings left unclaimed");
+
+    // Once every winner has claimed and the liquidity is withdrawn, no
+    // stakes remain: the last winner takes the rounding dust
+    harness.withdraw_all_liquidity(seed).await;
+    let leftover_stakes = harness.model.pool_vault;
+    assert_eq!(leftover_stakes, 0, "seed {seed}: {leftover_stakes} left in the vault after all claims");
+    harness.collect_fees(seed).await;
+    harness.close_votes(seed).await;
+
+    // Fully settled, the poll's accounts can go; the dust, including what
+    // rounding left of the providers' fees, moves out of the vaults but
+    // stays in the supply
+    let dust = harness.balance(harness.pool_vault).await + harness.balance(harness.fee_vault).await;
+    let before = harness.balance(harness.authority_token_account).await;
+    harness.close_poll_accounts().await.unwrap();
+    assert_eq!(harness.balance(harness.authority_token_account).await, before + dust);
+    for account in [harness.poll, harness.pool_vault, harness.fee_vault, harness.authority_fee_vault] {
+        let account = harness.ctx.banks_client.get_account(account).await.unwrap();
+        assert!(account.is_none(), "seed {seed}: poll account left open");
+    }
+}
+
+#[tokio::test]
+async fn accounting_invariants_hold_for_random_sequences() {
+    for seed in 0..CASES {
+        run_case(seed).await;
+    }
+}
+
+#[tokio::test]
+async fn only_the_upgrade_authority_initializes_the_config() {
+    let mut ctx = ProgramTest::new("solana_contracts", solana_contracts::ID, processor!(entry)).start_with_context().await;
+    let payer = ctx.payer.insecure_clone();
+    ctx.set_account(&program_data_address(), &program_data_account(&payer.pubkey()).into());
+
+    // Anyone else racing the deployer to the config is turned away
+    let squatter = Keypair::new();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(&payer.pubkey(), &squatter.pubkey(), 1_000_000_000),
+            initialize_config_ix(&squatter.pubkey()),
+        ],
+        Some(&payer.pubkey()),
+        &[&payer, &squatter],
+        ctx.last_blockhash,
+    );
+    let err = ctx.banks_client.process_transaction(tx).await.unwrap_err().to_string();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::Unauthorized as u32)), "{err}");
+
+    let tx = Transaction::new_signed_with_payer(
+        &[initialize_config_ix(&payer.pubkey())],
+        Some(&payer.pubkey()),
+        &[&payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+    let account = ctx.banks_client.get_account(solana_contracts::config_pda().0).await.unwrap().unwrap();
+    let config = Config::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(config.admin, payer.pubkey());
+}
+
+#[tokio::test]
+async fn votes_record_when_they_were_cast() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    let start = harness.closes_at - 600;
+
+    harness.warp_to(start).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.warp_to(start + 120).await;
+    harness.vote(1, 2, 10_000_000).await;
+
+    let first = harness.vote_account(harness.votes[0].0).await;
+    let second = harness.vote_account(harness.votes[1].0).await;
+    assert_eq!(first.created_at, start);
+    assert_eq!(second.created_at, start + 120);
+}
+
+#[tokio::test]
+async fn sol_bets_are_paid_back_as_lamports() {
+    let mut harness = Harness::with_options(300, 100, 2, PollOptions { native: true, ..Default::default() }).await;
+    assert_eq!(harness.poll().await.mint_decimals, 9);
+    harness.vote_sol(0, 1, 200_000_000).await;
+    harness.vote_sol(1, 2, 100_000_000).await;
+    harness.check_invariants("vote_sol").await;
+
+    let winning_nft = harness.outcomes[0];
+    harness.resolve(winning_nft).await;
+    let (winner, loser) = (harness.votes[0].0, harness.votes[1].0);
+    assert!(harness.claim_sol(loser, 1).await.is_err(), "losing vote was paid");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    let poll = harness.poll().await;
+    let payout = payout(&poll, &harness.vote_account(winner).await);
+    let received = harness.claim_sol(winner, 0).await.unwrap();
+    assert_eq!(received, payout - payout / 100, "winner not paid the unwrapped payout");
+    harness.check_invariants("claim_winnings_sol").await;
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    assert!(harness.claim_sol(winner, 0).await.is_err(), "winnings claimed twice");
+}
+
+#[tokio::test]
+async fn winnings_stay_locked_during_the_dispute_window() {
+    let options = PollOptions { dispute_period: 3600, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    let resolved_at = harness.poll().await.resolved_at;
+    assert_eq!(resolved_at, harness.closes_at);
+
+    let (vote, user) = harness.votes[0];
+    let err = harness.claim(vote, user).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::DisputeWindowOpen as u32)), "{err}");
+    harness.claim_batch(vote, user).await;
+
+    harness.warp_to(resolved_at + 3600).await;
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.claim(vote, user).await.unwrap();
+    harness.check_invariants("claim after the dispute window").await;
+}
+
+#[tokio::test]
+async fn admin_overrides_the_winner_within_the_dispute_window() {
+    let options = PollOptions { dispute_period: 3600, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 20_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    harness.override_resolution(harness.outcomes[1]).await.unwrap();
+    let poll = harness.poll().await;
+    assert_eq!(poll.winning_nft, Some(harness.outcomes[1]));
+    assert_eq!(poll.winning_side_shares, poll.vote_shares[1]);
+    // Indexers following the market state learn about the new winner too
+    let events = harness.events::<MarketStateEvent>();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].poll, events[0].winning_nft), (harness.poll, harness.outcomes[1]));
+    assert_eq!(events[0].reserves, poll.shares);
+
+    harness.warp_to(poll.resolved_at + 3600).await;
+    let err = harness.override_resolution(harness.outcomes[0]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::DisputeWindowClosed as u32)), "{err}");
+
+    let (first, second) = (harness.votes[0], harness.votes[1]);
+    assert!(harness.claim(first.0, first.1).await.is_err(), "overridden winner was paid");
+    harness.claim(second.0, second.1).await.unwrap();
+    harness.check_invariants("claim after override").await;
+    assert_eq!(harness.outstanding_winnings().await, 0);
+}
+
+#[tokio::test]
+async fn losing_votes_stay_open_during_the_dispute_window() {
+    let options = PollOptions { dispute_period: 3600, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 20_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    // Closing the losing vote now would forfeit it if the winner is overridden
+    let (loser, user) = harness.votes[1];
+    let err = harness.close_vote(loser, user).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::DisputeWindowOpen as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.override_resolution(harness.outcomes[1]).await.unwrap();
+
+    let resolved_at = harness.poll().await.resolved_at;
+    harness.warp_to(resolved_at + 3600).await;
+    harness.claim(loser, user).await.unwrap();
+    harness.close_vote(loser, user).await.unwrap();
+    let (first, first_user) = harness.votes[0];
+    harness.close_vote(first, first_user).await.unwrap();
+    harness.votes.clear();
+    harness.check_invariants("close after the dispute window").await;
+}
+
+#[tokio::test]
+async fn lopsided_pools_pay_the_last_winner_in_full() {
+    let mut harness = Harness::new(300, 200, 2).await;
+    // Many small and odd-sized bets on the favourite against one large bet
+    // on the underdog, so every payout rounds
+    for (user, amount) in [(0, 3_333_333), (1, 7_777_777), (2, 101), (3, 12_345_679)] {
+        for _ in 0..3 {
+            harness.vote(user, 1, amount).await;
+            harness.ctx.get_new_latest_blockhash().await.unwrap();
+        }
+    }
+    harness.vote(4, 2, 400_000_000).await;
+    harness.check_invariants("lopsided votes").await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    let winners: Vec<_> = harness.votes.iter().copied().filter(|&(_, user)| user != 4).collect();
+    for (vote, user) in winners {
+        let poll = harness.poll().await;
+        let expected = payout(&poll, &harness.vote_account(vote).await);
+        let before = harness.balance(harness.users[user].1).await;
+        harness.claim(vote, user).await.unwrap();
+        let received = harness.balance(harness.users[user].1).await - before;
+        assert_eq!(received, expected - expected * 200 / 10000, "winner {user} was shorted");
+        harness.check_invariants("lopsided claim").await;
+    }
+    // The last winner took the rounding dust of the others
+    assert_eq!(harness.poll().await.vault_liability, 0);
+}
+
+#[tokio::test]
+async fn liquidity_providers_split_fees_by_deposit() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.add_liquidity(0, vec![10_000_000, 10_000_000]).await.unwrap();
+    harness.add_liquidity(1, vec![30_000_000, 30_000_000]).await.unwrap();
+    harness.vote(2, 1, 50_000_000).await;
+    harness.vote(3, 2, 70_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    // The protocol takes its part right away; the providers' part stays
+    let fees = harness.model.fee_vault;
+    let lp_fees = fees * LP_FEE_SHARE_BPS as u64 / 10000;
+    assert_eq!(harness.model.lp_fees, lp_fees);
+    harness.withdraw_fees(None).await.unwrap();
+    assert_eq!(harness.balance(harness.fee_vault).await, lp_fees);
+    let before = [harness.balance(harness.users[0].1).await, harness.balance(harness.users[1].1).await];
+    harness.withdraw_lp(0).await;
+    harness.withdraw_lp(1).await;
+    let small = harness.balance(harness.users[0].1).await - before[0];
+    let large = harness.balance(harness.users[1].1).await - before[1];
+    assert!(small.abs_diff(20_000_000 + lp_fees / 4) <= 1, "{small}");
+    assert!(large.abs_diff(60_000_000 + lp_fees * 3 / 4) <= 1, "{large}");
+    harness.check_invariants("providers withdrawn").await;
+    assert_eq!(harness.poll().await.lp_deposits, 0);
+}
+
+#[tokio::test]
+async fn late_deposits_earn_no_fees_charged_before_them() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.add_liquidity(0, vec![10_000_000, 10_000_000]).await.unwrap();
+    harness.vote(2, 1, 50_000_000).await;
+    harness.vote(3, 2, 70_000_000).await;
+    // A dust deposit right before the close, after every fee was charged
+    let poll = harness.poll().await;
+    let dust: Vec<u64> = poll.shares.iter().map(|&reserve| reserve / 100_000).collect();
+    harness.add_liquidity(1, dust.clone()).await.unwrap();
+    harness.resolve(harness.outcomes[0]).await;
+
+    // The dust provider holds up neither the protocol nor the other provider
+    harness.withdraw_fees(None).await.unwrap();
+    let lp_fees = harness.model.lp_fees;
+    let before = [harness.balance(harness.users[0].1).await, harness.balance(harness.users[1].1).await];
+    harness.withdraw_lp(1).await;
+    harness.withdraw_lp(0).await;
+    let late = harness.balance(harness.users[1].1).await - before[1];
+    let early = harness.balance(harness.users[0].1).await - before[0];
+    assert!(late <= dust.iter().sum::<u64>(), "late deposit earned {late}");
+    assert!(early + 1 >= 20_000_000 + lp_fees, "early deposit earned {early}");
+    harness.check_invariants("providers withdrawn").await;
+}
+
+#[tokio::test]
+async fn early_bets_earn_more_bonus_shares() {
+    let options = PollOptions { bonus_enabled: true, ..PollOptions::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    // Right at creation the whole window is left; a tenth of it near the close
+    harness.vote(0, 1, 10_000_000).await;
+    harness.warp_to(harness.closes_at - 360).await;
+    harness.vote(1, 1, 10_000_000).await;
+
+    let early = harness.vote_account(harness.votes[0].0).await;
+    let late = harness.vote_account(harness.votes[1].0).await;
+    let max_bps = solana_contracts::EARLY_BIRD_BONUS_BPS as u64;
+    assert_eq!(early.bonus, early.amount * max_bps / 10000);
+    assert_eq!(late.bonus, late.amount * max_bps / 100000);
+    assert!(early.claim_shares().unwrap() > late.claim_shares().unwrap());
+    let poll = harness.poll().await;
+    assert_eq!(poll.vote_shares[0], early.claim_shares().unwrap() + late.claim_shares().unwrap());
+
+    // The bonus only changes how the winners split the pool
+    harness.resolve(harness.outcomes[0]).await;
+    harness.claim(harness.votes[0].0, 0).await.unwrap();
+    harness.claim(harness.votes[1].0, 1).await.unwrap();
+    harness.check_invariants("bonus claims").await;
+    assert!(harness.poll().await.vault_liability < 2);
+}
+
+#[tokio::test]
+async fn resolving_to_an_unbacked_outcome_refunds_every_stake() {
+    let mut harness = Harness::new(300, 0, 3).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 25_000_000).await;
+    // A refund is only owed once it is clear there are no winners
+    assert!(harness.refund(harness.votes[0].0, 0).await.is_err());
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.resolve(harness.outcomes[2]).await;
+    assert_eq!(harness.poll().await.winning_side_shares, 0);
+
+    for (vote, user) in harness.votes.clone() {
+        let stake = harness.vote_account(vote).await.stake;
+        let before = harness.balance(harness.users[user].1).await;
+        harness.refund(vote, user).await.unwrap();
+        assert_eq!(harness.balance(harness.users[user].1).await - before, stake);
+        harness.check_invariants("unbacked refund").await;
+    }
+    assert_eq!(harness.poll().await.vault_liability, 0);
+    harness.close_votes(0).await;
+}
+
+#[tokio::test]
+async fn canceled_polls_refund_fees_only_when_configured() {
+    for refund_fees_on_cancel in [false, true] {
+        let options = PollOptions { authority_fee_bps: 100, ..Default::default() };
+        let mut harness = Harness::with_options(300, 0, 2, options).await;
+        harness.vote(0, 1, 10_000_000).await;
+        harness.vote(1, 2, 20_000_000).await;
+        harness.cancel().await;
+        harness.set_refund_fees_on_cancel(refund_fees_on_cancel).await;
+
+        for (vote, user) in harness.votes.clone() {
+            let vote_account = harness.vote_account(vote).await;
+            // 2% of each bet went to the protocol and 1% to the authority
+            assert_eq!(vote_account.protocol_fee, (vote_account.value - vote_account.stake) * 2 / 3);
+            let before = harness.balance(harness.users[user].1).await;
+            harness.refund(vote, user).await.unwrap();
+            let fee_refund = if refund_fees_on_cancel { vote_account.protocol_fee } else { 0 };
+            assert_eq!(harness.balance(harness.users[user].1).await - before, vote_account.stake + fee_refund);
+            assert_eq!(harness.events::<RefundClaimed>()[0].fee_refund, fee_refund);
+            harness.check_invariants("canceled refund").await;
+        }
+        // Left in the fee vault for withdraw_fees unless it went back to the voters
+        let fee_vault = harness.balance(harness.fee_vault).await;
+        assert_eq!(fee_vault, if refund_fees_on_cancel { 0 } else { 600_000 });
+        assert_eq!(harness.balance(harness.authority_fee_vault).await, 300_000);
+        harness.collect_fees(0).await;
+    }
+}
+
+#[tokio::test]
+async fn claims_only_pay_out_of_the_poll_vault() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    // A richer account of the same mint, even one owned by the pool
+    // authority, is not the poll's vault PDA
+    let pool_vault = harness.pool_vault;
+    let decoy = harness.create_token_account(&harness.pool_authority.clone()).await;
+    harness.fund(decoy).await;
+    harness.pool_vault = decoy;
+    let err = harness.claim(harness.votes[0].0, 0).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", ErrorCode::ConstraintSeeds as u32)), "{err}");
+
+    harness.pool_vault = pool_vault;
+    harness.claim(harness.votes[0].0, 0).await.unwrap();
+    assert_eq!(harness.balance(decoy).await, USER_BALANCE);
+}
+
+#[tokio::test]
+async fn network_fees_split_between_protocol_and_authority() {
+    let options = PollOptions { authority_fee_bps: 100, ..PollOptions::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.check_invariants("split vote").await;
+    // 3% in all: 1% to the authority, the other 2% to the protocol
+    assert_eq!(harness.balance(harness.authority_fee_vault).await, 100_000);
+    assert_eq!(harness.balance(harness.fee_vault).await, 200_000);
+
+    let before = harness.balance(harness.authority_token_account).await;
+    harness.withdraw_authority_fees(None).await.unwrap();
+    assert_eq!(harness.balance(harness.authority_token_account).await - before, 100_000);
+    harness.check_invariants("authority fees withdrawn").await;
+}
+
+#[tokio::test]
+async fn fully_claimed_polls_return_their_rent() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    let accounts = [harness.poll, harness.pool_vault, harness.fee_vault, harness.authority_fee_vault];
+    let mut rent = 0;
+    for account in accounts {
+        rent += harness.lamports(account).await;
+    }
+    // The winner has not claimed yet
+    let err = harness.close_poll_accounts().await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::UnclaimedWinnings as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    harness.claim(harness.votes[0].0, 0).await.unwrap();
+    harness.close_votes(0).await;
+    let authority = harness.ctx.payer.pubkey();
+    let before = harness.lamports(authority).await;
+    harness.close_poll_accounts().await.unwrap();
+    // The authority also paid the transaction's single signature fee
+    assert_eq!(harness.lamports(authority).await, before + rent - 5000);
+    for account in accounts {
+        assert!(harness.ctx.banks_client.get_account(account).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn votes_still_open_close_after_their_poll() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 10_000_000).await;
+    harness.vote(2, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    harness.claim(harness.votes[0].0, 0).await.unwrap();
+    harness.claim(harness.votes[2].0, 2).await.unwrap();
+    harness.close_poll_accounts().await.unwrap();
+
+    // The loser and a paid winner left their votes open; the rent is still theirs
+    for (vote, user) in [harness.votes[1], harness.votes[2]] {
+        let rent = harness.lamports(vote).await;
+        let wallet = harness.users[user].0.pubkey();
+        let before = harness.lamports(wallet).await;
+        harness.close_vote(vote, user).await.unwrap();
+        assert!(harness.ctx.banks_client.get_account(vote).await.unwrap().is_none());
+        assert_eq!(harness.lamports(wallet).await, before + rent);
+    }
+}
+
+#[tokio::test]
+async fn unclaimed_winners_reach_zero_after_the_last_winner_claims() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 1, 20_000_000).await;
+    harness.vote(2, 2, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    assert_eq!(harness.poll().await.unclaimed_winners, 2);
+
+    // A losing vote has nothing to claim and does not count
+    assert!(harness.claim(harness.votes[2].0, 2).await.is_err());
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    assert_eq!(harness.poll().await.unclaimed_winners, 2);
+
+    harness.claim(harness.votes[0].0, 0).await.unwrap();
+    assert_eq!(harness.poll().await.unclaimed_winners, 1);
+    let err = harness.close_poll_accounts().await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::UnclaimedWinnings as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    harness.claim(harness.votes[1].0, 1).await.unwrap();
+    assert_eq!(harness.poll().await.unclaimed_winners, 0);
+    harness.check_invariants("all winners claimed").await;
+}
+
+#[tokio::test]
+async fn polls_only_use_allowed_mints_once_a_list_is_set() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    // The harness's poll was created while every mint was allowed
+    harness.add_allowed_mint(Pubkey::new_unique()).await;
+    let err = harness.create_poll(&Keypair::new(), 300, 0, &PollOptions::default()).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::MintNotAllowed as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    harness.add_allowed_mint(harness.mint).await;
+    harness.create_poll(&Keypair::new(), 300, 0, &PollOptions::default()).await.unwrap();
+}
+
+#[tokio::test]
+async fn zero_bets_are_rejected_before_any_transfer() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 0).await;
+    assert!(harness.votes.is_empty(), "a zero bet was accepted");
+    let vote = solana_contracts::vote_pda(&harness.poll, &harness.users[0].0.pubkey()).0;
+    assert!(harness.ctx.banks_client.get_account(vote).await.unwrap().is_none());
+    harness.check_invariants("zero bet").await;
+}
+
+#[tokio::test]
+async fn bets_can_be_funded_for_another_account() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.approve_delegate(1, 0).await.unwrap();
+    let funder_before = harness.balance(harness.users[0].1).await;
+    let beneficiary_before = harness.balance(harness.users[1].1).await;
+    harness.vote_for(0, 1, 1, 10_000_000).await;
+    harness.vote(2, 2, 10_000_000).await;
+    harness.check_invariants("funded bet").await;
+    assert_eq!(harness.balance(harness.users[0].1).await, funder_before - 10_000_000);
+
+    let (vote, owner) = harness.votes[0];
+    assert_eq!(owner, 1);
+    assert_eq!(harness.vote_account(vote).await.user, harness.users[1].0.pubkey());
+    harness.resolve(harness.outcomes[0]).await;
+    // Only the beneficiary can claim the position
+    assert!(harness.claim(vote, 0).await.is_err());
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.claim(vote, 1).await.unwrap();
+    harness.check_invariants("beneficiary claimed").await;
+    assert!(harness.balance(harness.users[1].1).await > beneficiary_before);
+}
+
+#[tokio::test]
+async fn only_approved_delegates_credit_a_position() {
+    let mut harness = Harness::with_options(300, 0, 2, PollOptions { min_vote_interval: 600, ..Default::default() }).await;
+    let code = format!("{:#x}", 6000 + AmmError::NotDelegated as u32);
+    // Unasked, a credit would fix user1's side and start their rate limit
+    let err = harness.try_vote_for(0, 1, 1, 1_000_000).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    harness.vote(1, 2, 10_000_000).await;
+    assert_eq!(harness.votes.len(), 1);
+    harness.check_invariants("credit refused").await;
+
+    // An approved delegate adds to the position; a revoked one may not
+    harness.approve_delegate(1, 0).await.unwrap();
+    harness.warp_to(harness.closes_at - 3000).await;
+    harness.try_vote_for(0, 1, 2, 1_000_000).await.unwrap();
+    harness.revoke_delegate(1, 0).await.unwrap();
+    harness.warp_to(harness.closes_at - 2000).await;
+    let err = harness.try_vote_for(0, 1, 2, 1_000_000).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    let vote = harness.vote_account(harness.votes[0].0).await;
+    assert_eq!((vote.user, vote.value), (harness.users[1].0.pubkey(), 11_000_000));
+    harness.check_invariants("delegated credit").await;
+}
+
+#[tokio::test]
+async fn anyone_cancels_a_poll_left_unresolved_past_its_deadline() {
+    let options = PollOptions { resolution_grace_period: 600, ..PollOptions::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 10_000_000).await;
+    assert_eq!(harness.poll().await.resolution_deadline, harness.closes_at + 600);
+
+    // Betting has ended but the resolver is still within the grace period
+    harness.warp_to(harness.closes_at + 600).await;
+    let err = harness.force_cancel_stale(2).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::PollNotStale as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    harness.warp_to(harness.closes_at + 601).await;
+    harness.force_cancel_stale(2).await.unwrap();
+    assert!(harness.poll().await.status == PollStatus::Canceled);
+    for (vote, user) in harness.votes.clone() {
+        harness.refund(vote, user).await.unwrap();
+        harness.check_invariants("stale refund").await;
+    }
+}
+
+#[tokio::test]
+async fn settled_polls_cannot_be_resolved_again() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    let err = harness.try_resolve(harness.outcomes[1]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::PollAlreadyResolved as u32)), "{err}");
+    assert_eq!(harness.poll().await.winning_nft, Some(harness.outcomes[0]));
+
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.cancel().await;
+    let err = harness.try_resolve(harness.outcomes[0]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::PollCanceled as u32)), "{err}");
+}
+
+#[tokio::test]
+async fn fee_rounding_follows_the_config() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    // 3% of 1_000_001 is 30_000.03
+    harness.vote(0, 1, 1_000_001).await;
+    assert_eq!(harness.balance(harness.fee_vault).await, 30_000);
+
+    harness.set_fee_rounding(true).await;
+    harness.vote(1, 1, 1_000_001).await;
+    assert_eq!(harness.balance(harness.fee_vault).await, 30_000 + 30_001);
+    // The sale fee rounds up too
+    harness.sell(0).await;
+    harness.check_invariants("rounded-up fees").await;
+}
+
+#[tokio::test]
+async fn liquidity_must_match_the_reserve_ratio() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 50_000_000).await;
+    let reserves = harness.poll().await.shares;
+    assert_ne!(reserves[0], reserves[1]);
+
+    let err = harness.add_liquidity(1, vec![10_000_000, 10_000_000]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::ImbalancedLiquidity as u32)), "{err}");
+
+    let amounts: Vec<u64> = reserves.iter().map(|&reserve| reserve / 100).collect();
+    harness.add_liquidity(1, amounts.clone()).await.unwrap();
+    let grown = harness.poll().await.shares;
+    assert_eq!(grown, vec![reserves[0] + amounts[0], reserves[1] + amounts[1]]);
+    harness.check_invariants("balanced liquidity").await;
+}
+
+#[tokio::test]
+async fn the_minimum_liquidity_stays_locked() {
+    let options = PollOptions { initial_shares: Some(10 * MINIMUM_LIQUIDITY), ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    let options = PollOptions { initial_shares: Some(MINIMUM_LIQUIDITY), ..Default::default() };
+    let err = harness.create_poll(&Keypair::new(), 300, 0, &options).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::InsufficientInitialLiquidity as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    harness.add_liquidity(USERS, vec![20_000_000, 20_000_000]).await.unwrap();
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 30_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    for (vote, user) in harness.votes.clone() {
+        let _ = harness.claim(vote, user).await;
+        harness.check_invariants("claim").await;
+    }
+    assert_eq!(harness.outstanding_winnings().await, 0);
+
+    // The deposit would cover the whole reserve, but its last
+    // MINIMUM_LIQUIDITY cannot be withdrawn
+    let poll = harness.poll().await;
+    let drainable = poll.shares[0] - MINIMUM_LIQUIDITY;
+    assert!(drainable < poll.liquidity);
+    let err = harness.remove_liquidity(vec![drainable + 1, 0]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::NotEnoughLiquidity as u32)), "{err}");
+    harness.remove_liquidity(vec![drainable, 0]).await.unwrap();
+    harness.check_invariants("remove_liquidity").await;
+    assert_eq!(harness.poll().await.shares[0], MINIMUM_LIQUIDITY);
+}
+
+#[tokio::test]
+async fn flagged_polls_need_a_vote_to_resolve() {
+    let options = PollOptions { require_votes: true, ..Default::default() };
+    let mut empty = Harness::with_options(300, 0, 2, options).await;
+    let err = empty.try_resolve(empty.outcomes[0]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::NoVotesCast as u32)), "{err}");
+    assert!(empty.poll().await.status == PollStatus::Active);
+
+    let options = PollOptions { require_votes: true, ..Default::default() };
+    let mut voted = Harness::with_options(300, 0, 2, options).await;
+    voted.vote(0, 1, 10_000_000).await;
+    voted.resolve(voted.outcomes[0]).await;
+    assert!(voted.poll().await.status == PollStatus::Resolved);
+
+    // Without the flag an empty poll still resolves
+    let mut unflagged = Harness::new(300, 0, 2).await;
+    unflagged.resolve(unflagged.outcomes[0]).await;
+}
+
+#[tokio::test]
+async fn winnings_can_be_claimed_in_parts() {
+    let mut harness = Harness::new(300, 100, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 1, 20_000_000).await;
+    harness.vote(2, 2, 30_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    let (vote, _) = harness.votes[0];
+    let owed = payout(&harness.poll().await, &harness.vote_account(vote).await);
+    let token_account = harness.users[0].1;
+    let before = harness.balance(token_account).await;
+    for amount in [Some(0), Some(owed + 1)] {
+        let err = harness.claim_part(vote, 0, None, amount).await.unwrap_err();
+        assert!(err.contains(&format!("{:#x}", 6000 + AmmError::InvalidClaimAmount as u32)), "{err}");
+    }
+
+    let first = owed / 3;
+    harness.claim_part(vote, 0, None, Some(first)).await.unwrap();
+    harness.check_invariants("first part").await;
+    let vote_account = harness.vote_account(vote).await;
+    assert!(!vote_account.claimed);
+    assert_eq!(vote_account.claimed_amount, first);
+    assert_eq!(harness.poll().await.unclaimed_winners, 2);
+
+    harness.claim(vote, 0).await.unwrap();
+    harness.check_invariants("second part").await;
+    let vote_account = harness.vote_account(vote).await;
+    assert!(vote_account.claimed);
+    assert_eq!(vote_account.claimed_amount, owed);
+    assert_eq!(harness.poll().await.unclaimed_winners, 1);
+    // The 1% claim fee is charged on each part
+    let fees = first / 100 + (owed - first) / 100;
+    assert_eq!(harness.balance(token_account).await - before, owed - fees);
+
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    let err = harness.claim(vote, 0).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::AlreadyClaimed as u32)), "{err}");
+}
+
+// Poll as the first deployment defined it, before outcomes became vectors
+// and accounts were versioned
+#[derive(AnchorSerialize)]
+struct BaselinePoll {
+    authority: Pubkey,
+    title: Vec<u8>,
+    closes_at: i64,
+    nft1: Pubkey,
+    nft2: Pubkey,
+    nft1_shares: u64,
+    nft2_shares: u64,
+    k: u64,
+    status: u8,
+    winning_nft: Option<Pubkey>,
+    token_mint: Pubkey,
+}
+
+// Vote as the first deployment left it: a keypair account of 8 + 90 bytes
+#[derive(AnchorSerialize)]
+struct BaselineVote {
+    poll: Pubkey,
+    user: Pubkey,
+    voted_for_nft: u8,
+    amount: u64,
+    value: u64,
+    price_at_transaction: u64,
+    claimed: bool,
+}
+
+// Replaces the harness's poll with one the first deployment would have left:
+// the baseline layout at its fixed 8 + 262 bytes, with neither a registry
+// nor PDA vaults, and every bet in `bets` of (user, nft_choice, value)
+// swapped by the baseline's constant product and staked, less its 3% fee,
+// in a vault of the pool authority. Returns that vault and the legacy votes
+async fn set_baseline_poll(harness: &mut Harness, status: u8, bets: &[(usize, u8, u64)]) -> (Pubkey, Vec<Pubkey>) {
+    let payer = harness.ctx.payer.pubkey();
+    for account in [harness.pool_vault, harness.fee_vault, harness.authority_fee_vault, solana_contracts::registry_pda(&payer).0] {
+        harness.ctx.set_account(&account, &Account::default().into());
+    }
+    harness.model.reserve_product = 0;
+    let legacy_vault = harness.create_token_account(&harness.pool_authority.clone()).await;
+
+    let current = harness.poll().await;
+    let (mut nft1_shares, mut nft2_shares) = (current.shares[0], current.shares[1]);
+    let k = nft1_shares * nft2_shares;
+    let mut votes = Vec::new();
+    for &(user, nft_choice, value) in bets {
+        let stake = value - value * 3 / 100;
+        let amount = if nft_choice == 1 {
+            nft2_shares += stake;
+            let received = nft1_shares - k / nft2_shares;
+            nft1_shares -= received;
+            received
+        } else {
+            nft1_shares += stake;
+            let received = nft2_shares - k / nft1_shares;
+            nft2_shares -= received;
+            received
+        };
+        let (wallet, token_account) = (harness.users[user].0.insecure_clone(), harness.users[user].1);
+        let transfer =
+            spl_token::instruction::transfer(&spl_token::ID, &token_account, &legacy_vault, &wallet.pubkey(), &[], stake)
+                .unwrap();
+        harness.send(&[transfer], &[&wallet]).await.unwrap();
+
+        let vote = BaselineVote {
+            poll: harness.poll,
+            user: wallet.pubkey(),
+            voted_for_nft: nft_choice,
+            amount,
+            value,
+            price_at_transaction: 0,
+            claimed: false,
+        };
+        let mut data = Vote::DISCRIMINATOR.to_vec();
+        data.extend(vote.try_to_vec().unwrap());
+        let address = Pubkey::new_unique();
+        let account = Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: solana_contracts::ID,
+            ..Default::default()
+        };
+        harness.ctx.set_account(&address, &account.into());
+        votes.push(address);
+    }
+
+    let baseline = BaselinePoll {
+        authority: current.authority,
+        title: b"Legacy poll".to_vec(),
+        closes_at: current.closes_at,
+        nft1: current.outcomes[0],
+        nft2: current.outcomes[1],
+        nft1_shares,
+        nft2_shares,
+        k,
+        status,
+        winning_nft: None,
+        token_mint: current.token_mint,
+    };
+    let legacy_space = 8 + 262;
+    let mut data = vec![0; legacy_space];
+    data[..8].copy_from_slice(Poll::DISCRIMINATOR);
+    let fields = baseline.try_to_vec().unwrap();
+    data[8..8 + fields.len()].copy_from_slice(&fields);
+    let legacy = Account {
+        lamports: Rent::default().minimum_balance(legacy_space),
+        data,
+        owner: solana_contracts::ID,
+        ..Default::default()
+    };
+    harness.ctx.set_account(&harness.poll, &legacy.into());
+    (legacy_vault, votes)
+}
+
+#[tokio::test]
+async fn unversioned_polls_migrate_to_the_current_layout() {
+    let mut harness = Harness::new(0, 0, 2).await;
+    let err = harness.migrate_poll().await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::PollAlreadyMigrated as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    let current = harness.poll().await;
+    let (legacy_vault, votes) = set_baseline_poll(&mut harness, 0, &[(0, 1, 30_000_000), (1, 2, 10_000_000)]).await;
+    harness.migrate_poll().await.unwrap();
+    let space = Poll::space(b"Legacy poll".len(), 2, 0);
+    let account = harness.ctx.banks_client.get_account(harness.poll).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), space);
+    assert!(account.lamports >= Rent::default().minimum_balance(space));
+    let migrated = harness.poll().await;
+    assert_eq!(migrated.version, POLL_VERSION);
+    assert_eq!(migrated.authority, current.authority);
+    assert_eq!(migrated.title, b"Legacy poll".to_vec());
+    assert_eq!(migrated.closes_at, current.closes_at);
+    assert_eq!(migrated.outcomes, current.outcomes);
+    assert!(migrated.shares[0] < current.shares[0] && migrated.shares[1] > current.shares[1]);
+    assert!(migrated.status == PollStatus::Active && migrated.winning_nft.is_none());
+    assert_eq!(migrated.token_mint, current.token_mint);
+    // The fields the old layout lacked take their defaults
+    assert_eq!((migrated.resolver, migrated.max_bet), (current.authority, u64::MAX));
+    assert_eq!(migrated.positions, vec![0, 0]);
+    assert_eq!(migrated.outcome_pools, vec![0, 0]);
+    assert_eq!((migrated.final_prices, migrated.min_reserve, migrated.claim_deadline), (vec![0, 0], 0, 0));
+    assert_eq!(migrated.outcome_disabled, vec![false, false]);
+    // The poll now has the vaults and the registry entry it lacked
+    let registry = harness.ctx.banks_client.get_account(solana_contracts::registry_pda(&current.authority).0).await.unwrap();
+    let registry = PollRegistry::try_deserialize(&mut &registry.unwrap().data[..]).unwrap();
+    assert_eq!((registry.authority, registry.active_poll_count), (current.authority, 1));
+    for vault in [harness.pool_vault, harness.fee_vault, harness.authority_fee_vault] {
+        assert_eq!(harness.balance(vault).await, 0);
+    }
+    harness.model.reserve_product = reserve_product(&harness.poll().await);
+
+    // Stakes only move out of a vault other than the pool vault
+    let err = harness.migrate_vote(votes[0], 0, harness.pool_vault).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::InvalidLegacyVault as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    for (user, &vote) in votes.iter().enumerate() {
+        harness.migrate_vote(vote, user, legacy_vault).await.unwrap();
+    }
+    harness.check_invariants("vote migration").await;
+    assert_eq!(harness.balance(legacy_vault).await, 0);
+    let poll = harness.poll().await;
+    assert_eq!(poll.positions, vec![1, 1]);
+    assert_eq!(poll.outcome_pools, vec![30_000_000 - 900_000, 10_000_000 - 300_000]);
+    let vote = harness.vote_account(votes[0]).await;
+    assert_eq!((vote.user, vote.voted_for_nft, vote.stake, vote.value), (harness.users[0].0.pubkey(), 1, 29_100_000, 30_000_000));
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    let err = harness.migrate_vote(votes[0], 0, legacy_vault).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::VoteAlreadyMigrated as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    // The migrated poll trades and settles like any other
+    harness.vote(2, 1, 10_000_000).await;
+    harness.check_invariants("vote after migration").await;
+    assert_eq!(harness.poll().await.positions, vec![2, 1]);
+    harness.resolve(current.outcomes[0]).await;
+    harness.check_invariants("resolution").await;
+    let registry = harness.ctx.banks_client.get_account(solana_contracts::registry_pda(&current.authority).0).await.unwrap();
+    assert_eq!(PollRegistry::try_deserialize(&mut &registry.unwrap().data[..]).unwrap().active_poll_count, 0);
+    for (vote, user) in harness.votes.clone() {
+        if harness.vote_account(vote).await.voted_for_nft == 1 {
+            harness.claim(vote, user).await.unwrap();
+            harness.check_invariants("claim").await;
+        }
+    }
+    assert_eq!(harness.balance(harness.pool_vault).await, 0);
+    harness.close_votes(0).await;
+}
+
+#[tokio::test]
+async fn resolved_unversioned_polls_are_not_migrated() {
+    let mut harness = Harness::new(0, 0, 2).await;
+    set_baseline_poll(&mut harness, 2, &[(0, 1, 10_000_000)]).await;
+    let err = harness.migrate_poll().await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::LegacyPollResolved as u32)), "{err}");
+}
+
+// Account data of `poll` as a program at `version` wrote it: each version
+// only appended fields, so it is the current encoding cut short
+fn poll_data_at_version(poll: &Poll, version: u8) -> Vec<u8> {
+    let outcome_count = poll.outcomes.len();
+    // Bytes appended by each version from 2 onwards: final_prices,
+    // min_reserve, claim_deadline, outcome_disabled, and lp_fees with
+    // fee_per_deposit
+    let appended = [4 + 8 * outcome_count, 8, 8, 4 + outcome_count, 8 + 16];
+    let mut data = Poll::DISCRIMINATOR.to_vec();
+    poll.serialize(&mut data).unwrap();
+    data[8] = version;
+    let newer: usize = appended[version as usize - 1..].iter().sum();
+    data.truncate(data.len() - newer);
+    data
+}
+
+#[tokio::test]
+async fn older_versions_upgrade_one_step_at_a_time() {
+    let options = PollOptions { min_reserve: 1_000_000, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 3, options).await;
+    harness.set_claim_window(1000).await;
+    harness.add_liquidity(2, vec![10_000_000; 3]).await.unwrap();
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 3, 4_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    let current = harness.poll().await;
+    assert!(current.final_prices.iter().all(|&price| price > 0));
+    assert!(current.claim_deadline > 0);
+    assert!(current.lp_fees > 0);
+
+    for version in 1..POLL_VERSION {
+        let data = poll_data_at_version(&current, version);
+        let older = Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: solana_contracts::ID,
+            ..Default::default()
+        };
+        harness.ctx.set_account(&harness.poll, &older.into());
+        harness.migrate_poll().await.unwrap();
+
+        let account = harness.ctx.banks_client.get_account(harness.poll).await.unwrap().unwrap();
+        assert_eq!(account.data.len(), Poll::space(current.title.len(), 3, current.metadata_uri.len()));
+        // What the older layout held carries over, and what it lacked
+        // starts out empty
+        let mut expected = current.clone();
+        if version < 2 {
+            expected.final_prices = vec![0; 3];
+        }
+        if version < 3 {
+            expected.min_reserve = 0;
+        }
+        if version < 4 {
+            expected.claim_deadline = 0;
+        }
+        if version < 5 {
+            expected.outcome_disabled = vec![false; 3];
+        }
+        if version < 6 {
+            expected.lp_fees = 0;
+            expected.fee_per_deposit = 0;
+        }
+        let migrated = harness.poll().await;
+        assert_eq!(migrated.try_to_vec().unwrap(), expected.try_to_vec().unwrap(), "from version {version}");
+        harness.ctx.get_new_latest_blockhash().await.unwrap();
+    }
+    // Coming from before the split, the providers' fees went to the protocol
+    harness.model.lp_fees = 0;
+    harness.check_invariants("migrate_poll").await;
+}
+
+#[tokio::test]
+async fn polls_are_sized_to_their_metadata() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    let account = harness.ctx.banks_client.get_account(harness.poll).await.unwrap().unwrap();
+    let title_len = b"Invariant harness".len();
+    assert_eq!(account.data.len(), Poll::space(title_len, 2, 0));
+    assert!(account.data.len() < 8 + Poll::LEN);
+
+    // A longer URI grows the account at the authority's expense
+    let payer = harness.ctx.payer.pubkey();
+    let lamports_before = harness.ctx.banks_client.get_balance(payer).await.unwrap();
+    let uri = b"https://example.com/polls/invariant-harness.json".to_vec();
+    harness.update_metadata(uri.clone()).await.unwrap();
+    let grown = harness.ctx.banks_client.get_account(harness.poll).await.unwrap().unwrap();
+    assert_eq!(grown.data.len(), Poll::space(title_len, 2, uri.len()));
+    let rent = Rent::default();
+    assert_eq!(grown.lamports, rent.minimum_balance(grown.data.len()));
+    let paid = lamports_before - harness.ctx.banks_client.get_balance(payer).await.unwrap();
+    assert!(paid >= grown.lamports - account.lamports, "rent was not paid by the authority");
+    assert_eq!(harness.poll().await.metadata_uri, uri);
+
+    let too_long = vec![b'a'; MAX_METADATA_URI_LEN + 1];
+    let err = harness.update_metadata(too_long).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::UriTooLong as u32)), "{err}");
+    harness.check_invariants("update_metadata").await;
+}
+
+#[tokio::test]
+async fn poll_state_events_mirror_the_account() {
+    let options =
+        PollOptions { dispute_period: 600, authority_fee_bps: 100, min_vote_interval: 5, ..Default::default() };
+    let mut harness = Harness::with_options(300, 50, 2, options).await;
+    harness.add_liquidity(USERS, vec![5_000_000, 5_000_000]).await.unwrap();
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 4_000_000).await;
+    harness.resolve(harness.outcomes[1]).await;
+
+    let ix = Instruction {
+        program_id: solana_contracts::ID,
+        accounts: accounts::GetPollState { poll: harness.poll }.to_account_metas(None),
+        data: instruction::GetPollState {}.data(),
+    };
+    harness.send(&[ix], &[]).await.unwrap();
+    let events = harness.events::<PollStateEvent>();
+    assert_eq!(events.len(), 1);
+    let (state, poll) = (&events[0], harness.poll().await);
+
+    assert_eq!((state.poll, state.version, state.authority), (harness.poll, poll.version, poll.authority));
+    assert_eq!((state.status, state.resolution_mode), (PollStatus::Resolved as u8, ResolutionMode::Resolver as u8));
+    assert_eq!(state.reserves, poll.shares);
+    assert_eq!(state.winning_nft, harness.outcomes[1]);
+    assert_eq!((state.parent_poll, state.required_parent_outcome), (Pubkey::default(), Pubkey::default()));
+    assert_eq!((state.token_mint, state.resolver), (poll.token_mint, poll.resolver));
+    assert_eq!((state.closes_at, state.created_at, state.closed_at), (poll.closes_at, poll.created_at, poll.closed_at));
+    assert_eq!((state.resolved_at, state.dispute_period), (poll.resolved_at, 600));
+    assert_eq!((state.resolution_deadline, state.min_vote_interval), (poll.resolution_deadline, 5));
+    assert_eq!((state.last_price_update, state.last_update_slot), (poll.last_price_update, poll.last_update_slot));
+    assert_eq!((state.fee_bps, state.claim_fee_bps, state.authority_fee_bps), (300, 50, 100));
+    assert_eq!((state.min_bet, state.max_bet, state.mint_decimals), (poll.min_bet, poll.max_bet, 6));
+    assert_eq!((state.winning_side_shares, state.liquidity), (poll.winning_side_shares, poll.liquidity));
+    assert_eq!((state.total_volume, state.vote_count), (14_000_000, 2));
+    assert_eq!((state.vault_liability, state.lp_deposits), (poll.vault_liability, 10_000_000));
+    assert_eq!(state.unclaimed_winners, 1);
+    assert_eq!(
+        (state.bonus_enabled, state.require_ownership_proof, state.require_votes),
+        (poll.bonus_enabled, poll.require_ownership_proof, poll.require_votes)
+    );
+}
+
+#[tokio::test]
+async fn bets_cannot_drain_a_reserve_below_the_floor() {
+    let initial_shares = 100_000_000;
+    let options =
+        PollOptions { initial_shares: Some(initial_shares), min_reserve: initial_shares / 2, ..Default::default() };
+    let mut harness = Harness::with_options(0, 0, 2, options).await;
+    let code = format!("{:#x}", 6000 + AmmError::NotEnoughLiquidity as u32);
+
+    // Would leave the first reserve at a third of its size
+    let err = harness.try_vote(0, 1, 2 * initial_shares).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    assert_eq!(harness.poll().await.vote_count, 0);
+
+    // Lands exactly on the floor
+    harness.try_vote(0, 1, initial_shares).await.unwrap();
+    assert_eq!(harness.poll().await.shares[0], initial_shares / 2);
+    // Any further bet on that side would go below it
+    let err = harness.try_vote(1, 1, 1_000_000).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+
+    // The other side only deepens the drained reserve
+    harness.try_vote(1, 2, 1_000_000).await.unwrap();
+    assert!(harness.poll().await.shares[0] > initial_shares / 2);
+    harness.check_invariants("reserve floor").await;
+}
+
+#[tokio::test]
+async fn sales_cannot_drain_a_reserve_below_the_floor() {
+    let initial_shares = 100_000_000;
+    let options =
+        PollOptions { initial_shares: Some(initial_shares), min_reserve: initial_shares / 2, ..Default::default() };
+    let mut harness = Harness::with_options(0, 0, 2, options).await;
+    harness.try_vote(0, 1, 10_000_000).await.unwrap();
+    // Leave the second reserve just above the floor
+    harness.try_vote(1, 2, 60_000_000).await.unwrap();
+    harness.try_vote(2, 2, 45_000_000).await.unwrap();
+    assert!(harness.poll().await.shares[1] < initial_shares * 52 / 100);
+
+    // Selling the first position back takes the second reserve under it
+    let code = format!("{:#x}", 6000 + AmmError::NotEnoughLiquidity as u32);
+    let err = harness.try_sell(0).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    assert_eq!(harness.votes.len(), 3);
+    assert!(harness.poll().await.shares[1] >= initial_shares / 2);
+    harness.check_invariants("sale below the floor").await;
+}
+
+#[tokio::test]
+async fn disabled_outcomes_are_refunded_while_the_others_trade() {
+    let mut harness = Harness::new(300, 0, 3).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 8_000_000).await;
+    harness.vote(2, 2, 4_000_000).await;
+    harness.vote(3, 3, 6_000_000).await;
+    let votes: Vec<Pubkey> = harness.votes.iter().map(|&(vote, _)| vote).collect();
+    let before = harness.poll().await;
+
+    harness.disable_outcome(1).await.unwrap();
+    let poll = harness.poll().await;
+    assert_eq!(poll.outcome_disabled, vec![false, true, false]);
+    assert_eq!(poll.shares, vec![before.shares[0], 0, before.shares[2]]);
+    let disabled = &harness.events::<OutcomeDisabled>()[0];
+    assert_eq!((disabled.outcome, disabled.reserve), (harness.outcomes[1], before.shares[1]));
+    assert_eq!((disabled.refunds_owed, disabled.positions), (before.outcome_pools[1], 2));
+    // The two outcomes left share the whole probability
+    let prices = &harness.events::<PriceUpdateEvent>()[0].prices;
+    assert_eq!(prices[1], 0);
+    assert!(prices[0] + prices[2] > 9990);
+    harness.check_invariants("disable_outcome").await;
+
+    // The disabled outcome takes no bets and cannot be disabled again, and
+    // its voters are refunded before the poll settles
+    let code = format!("{:#x}", 6000 + AmmError::OutcomeDisabled as u32);
+    let err = harness.try_vote(4, 2, 1_000_000).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    let err = harness.disable_outcome(1).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.refund(votes[1], 1).await.unwrap();
+    harness.check_invariants("refund of a disabled outcome").await;
+    // Two outcomes are the fewest a market can have
+    let err = harness.disable_outcome(0).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::InvalidOutcomeCount as u32)), "{err}");
+
+    // The other two keep trading, with liquidity added to their reserves only
+    let poll = harness.poll().await;
+    harness.add_liquidity(USERS, vec![poll.shares[0] / 10, 0, poll.shares[2] / 10]).await.unwrap();
+    harness.check_invariants("add_liquidity").await;
+    harness.vote(4, 1, 5_000_000).await;
+    harness.check_invariants("vote after disable_outcome").await;
+    harness.try_sell(0).await.unwrap();
+    harness.check_invariants("sale after disable_outcome").await;
+
+    let err = harness.try_resolve(harness.outcomes[1]).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.resolve(harness.outcomes[2]).await;
+    // The winner and the refund still owed on the disabled outcome
+    assert_eq!(harness.poll().await.unclaimed_winners, 2);
+    harness.check_invariants("resolve").await;
+
+    harness.claim(votes[3], 3).await.unwrap();
+    harness.check_invariants("claim").await;
+    harness.refund(votes[2], 2).await.unwrap();
+    harness.check_invariants("refund after resolution").await;
+    let poll = harness.poll().await;
+    assert_eq!((poll.unclaimed_winners, poll.vault_liability), (0, 0));
+}
+
+#[tokio::test]
+async fn unclaimed_winnings_are_swept_after_the_claim_deadline() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.set_claim_window(1000).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 1, 5_000_000).await;
+    harness.vote(2, 2, 4_000_000).await;
+    let votes = harness.votes.clone();
+    harness.resolve(harness.outcomes[0]).await;
+    let poll = harness.poll().await;
+    assert_eq!(poll.claim_deadline, poll.resolved_at + 1000);
+
+    // Before the deadline winners claim and nothing can be swept
+    harness.claim(votes[0].0, 0).await.unwrap();
+    let open = format!("{:#x}", 6000 + AmmError::ClaimWindowOpen as u32);
+    let err = harness.sweep_unclaimed().await.unwrap_err();
+    assert!(err.contains(&open), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.check_invariants("claim before the deadline").await;
+    // The unpaid winner's vote stays open while it may still claim
+    let err = harness.close_vote(votes[1].0, 1).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::VoteNotSettled as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    // From the deadline on the second winner is too late
+    harness.warp_to(poll.claim_deadline).await;
+    let err = harness.claim(votes[1].0, 1).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::ClaimWindowClosed as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    let owed = harness.poll().await.vault_liability;
+    assert!(owed > 0);
+    let before = harness.balance(harness.authority_token_account).await;
+    harness.sweep_unclaimed().await.unwrap();
+    assert_eq!(harness.balance(harness.authority_token_account).await, before + owed);
+    let swept = harness.events::<UnclaimedSweptEvent>();
+    assert_eq!(swept.len(), 1);
+    assert_eq!((swept[0].amount, swept[0].unclaimed_winners), (owed, 1));
+    let poll = harness.poll().await;
+    assert_eq!((poll.vault_liability, poll.unclaimed_winners), (0, 0));
+    harness.check_invariants("sweep_unclaimed").await;
+
+    // Nothing is left owed, so every vote, the unpaid winner's included, and
+    // the poll can be closed
+    harness.close_votes(0).await;
+    harness.withdraw_all_liquidity(0).await;
+    harness.close_poll_accounts().await.unwrap();
+}
+
+#[tokio::test]
+async fn the_claim_window_starts_after_the_dispute_window() {
+    let options = PollOptions { dispute_period: 3600, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    // Shorter than the dispute period, which must not swallow it
+    harness.set_claim_window(600).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    let poll = harness.poll().await;
+    assert_eq!(poll.claim_deadline, poll.resolved_at + 3600 + 600);
+
+    // Once the dispute window closes nothing can be swept yet, and the
+    // winner still has the whole claim window
+    harness.warp_to(poll.resolved_at + 3600).await;
+    let err = harness.sweep_unclaimed().await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::ClaimWindowOpen as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.warp_to(poll.claim_deadline - 1).await;
+    harness.claim(harness.votes[0].0, 0).await.unwrap();
+    harness.check_invariants("claim at the end of the window").await;
+}
+
+#[tokio::test]
+async fn polls_without_a_claim_window_are_never_swept() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    assert_eq!(harness.poll().await.claim_deadline, 0);
+
+    harness.warp_to(i64::MAX / 2).await;
+    let err = harness.sweep_unclaimed().await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::ClaimWindowOpen as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.claim(harness.votes[0].0, 0).await.unwrap();
+    harness.check_invariants("late claim").await;
+}
+
+#[tokio::test]
+async fn split_votes_record_both_legs() {
+    let mut harness = Harness::new(300, 0, 3).await;
+    // The same bets placed one after the other by two users
+    let mut sequential = Harness::new(300, 0, 3).await;
+    sequential.vote(0, 1, 10_000_000).await;
+    sequential.vote(1, 3, 4_000_000).await;
+
+    let before = harness.balance(harness.users[0].1).await;
+    harness.vote_split(0, (1, 10_000_000), (3, 4_000_000)).await.unwrap();
+    assert_eq!(before - harness.balance(harness.users[0].1).await, 14_000_000);
+    // The second swap ran on the reserves the first left
+    let (poll, expected) = (harness.poll().await, sequential.poll().await);
+    assert_eq!(poll.shares, expected.shares);
+    assert_eq!(poll.vote_shares, expected.vote_shares);
+    assert_eq!((poll.vote_count, poll.positions.clone()), (2, vec![1, 0, 1]));
+    // A single 3% fee on the combined stake
+    assert_eq!(harness.balance(harness.fee_vault).await, 420_000);
+
+    let user = harness.users[0].0.pubkey();
+    let legs = [
+        (solana_contracts::vote_pda(&harness.poll, &user).0, 1, 9_700_000),
+        (solana_contracts::split_vote_pda(&harness.poll, &user, 3).0, 3, 3_880_000),
+    ];
+    for (vote, nft_choice, stake) in legs {
+        let vote = harness.vote_account(vote).await;
+        assert_eq!((vote.user, vote.voted_for_nft, vote.stake), (user, nft_choice, stake));
+    }
+    // The first leg is the user's regular position, which only takes bets
+    // on its own outcome
+    let code = format!("{:#x}", 6000 + AmmError::ConflictingVote as u32);
+    let err = harness.try_vote(0, 2, 1_000_000).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    let err = harness.vote_split(0, (2, 1_000_000), (3, 1_000_000)).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    // Both legs on one outcome
+    let err = harness.vote_split(0, (1, 1_000_000), (1, 1_000_000)).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::InvalidNftChoice as u32)), "{err}");
+    harness.check_invariants("split vote").await;
+
+    harness.resolve(harness.outcomes[2]).await;
+    let winning_leg = solana_contracts::split_vote_pda(&harness.poll, &user, 3).0;
+    harness.claim(winning_leg, 0).await.unwrap();
+    harness.check_invariants("split vote claim").await;
+}
+
+#[tokio::test]
+async fn split_votes_wait_out_the_vote_interval() {
+    let options = PollOptions { min_vote_interval: 600, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 3, options).await;
+    let code = format!("{:#x}", 6000 + AmmError::VotingTooFast as u32);
+    let clock: Clock = harness.ctx.banks_client.get_sysvar().await.unwrap();
+    harness.vote(0, 1, 10_000_000).await;
+    let err = harness.vote_split(0, (1, 1_000_000), (2, 1_000_000)).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+
+    harness.warp_to(clock.unix_timestamp + 600).await;
+    harness.vote_split(0, (1, 1_000_000), (2, 1_000_000)).await.unwrap();
+    // The split restarted the interval for the regular position and the legs
+    let err = harness.try_vote(0, 1, 1_000_000).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    let err = harness.vote_split(0, (1, 1_000_000), (3, 1_000_000)).await.unwrap_err();
+    assert!(err.contains(&code), "{err}");
+    let user = harness.users[0].0.pubkey();
+    let vote = harness.vote_account(solana_contracts::vote_pda(&harness.poll, &user).0).await;
+    assert_eq!((vote.value, vote.last_vote_at), (11_000_000, clock.unix_timestamp + 600));
+    harness.check_invariants("split vote interval").await;
+}
+
+#[tokio::test]
+async fn closes_by_anyone_but_the_authority_are_automatic() {
+    for by_authority in [false, true] {
+        let mut harness = Harness::new(300, 0, 2).await;
+        // A keeper only signs; the payer covers the transaction fee
+        let caller = if by_authority { harness.ctx.payer.insecure_clone() } else { Keypair::new() };
+        harness.warp_to(harness.closes_at).await;
+        harness.close_poll(&caller).await.unwrap();
+
+        let closed = harness.events::<PollClosedEvent>();
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].poll, closed[0].closed_by), (harness.poll, caller.pubkey()));
+        assert_eq!(closed[0].auto, !by_authority);
+        assert!(harness.poll().await.status == PollStatus::Closed);
+    }
+}
+
+#[tokio::test]
+async fn resolution_records_the_final_prices() {
+    let mut harness = Harness::new(300, 0, 3).await;
+    assert_eq!(harness.poll().await.final_prices, vec![0; 3]);
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 3, 4_000_000).await;
+    // Prices of the reserves the last bet left, which resolution must keep
+    let prices = harness.events::<PriceUpdateEvent>().remove(0).prices;
+
+    harness.resolve(harness.outcomes[0]).await;
+    let resolved = harness.events::<PollResolvedEvent>();
+    assert_eq!(resolved.len(), 1);
+    assert_eq!(resolved[0].final_prices, prices);
+    assert_eq!(harness.poll().await.final_prices, prices);
+    assert!(prices[0] > prices[1] && prices[2] > prices[1]);
+}
+
+#[tokio::test]
+async fn frozen_vaults_reject_votes() {
+    let mut harness = Harness::new(300, 50, 2).await;
+    let pool_vault = harness.pool_vault;
+    let set_state = |harness: &mut Harness, account: &mut Account, state| {
+        let mut token = spl_token::state::Account::unpack(&account.data).unwrap();
+        token.state = state;
+        spl_token::state::Account::pack(token, &mut account.data).unwrap();
+        harness.ctx.set_account(&pool_vault, &account.clone().into());
+    };
+    let mut account = harness.ctx.banks_client.get_account(pool_vault).await.unwrap().unwrap();
+    set_state(&mut harness, &mut account, spl_token::state::AccountState::Frozen);
+
+    let before = harness.balance(pool_vault).await;
+    harness.vote(0, 1, 1_000_000).await;
+    let poll = harness.poll().await;
+    assert_eq!((poll.vote_count, poll.total_volume), (0, 0));
+    assert_eq!(harness.balance(pool_vault).await, before);
+
+    // Once thawed the same bet goes through
+    set_state(&mut harness, &mut account, spl_token::state::AccountState::Initialized);
+    harness.vote(0, 1, 1_000_000).await;
+    assert_eq!(harness.poll().await.vote_count, 1);
+    harness.check_invariants("thawed vote").await;
+}
+
+#[tokio::test]
+async fn polls_can_take_the_default_fee() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    let err = harness.set_default_fee(MAX_FEE_BPS + 1).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::FeeTooHigh as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+    harness.set_default_fee(150).await.unwrap();
+
+    // The harness poll was created with an explicit fee and keeps it
+    assert_eq!(harness.poll().await.fee_bps, 300);
+    let poll = Keypair::new();
+    harness.create_poll(&poll, USE_DEFAULT_FEE, 0, &PollOptions::default()).await.unwrap();
+    assert_eq!(harness.poll_account(poll.pubkey()).await.fee_bps, 150);
+}
+
+#[tokio::test]
+async fn later_votes_pay_the_changed_poll_fee() {
+    let options = PollOptions { authority_fee_bps: 50, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    harness.vote(0, 1, 10_000_000).await;
+    assert_eq!(harness.model.fee_vault + harness.model.authority_fee_vault, 300_000);
+
+    let user = harness.users[0].0.insecure_clone();
+    let err = harness.set_poll_fee(&user, 100).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::Unauthorized as u32)), "{err}");
+    let authority = harness.ctx.payer.insecure_clone();
+    let err = harness.set_poll_fee(&authority, MAX_FEE_BPS + 1).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::FeeTooHigh as u32)), "{err}");
+    // The authority's 50 bps would no longer fit in the fee
+    let err = harness.set_poll_fee(&authority, 40).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::InvalidFeeSplit as u32)), "{err}");
+
+    harness.set_poll_fee(&authority, 100).await.unwrap();
+    let changed = harness.events::<FeeChangedEvent>();
+    assert_eq!(changed.len(), 1);
+    assert_eq!((changed[0].previous_fee_bps, changed[0].fee_bps), (300, 100));
+    assert_eq!(harness.poll().await.fee_bps, 100);
+
+    harness.vote(1, 2, 10_000_000).await;
+    assert_eq!(harness.model.fee_vault + harness.model.authority_fee_vault, 400_000);
+    harness.check_invariants("after the fee change").await;
+
+    // Betting has ended, so the fee is settled
+    harness.warp_to(harness.closes_at).await;
+    let err = harness.set_poll_fee(&authority, 200).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::PollClosed as u32)), "{err}");
+}
+
+#[tokio::test]
+async fn raising_the_title_cap_allows_longer_titles() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    let title = vec![b'a'; DEFAULT_MAX_TITLE_LEN as usize + 36];
+    let options = PollOptions { title: Some(title.clone()), ..Default::default() };
+    let err = harness.create_poll(&Keypair::new(), 300, 0, &options).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::TitleTooLong as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    let err = harness.set_max_title_len(MAX_TITLE_LEN as u16 + 1).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::TitleLimitTooHigh as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    harness.set_max_title_len(128).await.unwrap();
+    let poll = Keypair::new();
+    harness.create_poll(&poll, 300, 0, &options).await.unwrap();
+    assert_eq!(harness.poll_account(poll.pubkey()).await.title, title);
+    let account = harness.ctx.banks_client.get_account(poll.pubkey()).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), Poll::space(title.len(), 2, 0));
+}
+
+#[tokio::test]
+async fn admin_actions_emit_events() {
+    let options = PollOptions { dispute_period: 3600, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    let admin = harness.ctx.payer.insecure_clone();
+
+    harness.set_paused(true).await;
+    harness.set_paused(false).await;
+    let paused = harness.events::<PausedEvent>();
+    assert_eq!(paused.len(), 1);
+    assert_eq!((paused[0].admin, paused[0].paused), (admin.pubkey(), false));
+
+    harness.vote(0, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    harness.override_resolution(harness.outcomes[1]).await.unwrap();
+    let overridden = harness.events::<ResolutionOverriddenEvent>();
+    assert_eq!(overridden.len(), 1);
+    assert_eq!(overridden[0].poll, harness.poll);
+    assert_eq!(overridden[0].admin, admin.pubkey());
+    assert_eq!(overridden[0].previous_winning_nft, harness.outcomes[0]);
+    assert_eq!(overridden[0].winning_nft, harness.outcomes[1]);
+
+    harness.withdraw_fees(Some(1_000)).await.unwrap();
+    let withdrawn = harness.events::<FeesWithdrawn>();
+    assert_eq!(withdrawn.len(), 1);
+    assert_eq!(withdrawn[0].poll, harness.poll);
+    assert_eq!(withdrawn[0].destination, harness.authority_token_account);
+    assert_eq!(withdrawn[0].amount, 1_000);
+
+    let new_admin = Keypair::new();
+    let transfer = Instruction {
+        program_id: solana_contracts::ID,
+        accounts: accounts::TransferAdmin { config: solana_contracts::config_pda().0, admin: admin.pubkey() }
+            .to_account_metas(None),
+        data: instruction::TransferAdmin { new_admin: new_admin.pubkey() }.data(),
+    };
+    harness.send(&[transfer], &[]).await.unwrap();
+    let proposed = harness.events::<AdminTransferProposed>();
+    assert_eq!(proposed.len(), 1);
+    assert_eq!((proposed[0].admin, proposed[0].pending_admin), (admin.pubkey(), new_admin.pubkey()));
+
+    let accept = Instruction {
+        program_id: solana_contracts::ID,
+        accounts: accounts::AcceptAdmin { config: solana_contracts::config_pda().0, new_admin: new_admin.pubkey() }
+            .to_account_metas(None),
+        data: instruction::AcceptAdmin {}.data(),
+    };
+    harness.send(&[accept], &[&new_admin]).await.unwrap();
+    let transferred = harness.events::<AdminTransferred>();
+    assert_eq!(transferred.len(), 1);
+    assert_eq!((transferred[0].previous_admin, transferred[0].new_admin), (admin.pubkey(), new_admin.pubkey()));
+    assert!(harness.events::<PausedEvent>().is_empty(), "events leaked from an earlier transaction");
+}
+
+#[tokio::test]
+async fn polls_reject_repeated_outcomes() {
+    let mut harness = Harness::new(300, 0, 3).await;
+    let outcomes = harness.outcomes.clone();
+    harness.outcomes = vec![outcomes[0], outcomes[1], outcomes[0]];
+    let err = harness.create_poll(&Keypair::new(), 300, 0, &PollOptions::default()).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::DuplicateOutcome as u32)), "{err}");
+    harness.ctx.get_new_latest_blockhash().await.unwrap();
+
+    harness.outcomes = outcomes;
+    harness.create_poll(&Keypair::new(), 300, 0, &PollOptions::default()).await.unwrap();
+}
+
+#[tokio::test]
+async fn every_vault_is_owned_by_the_pool_authority() {
+    let harness = Harness::new(300, 0, 2).await;
+    let (pool_authority, _) = Pubkey::find_program_address(&[b"pool", harness.poll.as_ref()], &solana_contracts::ID);
+    assert_eq!(harness.pool_authority, pool_authority);
+    for vault in [harness.pool_vault, harness.fee_vault, harness.authority_fee_vault] {
+        let account = harness.ctx.banks_client.get_account(vault).await.unwrap().unwrap();
+        let token_account = spl_token::state::Account::unpack(&account.data).unwrap();
+        assert_eq!(token_account.owner, pool_authority);
+        assert_eq!(token_account.mint, harness.mint);
+    }
+}
+
+#[tokio::test]
+async fn resolution_records_the_final_market_state() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    let bets = [(0, 1, 10_000_000), (1, 1, 25_000_000), (2, 2, 40_000_000)];
+    for (user, nft_choice, amount) in bets {
+        harness.vote(user, nft_choice, amount).await;
+    }
+    harness.resolve(harness.outcomes[1]).await;
+
+    let poll = harness.poll().await;
+    let events = harness.events::<MarketStateEvent>();
+    assert_eq!(events.len(), 1);
+    let state = &events[0];
+    assert_eq!((state.poll, state.winning_nft), (harness.poll, harness.outcomes[1]));
+    for index in 0..2 {
+        let staked: u64 = bets
+            .iter()
+            .filter(|&&(_, nft_choice, _)| nft_choice as usize == index + 1)
+            .map(|&(_, _, amount)| amount - harness.network_fee(amount, 300))
+            .sum();
+        assert_eq!(state.outcome_pools[index], staked);
+    }
+    assert_eq!(state.total_volume, bets.iter().map(|&(_, _, amount)| amount).sum::<u64>());
+    assert_eq!(state.vote_count, bets.len() as u64);
+    assert_eq!(state.reserves, poll.shares);
+    assert_eq!(state.probabilities.iter().sum::<u64>(), 10_000);
+    // The side most bet on is the most likely one
+    assert!(state.probabilities[1] > state.probabilities[0]);
+}
+
+#[tokio::test]
+async fn winnings_can_be_claimed_into_another_account() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    let (vote, user) = harness.votes[0];
+    let (own, custody) = (harness.users[user].1, harness.users[1].1);
+    let (own_before, custody_before) = (harness.balance(own).await, harness.balance(custody).await);
+    let owed = harness.outstanding_winnings().await;
+    harness.claim_to(vote, user, Some(custody)).await.unwrap();
+    assert_eq!(harness.balance(own).await, own_before);
+    assert_eq!(harness.balance(custody).await, custody_before + owed);
+    harness.check_invariants("claim to another account").await;
+
+    // Only the vote's owner may claim, wherever the payout goes
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+    let vote = harness.votes[0].0;
+    let err = harness.claim_to(vote, 1, Some(harness.users[1].1)).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::Unauthorized as u32)), "{err}");
+}
+
+#[tokio::test]
+async fn position_summaries_add_up_a_users_votes() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(0, 1, 5_000_000).await;
+    harness.vote(1, 2, 20_000_000).await;
+    // Both bets of user 0 went into the same position
+    assert_eq!(harness.votes.len(), 2);
+    let (first, other) = (harness.votes[0].0, harness.votes[1].0);
+    let first_account = harness.vote_account(first).await;
+
+    let user = harness.users[0].0.pubkey();
+    let get_position = |votes: &[Pubkey]| {
+        let mut accounts = accounts::GetPosition { poll: harness.poll }.to_account_metas(None);
+        accounts.extend(votes.iter().map(|&vote| AccountMeta::new_readonly(vote, false)));
+        Instruction { program_id: solana_contracts::ID, accounts, data: instruction::GetPosition { user }.data() }
+    };
+    let (summary, duplicate, foreign) =
+        (get_position(&[first]), get_position(&[first, first]), get_position(&[first, other]));
+
+    harness.send(&[summary], &[]).await.unwrap();
+    let events = harness.events::<PositionSummaryEvent>();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].poll, events[0].user), (harness.poll, user));
+    assert_eq!(events[0].shares, vec![first_account.amount, 0]);
+    assert_eq!(events[0].total_value, 15_000_000);
+    assert_eq!(events[0].total_stake, 15_000_000 - harness.network_fee(10_000_000, 300) - harness.network_fee(5_000_000, 300));
+
+    let err = harness.send(&[duplicate], &[]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::DuplicateVote as u32)), "{err}");
+    let err = harness.send(&[foreign], &[]).await.unwrap_err();
+    assert!(err.contains(&format!("{:#x}", 6000 + AmmError::InvalidVote as u32)), "{err}");
+}
+
+#[tokio::test]
+async fn the_last_winner_empties_the_stakes() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    for (user, nft_choice, amount) in [(0, 1, 3_333_333), (1, 1, 7_777_777), (2, 1, 101), (3, 2, 12_345_679)] {
+        harness.vote(user, nft_choice, amount).await;
+    }
+    harness.resolve(harness.outcomes[0]).await;
+    let pool = harness.poll().await.total_pool().unwrap();
+
+    let mut paid = 0;
+    for (vote, user) in harness.votes.clone().into_iter().filter(|&(_, user)| user != 3) {
+        let before = harness.balance(harness.users[user].1).await;
+        harness.claim(vote, user).await.unwrap();
+        paid += harness.balance(harness.users[user].1).await - before;
+    }
+    assert_eq!(paid, pool);
+    assert_eq!(harness.balance(harness.pool_vault).await, 0);
+    let poll = harness.poll().await;
+    assert_eq!((poll.vault_liability, poll.unclaimed_winners), (0, 0));
+}
+
+#[tokio::test]
+async fn flagged_polls_need_proof_of_the_winning_nft() {
+    let options = PollOptions { require_ownership_proof: true, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    let (resolver, stranger) = (harness.ctx.payer.pubkey(), harness.users[0].0.pubkey());
+    let (winner, loser) = (harness.outcomes[0], harness.outcomes[1]);
+    let code = format!("{:#x}", 6000 + AmmError::OwnershipProofRequired as u32);
+
+    let wrong_nft = harness.hold_nft(loser, resolver, 1);
+    let not_owned = harness.hold_nft(winner, stranger, 1);
+    let sold = harness.hold_nft(winner, resolver, 0);
+    for proof in [None, Some(wrong_nft), Some(not_owned), Some(sold)] {
+        let err = harness.try_resolve_with_proof(winner, proof).await.unwrap_err();
+        assert!(err.contains(&code), "{proof:?}: {err}");
+    }
+
+    let proof = harness.hold_nft(winner, resolver, 1);
+    harness.try_resolve_with_proof(winner, Some(proof)).await.unwrap();
+    assert_eq!(harness.poll().await.winning_nft, Some(winner));
+
+    // Without the flag no proof is asked for
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.resolve(harness.outcomes[0]).await;
+}
+
+#[tokio::test]
+async fn polls_can_rate_limit_each_users_bets() {
+    let options = PollOptions { min_vote_interval: 60, ..Default::default() };
+    let mut harness = Harness::with_options(300, 0, 2, options).await;
+    let clock: Clock = harness.ctx.banks_client.get_sysvar().await.unwrap();
+    harness.vote(0, 1, 10_000_000).await;
+    let vote = harness.votes[0].0;
+    assert_eq!(harness.vote_account(vote).await.last_vote_at, clock.unix_timestamp);
+
+    // Too soon: rejected, while other users may still bet
+    harness.vote(0, 1, 5_000_000).await;
+    harness.vote(1, 1, 5_000_000).await;
+    assert_eq!(harness.poll().await.vote_count, 2);
+    assert_eq!(harness.vote_account(vote).await.value, 10_000_000);
+    harness.check_invariants("rate-limited vote").await;
+
+    harness.warp_to(clock.unix_timestamp + 60).await;
+    harness.vote(0, 1, 5_000_000).await;
+    assert_eq!(harness.poll().await.vote_count, 3);
+    let position = harness.vote_account(vote).await;
+    assert_eq!((position.value, position.last_vote_at), (15_000_000, clock.unix_timestamp + 60));
+}
+
+#[tokio::test]
+async fn claim_events_record_the_entry_price_and_payout_ratio() {
+    let mut harness = Harness::new(300, 0, 2).await;
+    harness.vote(0, 1, 10_000_000).await;
+    harness.vote(1, 2, 30_000_000).await;
+    harness.resolve(harness.outcomes[0]).await;
+
+    let (vote, user) = harness.votes[0];
+    let position = harness.vote_account(vote).await;
+    let owed = harness.outstanding_winnings().await;
+    harness.claim(vote, user).await.unwrap();
+    let events = harness.events::<WinningsClaimed>();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].gross_amount, owed);
+    assert_eq!(events[0].entry_price, position.price_at_transaction);
+    assert_eq!(events[0].payout_ratio, (owed as u128 * 10000 / position.value as u128) as u64);
+    // The only winner takes both stakes, nearly four times the wager
+    assert!(events[0].payout_ratio > 38_000, "{}", events[0].payout_ratio);
+}
@@ -1,14 +1,16 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { SolanaContracts } from "../target/types/solana_contracts";
-import { Keypair, PublicKey, SystemProgram, Connection } from "@solana/web3.js";
+import { Keypair, PublicKey, SystemProgram, Connection, Transaction } from "@solana/web3.js";
 import { 
   TOKEN_PROGRAM_ID, 
   createMint, 
   getOrCreateAssociatedTokenAccount,
   mintTo,
   ASSOCIATED_TOKEN_PROGRAM_ID,
-  getAccount
+  NATIVE_MINT,
+  getAccount,
+  freezeAccount
 } from "@solana/spl-token";
 import { expect } from "chai";
 
@@ -32,21 +34,192 @@ describe("amm_voting", () => {
   let adminTokenAccount: PublicKey;
   let user1TokenAccount: PublicKey;
   let user2TokenAccount: PublicKey;
-  let feeVault: PublicKey;
   
   // Poll accounts
   let pollKeypair = Keypair.generate();
   let poll: PublicKey;
-  let vote1Keypair = Keypair.generate();
-  let vote2Keypair = Keypair.generate();
+  const vote1 = voteFor(pollKeypair.publicKey, user1.publicKey);
+  const vote2 = voteFor(pollKeypair.publicKey, user2.publicKey);
   let poolVaultAccount: PublicKey;
-  let poolAuthority: PublicKey;
-  let poolAuthorityBump: number;
+  let pollClosesAt: number;
 
   // NFT mock data
   const nft1 = Keypair.generate().publicKey;
   const nft2 = Keypair.generate().publicKey;
 
+  const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+  // No upper bet limit
+  const MAX_U64 = new anchor.BN("18446744073709551615");
+
+  // Creates a poll with default parameters; tests override only what they need
+  async function createPoll(
+    pollKp: Keypair,
+    {
+      title = Buffer.from("Test poll"),
+      closesAt = Math.floor(Date.now() / 1000) + 86400,
+      parentPoll = null,
+      requiredParentOutcome = null,
+      claimFeeBps = 0,
+      resolutionMode = { resolver: {} },
+      initialShares = new anchor.BN(1000000000),
+      feeBps = 300,
+      outcomes = [nft1, nft2],
+      minBet = new anchor.BN(1),
+      maxBet = MAX_U64,
+      metadataUri = Buffer.from(""),
+      resolver = PublicKey.default,
+      tokenMint = mint,
+      disputePeriod = 0,
+      expectedDecimals = null,
+      bonusEnabled = false,
+      authorityFeeBps = 0,
+      resolutionGracePeriod = 0,
+      requireOwnershipProof = false,
+      minVoteInterval = 0,
+      requireVotes = false,
+      minReserve = new anchor.BN(0),
+    }: {
+      title?: Buffer;
+      closesAt?: number;
+      parentPoll?: PublicKey | null;
+      requiredParentOutcome?: PublicKey | null;
+      claimFeeBps?: number;
+      resolutionMode?: object;
+      initialShares?: anchor.BN;
+      feeBps?: number;
+      outcomes?: PublicKey[];
+      minBet?: anchor.BN;
+      maxBet?: anchor.BN;
+      metadataUri?: Buffer;
+      resolver?: PublicKey;
+      tokenMint?: PublicKey;
+      disputePeriod?: number;
+      expectedDecimals?: number | null;
+      bonusEnabled?: boolean;
+      authorityFeeBps?: number;
+      resolutionGracePeriod?: number;
+      requireOwnershipProof?: boolean;
+      minVoteInterval?: number;
+      requireVotes?: boolean;
+      minReserve?: anchor.BN;
+    } = {}
+  ) {
+    await program.methods
+      .createPoll(
+        title,
+        new anchor.BN(closesAt),
+        outcomes,
+        outcomes.map(() => initialShares),
+        requiredParentOutcome,
+        claimFeeBps,
+        resolutionMode as any,
+        feeBps,
+        minBet,
+        maxBet,
+        metadataUri,
+        resolver,
+        new anchor.BN(disputePeriod),
+        expectedDecimals,
+        bonusEnabled,
+        authorityFeeBps,
+        new anchor.BN(resolutionGracePeriod),
+        requireOwnershipProof,
+        new anchor.BN(minVoteInterval),
+        requireVotes,
+        minReserve
+      )
+      .accounts({
+        poll: pollKp.publicKey,
+        authority: admin.publicKey,
+        tokenMint,
+        parentPoll,
+      })
+      .signers([admin, pollKp])
+      .rpc();
+  }
+
+  // Vault PDAs that create_poll initializes for each poll
+  function poolVaultFor(pollKey: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_vault"), pollKey.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  function authorityFeeVaultFor(pollKey: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("authority_fee_vault"), pollKey.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  function feeVaultFor(pollKey: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("fee_vault"), pollKey.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  // Liquidity provider position PDA for a (poll, provider) pair
+  function lpPositionFor(pollKey: PublicKey, providerKey: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("lp"), pollKey.toBuffer(), providerKey.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  // Each user's single Vote PDA on a poll
+  function voteFor(pollKey: PublicKey, userKey: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("vote"), pollKey.toBuffer(), userKey.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  // Approval by `beneficiary` of `delegate` crediting bets to its positions
+  function delegationFor(beneficiary: PublicKey, delegate: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("delegation"), beneficiary.toBuffer(), delegate.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  // Vote PDA of one leg of a split vote, kept apart from the regular position
+  function splitVoteFor(pollKey: PublicKey, userKey: PublicKey, nftChoice: number): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("vote"), pollKey.toBuffer(), userKey.toBuffer(), Buffer.from([nftChoice])],
+      program.programId
+    )[0];
+  }
+
+  // Casts a vote and returns the user's vote PDA
+  async function castVote(
+    pollKey: PublicKey,
+    user: Keypair,
+    userTokenAccount: PublicKey,
+    nftChoice: number,
+    amount: number,
+    minSharesOut = 0,
+    maxPriceImpactBps: number | null = null,
+    beneficiary: PublicKey | null = null
+  ): Promise<PublicKey> {
+    // The position belongs to the beneficiary when one is given
+    const vote = voteFor(pollKey, beneficiary ?? user.publicKey);
+    await program.methods
+      .vote(nftChoice, new anchor.BN(amount), new anchor.BN(minSharesOut), maxPriceImpactBps, beneficiary)
+      .accounts({
+        poll: pollKey,
+        vote,
+        delegation: beneficiary ? delegationFor(beneficiary, user.publicKey) : null,
+        user: user.publicKey,
+        userTokenAccount,
+      })
+      .signers([user])
+      .rpc();
+    return vote;
+  }
+
   before(async () => {
     // Airdrop SOL to test accounts
     try {
@@ -95,14 +268,6 @@ describe("amm_voting", () => {
         user2.publicKey
       )).address;
 
-      // Create fee vault (also using ATA)
-      feeVault = (await getOrCreateAssociatedTokenAccount(
-        provider.connection,
-        admin,
-        mint,
-        admin.publicKey
-      )).address;
-
       // Mint tokens to users
       await mintTo(
         provider.connection,
@@ -122,25 +287,16 @@ describe("amm_voting", () => {
         1000000000 // 1000 tokens
       );
 
-      // Find PDA for pool authority
-      const [poolAuthorityPDA, bump] = await PublicKey.findProgramAddress(
-        [
-          Buffer.from("pool"),
-          pollKeypair.publicKey.toBuffer(),
-        ],
-        program.programId
-      );
-      poolAuthority = poolAuthorityPDA;
-      poolAuthorityBump = bump;
+      // The pool vault is created by create_poll at its PDA
+      poolVaultAccount = poolVaultFor(pollKeypair.publicKey);
 
-      // Create pool vault using ATA
-      poolVaultAccount = (await getOrCreateAssociatedTokenAccount(
-        provider.connection,
-        admin,
-        mint,
-        poolAuthority,
-        true // allowOwnerOffCurve: true for PDA
-      )).address;
+      // The admin is pinned once in the program-wide Config; only the
+      // upgrade authority, the provider wallet that deployed, may do it
+      await program.methods
+        .initializeConfig()
+        .accounts({ admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
     } catch (error) {
       console.error("Setup error:", error);
       throw error;
@@ -152,9 +308,10 @@ describe("amm_voting", () => {
     const title = "Which NFT will be worth more?";
     const titleBytes = Buffer.from(title);
     
-    // Get current timestamp + 1 day
+    // Betting closes shortly so the poll can be resolved later in this suite
     const now = Math.floor(Date.now() / 1000);
-    const closesAt = now + 86400; // 1 day from now
+    const closesAt = now + 10;
+    pollClosesAt = closesAt;
     
     // Initial shares
     const initialNft1Shares = new anchor.BN(1000000000);
@@ -165,15 +322,31 @@ describe("amm_voting", () => {
         .createPoll(
           titleBytes,
           new anchor.BN(closesAt),
-          nft1,
-          nft2,
-          initialNft1Shares,
-          initialNft2Shares
+          [nft1, nft2],
+          [initialNft1Shares, initialNft2Shares],
+          null,
+          0,
+          { resolver: {} },
+          300,
+          new anchor.BN(1),
+          MAX_U64,
+          Buffer.from(""),
+          PublicKey.default,
+          new anchor.BN(0),
+          null,
+          false,
+          0,
+          new anchor.BN(0),
+          false,
+          new anchor.BN(0),
+          false,
+          new anchor.BN(0)
         )
         .accounts({
           poll: pollKeypair.publicKey,
           authority: admin.publicKey,
           tokenMint: mint,
+          parentPoll: null,
         })
         .signers([admin, pollKeypair])
         .rpc();
@@ -181,11 +354,11 @@ describe("amm_voting", () => {
       // Verify poll state
       const pollAccount = await program.account.poll.fetch(pollKeypair.publicKey);
       expect(pollAccount.authority.toString()).to.equal(admin.publicKey.toString());
-      expect(pollAccount.nft1.toString()).to.equal(nft1.toString());
-      expect(pollAccount.nft2.toString()).to.equal(nft2.toString());
-      expect(pollAccount.nft1Shares.toString()).to.equal(initialNft1Shares.toString());
-      expect(pollAccount.nft2Shares.toString()).to.equal(initialNft2Shares.toString());
-      expect(pollAccount.k.toString()).to.equal(initialNft1Shares.mul(initialNft2Shares).toString());
+      expect(pollAccount.outcomes.map((o) => o.toString())).to.deep.equal([nft1.toString(), nft2.toString()]);
+      expect(pollAccount.shares.map((s) => s.toString())).to.deep.equal([
+        initialNft1Shares.toString(),
+        initialNft2Shares.toString(),
+      ]);
       expect(pollAccount.status).to.deep.equal({ active: {} });
       expect(pollAccount.tokenMint.toString()).to.equal(mint.toString());
       expect(Buffer.from(pollAccount.title).toString().trim()).to.equal(title);
@@ -207,31 +380,30 @@ describe("amm_voting", () => {
       }
       
       await program.methods
-        .vote(1, amount)
+        .vote(1, amount, new anchor.BN(0), null, null)
         .accounts({
           poll: pollKeypair.publicKey,
-          vote: vote1Keypair.publicKey,
+          vote: voteFor(pollKeypair.publicKey, user1.publicKey),
+          delegation: null,
           user: user1.publicKey,
           userTokenAccount: user1TokenAccount,
-          poolVault: poolVaultAccount,
-          feeVault: feeVault,
         })
-        .signers([user1, vote1Keypair])
+        .signers([user1])
         .rpc();
       
       // Verify vote state
-      const voteAccount = await program.account.vote.fetch(vote1Keypair.publicKey);
+      const voteAccount = await program.account.vote.fetch(vote1);
       expect(voteAccount.user.toString()).to.equal(user1.publicKey.toString());
       expect(voteAccount.votedForNft).to.equal(1);
       expect(voteAccount.claimed).to.equal(false);
       
       // Verify poll state updated
       const pollAccount = await program.account.poll.fetch(pollKeypair.publicKey);
-      expect(pollAccount.nft1Shares.toString()).not.to.equal("10000"); // Should have changed
+      expect(pollAccount.shares[0].toString()).not.to.equal("1000000000"); // Should have changed
       
       // Verify tokens transferred
       const poolVaultInfo = await getAccount(provider.connection, poolVaultAccount);
-      const feeVaultInfo = await getAccount(provider.connection, feeVault);
+      const feeVaultInfo = await getAccount(provider.connection, feeVaultFor(pollKeypair.publicKey));
       
       // Fee should be 3% of 100 = 3 tokens, so vault should have 97 tokens
       expect(Number(poolVaultInfo.amount)).to.be.greaterThan(0);
@@ -248,20 +420,19 @@ describe("amm_voting", () => {
     
     try {
       await program.methods
-        .vote(2, amount)
+        .vote(2, amount, new anchor.BN(0), null, null)
         .accounts({
           poll: pollKeypair.publicKey,
-          vote: vote2Keypair.publicKey,
+          vote: voteFor(pollKeypair.publicKey, user2.publicKey),
+          delegation: null,
           user: user2.publicKey,
           userTokenAccount: user2TokenAccount,
-          poolVault: poolVaultAccount,
-          feeVault: feeVault,
         })
-        .signers([user2, vote2Keypair])
+        .signers([user2])
         .rpc();
       
       // Verify vote state
-      const voteAccount = await program.account.vote.fetch(vote2Keypair.publicKey);
+      const voteAccount = await program.account.vote.fetch(vote2);
       expect(voteAccount.user.toString()).to.equal(user2.publicKey.toString());
       expect(voteAccount.votedForNft).to.equal(2);
       expect(voteAccount.claimed).to.equal(false);
@@ -272,13 +443,16 @@ describe("amm_voting", () => {
   });
 
   it("Resolves the poll", async () => {
+    // Resolution waits for betting to close
+    await sleep((pollClosesAt + 2) * 1000 - Date.now());
     try {
       await program.methods
         .resolvePoll(nft1) // NFT1 wins
         .accounts({
           poll: pollKeypair.publicKey,
           authority: admin.publicKey,
-          admin: admin.publicKey, // Admin is the same as authority in this test
+          parentPoll: null,
+          ownershipProof: null,
         })
         .signers([admin])
         .rpc();
@@ -287,6 +461,13 @@ describe("amm_voting", () => {
       const pollAccount = await program.account.poll.fetch(pollKeypair.publicKey);
       expect(pollAccount.status).to.deep.equal({ resolved: {} });
       expect(pollAccount.winningNft.toString()).to.equal(nft1.toString());
+
+      // The settled prices follow the reserves' reciprocals, as compute_prices does
+      const scale = new anchor.BN(10).pow(new anchor.BN(30));
+      const inverses = pollAccount.shares.map((reserve) => scale.div(reserve));
+      const total = inverses.reduce((sum, inverse) => sum.add(inverse), new anchor.BN(0));
+      const expected = inverses.map((inverse) => inverse.muln(10000).div(total).toString());
+      expect(pollAccount.finalPrices.map((price) => price.toString())).to.deep.equal(expected);
     } catch (error) {
       console.error("Error resolving poll:", error);
       throw error;
@@ -297,19 +478,20 @@ describe("amm_voting", () => {
     // User1 bet on NFT1 which won, so they should be able to claim
     try {
       await program.methods
-        .claimWinnings()
+        .claimWinnings(null)
         .accounts({
           poll: pollKeypair.publicKey,
-          vote: vote1Keypair.publicKey,
+          vote: vote1,
           user: user1.publicKey,
           userTokenAccount: user1TokenAccount,
-          poolVault: poolVaultAccount
+          parentPoll: null,
+          destination: null,
         })
         .signers([user1])
         .rpc();
       
       // Verify vote marked as claimed
-      const voteAccount = await program.account.vote.fetch(vote1Keypair.publicKey);
+      const voteAccount = await program.account.vote.fetch(vote1);
       expect(voteAccount.claimed).to.equal(true);
       
       // Verify tokens transferred to user
@@ -325,13 +507,14 @@ describe("amm_voting", () => {
     // User2 bet on NFT2 which lost, so they should not be able to claim
     try {
       await program.methods
-        .claimWinnings()
+        .claimWinnings(null)
         .accounts({
           poll: pollKeypair.publicKey,
-          vote: vote2Keypair.publicKey,
+          vote: vote2,
           user: user2.publicKey,
           userTokenAccount: user2TokenAccount,
-          poolVault: poolVaultAccount
+          parentPoll: null,
+          destination: null,
         })
         .signers([user2])
         .rpc();
@@ -348,13 +531,14 @@ describe("amm_voting", () => {
     // User1 already claimed, so they should not be able to claim again
     try {
       await program.methods
-        .claimWinnings()
+        .claimWinnings(null)
         .accounts({
           poll: pollKeypair.publicKey,
-          vote: vote1Keypair.publicKey,
+          vote: vote1,
           user: user1.publicKey,
           userTokenAccount: user1TokenAccount,
-          poolVault: poolVaultAccount
+          parentPoll: null,
+          destination: null,
         })
         .signers([user1])
         .rpc();
@@ -367,6 +551,88 @@ describe("amm_voting", () => {
     }
   });
 
+  it("Rejects polls with too few or too many outcomes", async () => {
+    const tooMany = Array.from({ length: 9 }, () => Keypair.generate().publicKey);
+    for (const outcomes of [[nft1], tooMany]) {
+      const rejectedPollKeypair = Keypair.generate();
+      try {
+        await createPoll(rejectedPollKeypair, { outcomes });
+        expect.fail("Outcome count outside 2..=8 should be rejected");
+      } catch (error) {
+        expect(error.toString()).to.include("Invalid number of outcomes");
+      }
+
+      // No broken pool was created
+      const info = await provider.connection.getAccountInfo(rejectedPollKeypair.publicKey);
+      expect(info).to.equal(null);
+    }
+  });
+
+  it("Rejects polls that repeat an outcome", async () => {
+    const duplicatePollKeypair = Keypair.generate();
+    try {
+      await createPoll(duplicatePollKeypair, { outcomes: [nft1, nft2, nft1] });
+      expect.fail("Both sides of a poll cannot be the same NFT");
+    } catch (error) {
+      expect(error.toString()).to.include("Outcomes must be distinct");
+    }
+
+    const info = await provider.connection.getAccountInfo(duplicatePollKeypair.publicKey);
+    expect(info).to.equal(null);
+  });
+
+  it("Rejects initial shares at or below the locked minimum liquidity", async () => {
+    const thinPollKeypair = Keypair.generate();
+    try {
+      await createPoll(thinPollKeypair, { initialShares: new anchor.BN(1000) });
+      expect.fail("MINIMUM_LIQUIDITY of every reserve stays locked");
+    } catch (error) {
+      expect(error.toString()).to.include("Initial shares must exceed the locked minimum liquidity");
+    }
+    expect(await provider.connection.getAccountInfo(thinPollKeypair.publicKey)).to.equal(null);
+  });
+
+  it("Rejects polls that close in the past", async () => {
+    const pastPollKeypair = Keypair.generate();
+    try {
+      await createPoll(pastPollKeypair, { closesAt: Math.floor(Date.now() / 1000) - 60 });
+      expect.fail("A poll closing in the past should be rejected");
+    } catch (error) {
+      expect(error.toString()).to.include("Close time must be in the future");
+    }
+    expect(await provider.connection.getAccountInfo(pastPollKeypair.publicKey)).to.equal(null);
+  });
+
+  it("Stores a metadata URI and rejects one over 200 bytes", async () => {
+    const uri = "https://example.com/polls/" + "a".repeat(174);
+    const metadataPollKeypair = Keypair.generate();
+    await createPoll(metadataPollKeypair, { metadataUri: Buffer.from(uri) });
+    const pollAccount = await program.account.poll.fetch(metadataPollKeypair.publicKey);
+    expect(Buffer.from(pollAccount.metadataUri).toString()).to.equal(uri);
+
+    try {
+      await createPoll(Keypair.generate(), { metadataUri: Buffer.from(uri + "a") });
+      expect.fail("A URI over 200 bytes should be rejected");
+    } catch (error) {
+      expect(error.toString()).to.include("Metadata URI too long");
+    }
+  });
+
+  it("Stores the mint decimals and rejects unexpected ones", async () => {
+    const nineDecimalMint = await createMint(provider.connection, admin, admin.publicKey, null, 9);
+    const decimalsPollKeypair = Keypair.generate();
+    await createPoll(decimalsPollKeypair, { tokenMint: nineDecimalMint, expectedDecimals: 9 });
+    const pollAccount = await program.account.poll.fetch(decimalsPollKeypair.publicKey);
+    expect(pollAccount.mintDecimals).to.equal(9);
+
+    try {
+      await createPoll(Keypair.generate(), { tokenMint: nineDecimalMint, expectedDecimals: 6 });
+      expect.fail("A 9-decimal mint should be rejected when 6 are expected");
+    } catch (error) {
+      expect(error.toString()).to.include("Token mint does not have the expected decimals");
+    }
+  });
+
   it("Prevents unauthorized users from resolving", async () => {
     // Create a new poll to test with
     const newPollKeypair = Keypair.generate();
@@ -381,15 +647,31 @@ describe("amm_voting", () => {
       .createPoll(
         titleBytes,
         new anchor.BN(closesAt),
-        nft1,
-        nft2,
-        initialShares,
-        initialShares
+        [nft1, nft2],
+        [initialShares, initialShares],
+        null,
+        0,
+        { resolver: {} },
+        300,
+        new anchor.BN(1),
+        MAX_U64,
+        Buffer.from(""),
+        PublicKey.default,
+        new anchor.BN(0),
+        null,
+        false,
+        0,
+        new anchor.BN(0),
+        false,
+        new anchor.BN(0),
+        false,
+        new anchor.BN(0)
       )
       .accounts({
         poll: newPollKeypair.publicKey,
         authority: admin.publicKey,
         tokenMint: mint,
+        parentPoll: null,
       })
       .signers([admin, newPollKeypair])
       .rpc();
@@ -401,7 +683,8 @@ describe("amm_voting", () => {
         .accounts({
           poll: newPollKeypair.publicKey,
           authority: user1.publicKey,
-          admin: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
         })
         .signers([user1])
         .rpc();
@@ -412,5 +695,3564 @@ describe("amm_voting", () => {
       // Expected error
       expect(error.toString()).to.include("Unauthorized");
     }
+
+    // Nor can they bring their own admin: only the Config PDA is accepted
+    try {
+      await program.methods
+        .resolvePoll(nft1)
+        .accountsPartial({
+          poll: newPollKeypair.publicKey,
+          authority: user1.publicKey,
+          config: Keypair.generate().publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([user1])
+        .rpc();
+      expect.fail("A random account should not stand in for the Config");
+    } catch (error) {
+      expect(error.toString()).to.include("AccountNotInitialized");
+    }
+    const pollAccount = await program.account.poll.fetch(newPollKeypair.publicKey);
+    expect(pollAccount.status).to.deep.equal({ active: {} });
+  });
+
+  describe("conditional markets", () => {
+    const parentKeypair = Keypair.generate();
+    const childKeypair = Keypair.generate();
+    const voidedChildKeypair = Keypair.generate();
+    let childVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 8;
+      await createPoll(parentKeypair, { closesAt });
+      await createPoll(childKeypair, {
+        closesAt,
+        parentPoll: parentKeypair.publicKey,
+        requiredParentOutcome: nft1,
+      });
+      await createPoll(voidedChildKeypair, {
+        closesAt,
+        parentPoll: parentKeypair.publicKey,
+        requiredParentOutcome: nft2,
+      });
+
+      childVote = await castVote(childKeypair.publicKey, user1, user1TokenAccount, 1, 100000000);
+
+      await sleep((closesAt + 2) * 1000 - Date.now());
+    });
+
+    it("Records the parent condition on the child poll", async () => {
+      const child = await program.account.poll.fetch(childKeypair.publicKey);
+      expect(child.parentPoll.toString()).to.equal(parentKeypair.publicKey.toString());
+      expect(child.requiredParentOutcome.toString()).to.equal(nft1.toString());
+    });
+
+    it("Rejects resolving the child before the parent is resolved", async () => {
+      try {
+        await program.methods
+          .resolvePoll(nft1)
+          .accounts({
+            poll: childKeypair.publicKey,
+            authority: admin.publicKey,
+            parentPoll: parentKeypair.publicKey,
+            ownershipProof: null,
+          })
+          .signers([admin])
+          .rpc();
+        expect.fail("Child should not resolve before its parent");
+      } catch (error) {
+        expect(error.toString()).to.include("Parent poll is not resolved yet");
+      }
+    });
+
+    it("Resolves the parent, then the child whose condition is met", async () => {
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: parentKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: childKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: parentKeypair.publicKey,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+
+      const child = await program.account.poll.fetch(childKeypair.publicKey);
+      expect(child.status).to.deep.equal({ resolved: {} });
+      expect(child.winningNft.toString()).to.equal(nft1.toString());
+    });
+
+    it("Pays out the child poll once the parent condition holds", async () => {
+      const before = await getAccount(provider.connection, user1TokenAccount);
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: childKeypair.publicKey,
+          vote: childVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: parentKeypair.publicKey,
+          destination: null,
+        })
+        .signers([user1])
+        .rpc();
+
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      expect(Number(after.amount)).to.be.greaterThan(Number(before.amount));
+    });
+
+    it("Voids the child whose parent resolved to another outcome", async () => {
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: voidedChildKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: parentKeypair.publicKey,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+
+      const child = await program.account.poll.fetch(voidedChildKeypair.publicKey);
+      expect(child.status).to.deep.equal({ canceled: {} });
+      expect(child.winningNft).to.equal(null);
+    });
+  });
+
+  describe("conditional markets on a disputable parent", () => {
+    const parentKeypair = Keypair.generate();
+    const childKeypair = Keypair.generate();
+    const disputePeriod = 6;
+
+    const resolve = (poll: PublicKey, parentPoll: PublicKey | null) =>
+      program.methods
+        .resolvePoll(nft1)
+        .accounts({ poll, authority: admin.publicKey, parentPoll, ownershipProof: null })
+        .signers([admin])
+        .rpc();
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 8;
+      await createPoll(parentKeypair, { closesAt, disputePeriod });
+      await createPoll(childKeypair, {
+        closesAt,
+        parentPoll: parentKeypair.publicKey,
+        requiredParentOutcome: nft1,
+      });
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await resolve(parentKeypair.publicKey, null);
+    });
+
+    it("Rejects resolving the child while the parent can still be overridden", async () => {
+      try {
+        await resolve(childKeypair.publicKey, parentKeypair.publicKey);
+        expect.fail("Child should wait for the parent's dispute window");
+      } catch (error) {
+        expect(error.toString()).to.include("Parent poll is not resolved yet");
+      }
+    });
+
+    it("Resolves the child once the parent's dispute window has passed", async () => {
+      const parent = await program.account.poll.fetch(parentKeypair.publicKey);
+      await sleep((parent.resolvedAt.toNumber() + disputePeriod + 2) * 1000 - Date.now());
+      await resolve(childKeypair.publicKey, parentKeypair.publicKey);
+
+      const child = await program.account.poll.fetch(childKeypair.publicKey);
+      expect(child.status).to.deep.equal({ resolved: {} });
+    });
+  });
+
+  describe("batch close", () => {
+    const expiredKeypair = Keypair.generate();
+    const activeKeypair = Keypair.generate();
+    const alreadyClosedKeypair = Keypair.generate();
+
+    before(async () => {
+      const now = Math.floor(Date.now() / 1000);
+      await createPoll(expiredKeypair, { closesAt: now + 2 });
+      await createPoll(alreadyClosedKeypair, { closesAt: now + 2 });
+      await createPoll(activeKeypair);
+      await sleep(4000);
+
+      // Close one poll up front so the main batch contains a closed poll
+      await program.methods
+        .batchClose()
+        .accounts({ caller: admin.publicKey })
+        .remainingAccounts([
+          { pubkey: alreadyClosedKeypair.publicKey, isWritable: true, isSigner: false },
+        ])
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Closes only expired active polls and skips the rest", async () => {
+      await program.methods
+        .batchClose()
+        .accounts({ caller: admin.publicKey })
+        .remainingAccounts(
+          [expiredKeypair, activeKeypair, alreadyClosedKeypair].map((kp) => ({
+            pubkey: kp.publicKey,
+            isWritable: true,
+            isSigner: false,
+          }))
+        )
+        .signers([admin])
+        .rpc();
+
+      const expired = await program.account.poll.fetch(expiredKeypair.publicKey);
+      const active = await program.account.poll.fetch(activeKeypair.publicKey);
+      const alreadyClosed = await program.account.poll.fetch(alreadyClosedKeypair.publicKey);
+      expect(expired.status).to.deep.equal({ closed: {} });
+      expect(active.status).to.deep.equal({ active: {} });
+      expect(alreadyClosed.status).to.deep.equal({ closed: {} });
+    });
+  });
+
+  describe("claim fee", () => {
+    const feePollKeypair = Keypair.generate();
+    let feeVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(feePollKeypair, { closesAt, claimFeeBps: 500 });
+
+      feeVote = await castVote(feePollKeypair.publicKey, user1, user1TokenAccount, 1, 100000000);
+
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: feePollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Rejects a claim fee above 10%", async () => {
+      try {
+        await createPoll(Keypair.generate(), { claimFeeBps: 1001 });
+        expect.fail("Claim fee above the cap should be rejected");
+      } catch (error) {
+        expect(error.toString()).to.include("Fee too high");
+      }
+    });
+
+    it("Pays the winner net of the claim fee", async () => {
+      // The only winner takes the whole staked pool
+      const pollAccount = await program.account.poll.fetch(feePollKeypair.publicKey);
+      const gross = pollAccount.outcomePools.reduce((a, b) => a.add(b)).toNumber();
+      const fee = Math.floor((gross * 500) / 10000);
+
+      const userBefore = await getAccount(provider.connection, user1TokenAccount);
+      const feeVaultBefore = await getAccount(provider.connection, feeVaultFor(feePollKeypair.publicKey));
+
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: feePollKeypair.publicKey,
+          vote: feeVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: null,
+          destination: null,
+        })
+        .signers([user1])
+        .rpc();
+
+      const userAfter = await getAccount(provider.connection, user1TokenAccount);
+      const feeVaultAfter = await getAccount(provider.connection, feeVaultFor(feePollKeypair.publicKey));
+      expect(Number(userAfter.amount) - Number(userBefore.amount)).to.equal(gross - fee);
+      expect(Number(feeVaultAfter.amount) - Number(feeVaultBefore.amount)).to.equal(fee);
+    });
+  });
+
+  describe("consensus resolution", () => {
+    const majorityKeypair = Keypair.generate();
+    const tieKeypair = Keypair.generate();
+    let closesAt: number;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 15;
+      for (const kp of [majorityKeypair, tieKeypair]) {
+        await createPoll(kp, { closesAt, resolutionMode: { consensus: {} } });
+      }
+
+      await castVote(majorityKeypair.publicKey, user1, user1TokenAccount, 1, 20000000);
+      await castVote(majorityKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+
+      await castVote(tieKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await castVote(tieKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+    });
+
+    it("Rejects consensus resolution while the poll is open", async () => {
+      try {
+        await program.methods
+          .resolveByConsensus()
+          .accounts({ poll: majorityKeypair.publicKey, caller: user1.publicKey, parentPoll: null })
+          .signers([user1])
+          .rpc();
+        expect.fail("Consensus resolution should wait for closes_at");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is still open");
+      }
+    });
+
+    it("Rejects resolver-based resolution of a consensus poll", async () => {
+      try {
+        await program.methods
+          .resolvePoll(nft1)
+          .accounts({
+            poll: majorityKeypair.publicKey,
+            authority: admin.publicKey,
+            parentPoll: null,
+            ownershipProof: null,
+          })
+          .signers([admin])
+          .rpc();
+        expect.fail("Consensus polls cannot be resolved by the authority");
+      } catch (error) {
+        expect(error.toString()).to.include("Not allowed for this poll's resolution mode");
+      }
+    });
+
+    it("Resolves to the side with the larger stake", async () => {
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolveByConsensus()
+        .accounts({ poll: majorityKeypair.publicKey, caller: user2.publicKey, parentPoll: null })
+        .signers([user2])
+        .rpc();
+
+      const pollAccount = await program.account.poll.fetch(majorityKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ resolved: {} });
+      expect(pollAccount.winningNft.toString()).to.equal(nft1.toString());
+      expect(pollAccount.outcomePools[0].gt(pollAccount.outcomePools[1])).to.equal(true);
+    });
+
+    it("Voids the poll on an exact tie", async () => {
+      await program.methods
+        .resolveByConsensus()
+        .accounts({ poll: tieKeypair.publicKey, caller: user1.publicKey, parentPoll: null })
+        .signers([user1])
+        .rpc();
+
+      const pollAccount = await program.account.poll.fetch(tieKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ canceled: {} });
+      expect(pollAccount.winningNft).to.equal(null);
+    });
+  });
+
+  describe("price accumulator", () => {
+    const twapPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(twapPollKeypair);
+      // Let a few slots pass so the first vote has elapsed time to accumulate
+      await sleep(2000);
+    });
+
+    it("Weights same-slot votes only once", async () => {
+      const before = await program.account.poll.fetch(twapPollKeypair.publicKey);
+      // Same formula as the program: prices are proportional to 1 / reserve
+      const scale = new anchor.BN(10).pow(new anchor.BN(30));
+      const inverses = before.shares.map((reserve) => scale.div(reserve));
+      const priceBefore = inverses[0].muln(10000).div(inverses.reduce((a, b) => a.add(b)));
+
+      // Two large votes packed into a single transaction land in the same slot
+      const tx = new Transaction();
+      for (let i = 0; i < 2; i++) {
+        tx.add(
+          await program.methods
+            .vote(1, new anchor.BN(200000000), new anchor.BN(0), null, null)
+            .accounts({
+              poll: twapPollKeypair.publicKey,
+              vote: voteFor(twapPollKeypair.publicKey, user1.publicKey),
+              delegation: null,
+              user: user1.publicKey,
+              userTokenAccount: user1TokenAccount,
+            })
+            .instruction()
+        );
+      }
+      await provider.sendAndConfirm(tx, [user1]);
+
+      const after = await program.account.poll.fetch(twapPollKeypair.publicKey);
+      const elapsedSlots = after.lastUpdateSlot.sub(before.lastUpdateSlot);
+      expect(elapsedSlots.gtn(0)).to.equal(true);
+
+      // Only the price in effect before the transaction was accumulated; the
+      // price moved by the first vote was not weighted in the same slot
+      const accumulated = after.priceCumulative[0].sub(before.priceCumulative[0]);
+      expect(accumulated.toString()).to.equal(priceBefore.mul(elapsedSlots).toString());
+    });
+  });
+
+  describe("configurable fee", () => {
+    const feeTierKeypair = Keypair.generate();
+
+    it("Rejects a network fee above 10%", async () => {
+      try {
+        await createPoll(Keypair.generate(), { feeBps: 1001 });
+        expect.fail("Fee above the cap should be rejected");
+      } catch (error) {
+        expect(error.toString()).to.include("Fee too high");
+      }
+    });
+
+    it("Charges the poll's fee tier on votes", async () => {
+      await createPoll(feeTierKeypair, { feeBps: 50 });
+      const vault = poolVaultFor(feeTierKeypair.publicKey);
+
+      const amount = 100000000;
+      const feeVaultBefore = await getAccount(provider.connection, feeVaultFor(feeTierKeypair.publicKey));
+      await castVote(feeTierKeypair.publicKey, user1, user1TokenAccount, 1, amount);
+      const feeVaultAfter = await getAccount(provider.connection, feeVaultFor(feeTierKeypair.publicKey));
+
+      const expectedFee = Math.floor((amount * 50) / 10000);
+      expect(Number(feeVaultAfter.amount) - Number(feeVaultBefore.amount)).to.equal(expectedFee);
+
+      const vaultInfo = await getAccount(provider.connection, vault);
+      expect(Number(vaultInfo.amount)).to.equal(amount - expectedFee);
+    });
+  });
+
+  describe("close poll", () => {
+    const closingKeypair = Keypair.generate();
+    let closesAt: number;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(closingKeypair, { closesAt });
+    });
+
+    it("Rejects closing before closes_at", async () => {
+      try {
+        await program.methods
+          .closePoll()
+          .accounts({ poll: closingKeypair.publicKey, caller: user1.publicKey })
+          .signers([user1])
+          .rpc();
+        expect.fail("Poll should not close before closes_at");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is still open");
+      }
+    });
+
+    it("Closes the poll after closes_at", async () => {
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      const events: any[] = [];
+      const listener = program.addEventListener("pollClosedEvent", (e) => {
+        events.push(e);
+      });
+      await program.methods
+        .closePoll()
+        .accounts({ poll: closingKeypair.publicKey, caller: user1.publicKey })
+        .signers([user1])
+        .rpc();
+      for (let i = 0; i < 20 && events.length < 1; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      // A keeper other than the authority closed it
+      expect(events[0].closedBy.toString()).to.equal(user1.publicKey.toString());
+      expect(events[0].auto).to.equal(true);
+
+      const pollAccount = await program.account.poll.fetch(closingKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ closed: {} });
+      expect(pollAccount.closedAt.toNumber()).to.be.at.least(closesAt);
+      expect(pollAccount.resolvedAt.toNumber()).to.equal(0);
+    });
+
+    it("Rejects votes on a closed poll", async () => {
+      try {
+        await castVote(closingKeypair.publicKey, user2, user2TokenAccount, 1, 10000000);
+        expect.fail("Votes on a closed poll should be rejected");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is closed");
+      }
+    });
+
+    it("Records a timestamp at each lifecycle transition", async () => {
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: closingKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+
+      const pollAccount = await program.account.poll.fetch(closingKeypair.publicKey);
+      const createdAt = pollAccount.createdAt.toNumber();
+      expect(createdAt).to.be.greaterThan(0);
+      expect(createdAt).to.be.below(closesAt);
+      expect(pollAccount.closedAt.toNumber()).to.be.at.least(closesAt);
+      expect(pollAccount.resolvedAt.toNumber()).to.be.at.least(pollAccount.closedAt.toNumber());
+    });
+  });
+
+  describe("proportional payouts", () => {
+    const splitPollKeypair = Keypair.generate();
+    const loser = Keypair.generate();
+    let loserTokenAccount: PublicKey;
+    let splitVault: PublicKey;
+    let smallVote: PublicKey;
+    let largeVote: PublicKey;
+
+    before(async () => {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(loser.publicKey, 1000000000),
+        "confirmed"
+      );
+      loserTokenAccount = (await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin,
+        mint,
+        loser.publicKey
+      )).address;
+      await mintTo(provider.connection, admin, mint, loserTokenAccount, admin.publicKey, 1000000000);
+
+      const closesAt = Math.floor(Date.now() / 1000) + 8;
+      await createPoll(splitPollKeypair, { closesAt });
+      splitVault = poolVaultFor(splitPollKeypair.publicKey);
+      smallVote = await castVote(splitPollKeypair.publicKey, user1, user1TokenAccount, 1, 20000000);
+      largeVote = await castVote(splitPollKeypair.publicKey, user2, user2TokenAccount, 1, 40000000);
+      await castVote(splitPollKeypair.publicKey, loser, loserTokenAccount, 2, 90000000);
+
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: splitPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Splits the whole pool between winners by shares", async () => {
+      const pollAccount = await program.account.poll.fetch(splitPollKeypair.publicKey);
+      const totalPool = pollAccount.outcomePools.reduce((a, b) => a.add(b));
+      expect(pollAccount.winningSideShares.toString()).to.equal(pollAccount.voteShares[0].toString());
+
+      for (const [voteKey, user, tokenAccount] of [
+        [smallVote, user1, user1TokenAccount],
+        [largeVote, user2, user2TokenAccount],
+      ] as [PublicKey, Keypair, PublicKey][]) {
+        const voteAccount = await program.account.vote.fetch(voteKey);
+        // The last winner to claim takes what is left of the stakes
+        const current = await program.account.poll.fetch(splitPollKeypair.publicKey);
+        const expected =
+          current.unclaimedWinners === 1
+            ? current.vaultLiability
+            : voteAccount.amount.mul(totalPool).div(pollAccount.winningSideShares);
+
+        const before = await getAccount(provider.connection, tokenAccount);
+        await program.methods
+          .claimWinnings(null)
+          .accounts({
+            poll: splitPollKeypair.publicKey,
+            vote: voteKey,
+            user: user.publicKey,
+            userTokenAccount: tokenAccount,
+            parentPoll: null,
+            destination: null,
+          })
+          .signers([user])
+          .rpc();
+        const after = await getAccount(provider.connection, tokenAccount);
+        expect(Number(after.amount) - Number(before.amount)).to.equal(expected.toNumber());
+      }
+
+      // No rounding dust is left behind
+      const vaultInfo = await getAccount(provider.connection, splitVault);
+      expect(Number(vaultInfo.amount)).to.equal(0);
+      const settled = await program.account.poll.fetch(splitPollKeypair.publicKey);
+      expect(settled.vaultLiability.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("refunds", () => {
+    const refundPollKeypair = Keypair.generate();
+    let refundVote: PublicKey;
+    let balanceBeforeVote: number;
+
+    before(async () => {
+      await createPoll(refundPollKeypair);
+
+      balanceBeforeVote = Number((await getAccount(provider.connection, user1TokenAccount)).amount);
+      refundVote = await castVote(refundPollKeypair.publicKey, user1, user1TokenAccount, 1, 100000000);
+
+      await program.methods
+        .cancelPoll(0)
+        .accounts({
+          poll: refundPollKeypair.publicKey,
+          authority: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    async function claimRefund() {
+      await program.methods
+        .claimRefund()
+        .accounts({
+          poll: refundPollKeypair.publicKey,
+          vote: refundVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+        })
+        .signers([user1])
+        .rpc();
+    }
+
+    it("Refunds the stake of a canceled poll", async () => {
+      await claimRefund();
+
+      // Everything but the 3% network fee comes back
+      const fee = Math.floor((100000000 * 300) / 10000);
+      const balance = Number((await getAccount(provider.connection, user1TokenAccount)).amount);
+      expect(balance).to.equal(balanceBeforeVote - fee);
+
+      const voteAccount = await program.account.vote.fetch(refundVote);
+      expect(voteAccount.claimed).to.equal(true);
+    });
+
+    it("Prevents a second refund", async () => {
+      try {
+        await claimRefund();
+        expect.fail("Refund should only be paid once");
+      } catch (error) {
+        expect(error.toString()).to.include("Winnings already claimed");
+      }
+    });
+  });
+
+  describe("fee refunds on cancel", () => {
+    const feeRefundPollKeypair = Keypair.generate();
+    let feeRefundVote: PublicKey;
+    let balanceBeforeVote: number;
+
+    async function setRefundFeesOnCancel(refundFeesOnCancel: boolean) {
+      await program.methods
+        .setRefundFeesOnCancel(refundFeesOnCancel)
+        .accounts({ admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    }
+
+    before(async () => {
+      await setRefundFeesOnCancel(true);
+      await createPoll(feeRefundPollKeypair);
+      balanceBeforeVote = Number((await getAccount(provider.connection, user1TokenAccount)).amount);
+      feeRefundVote = await castVote(feeRefundPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await program.methods
+        .cancelPoll(0)
+        .accounts({ poll: feeRefundPollKeypair.publicKey, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    after(async () => {
+      await setRefundFeesOnCancel(false);
+    });
+
+    it("Returns the network fee along with the stake", async () => {
+      await program.methods
+        .claimRefund()
+        .accounts({
+          poll: feeRefundPollKeypair.publicKey,
+          vote: feeRefundVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+        })
+        .signers([user1])
+        .rpc();
+
+      // The whole fee went to the protocol, so the whole bet comes back
+      const balance = Number((await getAccount(provider.connection, user1TokenAccount)).amount);
+      expect(balance).to.equal(balanceBeforeVote);
+      const feeVaultInfo = await getAccount(provider.connection, feeVaultFor(feeRefundPollKeypair.publicKey));
+      expect(Number(feeVaultInfo.amount)).to.equal(0);
+    });
+  });
+
+  describe("vault PDAs", () => {
+    const vaultPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(vaultPollKeypair);
+    });
+
+    it("Creates every vault owned by the pool authority", async () => {
+      const [authority] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pool"), vaultPollKeypair.publicKey.toBuffer()],
+        program.programId
+      );
+      const vaults = [
+        poolVaultFor(vaultPollKeypair.publicKey),
+        feeVaultFor(vaultPollKeypair.publicKey),
+        authorityFeeVaultFor(vaultPollKeypair.publicKey),
+      ];
+      for (const vault of vaults) {
+        const info = await getAccount(provider.connection, vault);
+        expect(info.owner.toString()).to.equal(authority.toString());
+        expect(info.mint.toString()).to.equal(mint.toString());
+      }
+    });
+
+    it("Rejects a vote into a foreign pool vault", async () => {
+      try {
+        await program.methods
+          .vote(1, new anchor.BN(10000000), new anchor.BN(0), null, null)
+          .accountsPartial({
+            poll: vaultPollKeypair.publicKey,
+            vote: voteFor(vaultPollKeypair.publicKey, user1.publicKey),
+            delegation: null,
+            user: user1.publicKey,
+            userTokenAccount: user1TokenAccount,
+            poolVault: adminTokenAccount,
+            feeVault: feeVaultFor(vaultPollKeypair.publicKey),
+          })
+          .signers([user1])
+          .rpc();
+        expect.fail("A vault not derived from the poll should be rejected");
+      } catch (error) {
+        expect(error.toString()).to.include("ConstraintSeeds");
+      }
+    });
+
+    it("Rejects the pool vault passed as the fee vault", async () => {
+      // Both vaults are PDAs with distinct seeds, so they can never alias
+      try {
+        await program.methods
+          .vote(1, new anchor.BN(10000000), new anchor.BN(0), null, null)
+          .accountsPartial({
+            poll: vaultPollKeypair.publicKey,
+            vote: voteFor(vaultPollKeypair.publicKey, user1.publicKey),
+            delegation: null,
+            user: user1.publicKey,
+            userTokenAccount: user1TokenAccount,
+            poolVault: poolVaultFor(vaultPollKeypair.publicKey),
+            feeVault: poolVaultFor(vaultPollKeypair.publicKey),
+          })
+          .signers([user1])
+          .rpc();
+        expect.fail("The fee and stake must land in separate vaults");
+      } catch (error) {
+        expect(error.toString()).to.include("ConstraintSeeds");
+      }
+    });
+  });
+
+  describe("slippage protection", () => {
+    const slippagePollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(slippagePollKeypair);
+    });
+
+    it("Reverts a vote that cannot receive min_shares_out", async () => {
+      const userBefore = await getAccount(provider.connection, user2TokenAccount);
+      const vaultBefore = await getAccount(provider.connection, poolVaultFor(slippagePollKeypair.publicKey));
+
+      // After the fee and price impact, 10 tokens buy fewer than 10 shares
+      const amount = 10000000;
+      try {
+        await castVote(slippagePollKeypair.publicKey, user2, user2TokenAccount, 1, amount, amount);
+        expect.fail("Vote below min_shares_out should revert");
+      } catch (error) {
+        expect(error.toString()).to.include("Slippage exceeded");
+      }
+
+      const userAfter = await getAccount(provider.connection, user2TokenAccount);
+      const vaultAfter = await getAccount(provider.connection, poolVaultFor(slippagePollKeypair.publicKey));
+      expect(userAfter.amount.toString()).to.equal(userBefore.amount.toString());
+      expect(vaultAfter.amount.toString()).to.equal(vaultBefore.amount.toString());
+    });
+  });
+
+  describe("multi-outcome polls", () => {
+    const threeWayKeypair = Keypair.generate();
+    const nft3 = Keypair.generate().publicKey;
+    let middleVote: PublicKey;
+    let closesAt: number;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 15;
+      await createPoll(threeWayKeypair, { closesAt, outcomes: [nft1, nft2, nft3] });
+    });
+
+    it("Creates a poll with three outcomes", async () => {
+      const pollAccount = await program.account.poll.fetch(threeWayKeypair.publicKey);
+      expect(pollAccount.outcomes.map((o) => o.toString())).to.deep.equal(
+        [nft1, nft2, nft3].map((o) => o.toString())
+      );
+      expect(pollAccount.shares.length).to.equal(3);
+    });
+
+    it("Votes on the middle outcome", async () => {
+      middleVote = await castVote(threeWayKeypair.publicKey, user1, user1TokenAccount, 2, 100000000);
+      await castVote(threeWayKeypair.publicKey, user2, user2TokenAccount, 3, 50000000);
+
+      const pollAccount = await program.account.poll.fetch(threeWayKeypair.publicKey);
+      const voteAccount = await program.account.vote.fetch(middleVote);
+      expect(voteAccount.votedForNft).to.equal(2);
+      expect(pollAccount.voteShares[1].toString()).to.equal(voteAccount.amount.toString());
+      // The stake was added to the other reserves and the middle one shrank
+      expect(pollAccount.shares[1].lt(pollAccount.shares[0])).to.equal(true);
+    });
+
+    it("Rejects a choice past the last outcome", async () => {
+      try {
+        await castVote(threeWayKeypair.publicKey, user1, user1TokenAccount, 4, 10000000);
+        expect.fail("Choice 4 does not exist on a three-way poll");
+      } catch (error) {
+        expect(error.toString()).to.include("Invalid NFT choice");
+      }
+    });
+
+    it("Resolves to the middle outcome and pays its voter the whole pool", async () => {
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft2)
+        .accounts({
+          poll: threeWayKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+
+      const pollAccount = await program.account.poll.fetch(threeWayKeypair.publicKey);
+      expect(pollAccount.winningNft.toString()).to.equal(nft2.toString());
+      const totalPool = pollAccount.outcomePools.reduce((a, b) => a.add(b));
+
+      const before = await getAccount(provider.connection, user1TokenAccount);
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: threeWayKeypair.publicKey,
+          vote: middleVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: null,
+          destination: null,
+        })
+        .signers([user1])
+        .rpc();
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      expect(Number(after.amount) - Number(before.amount)).to.equal(totalPool.toNumber());
+    });
+  });
+
+  describe("remove liquidity", () => {
+    const liquidityPollKeypair = Keypair.generate();
+    const deposit = [new anchor.BN(40000000), new anchor.BN(40000000)];
+
+    before(async () => {
+      await createPoll(liquidityPollKeypair);
+      await mintTo(provider.connection, admin, mint, adminTokenAccount, admin.publicKey, 100000000);
+    });
+
+    async function removeLiquidity(
+      amounts: anchor.BN[],
+      authority: Keypair,
+      tokenAccount: PublicKey,
+      lpPosition: PublicKey | null = null
+    ) {
+      await program.methods
+        .removeLiquidity(amounts)
+        .accountsPartial({
+          poll: liquidityPollKeypair.publicKey,
+          authority: authority.publicKey,
+          authorityTokenAccount: tokenAccount,
+          lpPosition,
+        })
+        .signers([authority])
+        .rpc();
+    }
+
+    it("Returns reserves and tokens to where they started", async () => {
+      const pollBefore = await program.account.poll.fetch(liquidityPollKeypair.publicKey);
+      const balanceBefore = await getAccount(provider.connection, adminTokenAccount);
+
+      await program.methods
+        .addLiquidity(deposit)
+        .accounts({
+          poll: liquidityPollKeypair.publicKey,
+          user: admin.publicKey,
+          userTokenAccount: adminTokenAccount,
+        })
+        .signers([admin])
+        .rpc();
+      const pollAdded = await program.account.poll.fetch(liquidityPollKeypair.publicKey);
+      expect(pollAdded.liquidity.toString()).to.equal("80000000");
+
+      const lpPosition = lpPositionFor(liquidityPollKeypair.publicKey, admin.publicKey);
+      expect((await program.account.lpPosition.fetch(lpPosition)).deposited.toString()).to.equal("80000000");
+      await removeLiquidity(deposit, admin, adminTokenAccount, lpPosition);
+
+      const pollAfter = await program.account.poll.fetch(liquidityPollKeypair.publicKey);
+      const balanceAfter = await getAccount(provider.connection, adminTokenAccount);
+      expect(pollAfter.shares.map((r) => r.toString())).to.deep.equal(
+        pollBefore.shares.map((r) => r.toString())
+      );
+      expect(pollAfter.liquidity.toString()).to.equal("0");
+      expect(balanceAfter.amount.toString()).to.equal(balanceBefore.amount.toString());
+    });
+
+    it("Rejects withdrawals by anyone but the authority", async () => {
+      try {
+        await removeLiquidity([new anchor.BN(1), new anchor.BN(0)], user1, user1TokenAccount);
+        expect.fail("Only the authority or admin can remove liquidity");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("Rejects withdrawing the virtual initial shares", async () => {
+      try {
+        await removeLiquidity([new anchor.BN(1000000), new anchor.BN(0)], admin, adminTokenAccount);
+        expect.fail("Only deposited liquidity can be withdrawn");
+      } catch (error) {
+        expect(error.toString()).to.include("Not enough liquidity");
+      }
+    });
+  });
+
+  describe("designated resolver", () => {
+    const oraclePollKeypair = Keypair.generate();
+    const oracle = Keypair.generate();
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(oraclePollKeypair, { closesAt, resolver: oracle.publicKey });
+      await sleep((closesAt + 2) * 1000 - Date.now());
+    });
+
+    async function resolveAs(signer: Keypair) {
+      await program.methods
+        .resolvePoll(nft2)
+        .accounts({
+          poll: oraclePollKeypair.publicKey,
+          authority: signer.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([signer])
+        .rpc();
+    }
+
+    it("Defaults the resolver to the poll authority", async () => {
+      const pollAccount = await program.account.poll.fetch(pollKeypair.publicKey);
+      expect(pollAccount.resolver.toString()).to.equal(admin.publicKey.toString());
+    });
+
+    it("Rejects an unrelated signer", async () => {
+      try {
+        await resolveAs(user1);
+        expect.fail("Only the authority, resolver or admin may resolve");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("Lets the designated resolver settle the poll", async () => {
+      await resolveAs(oracle);
+      const pollAccount = await program.account.poll.fetch(oraclePollKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ resolved: {} });
+      expect(pollAccount.winningNft.toString()).to.equal(nft2.toString());
+    });
+  });
+
+  describe("liquidity after settlement", () => {
+    const settledLiquidityKeypair = Keypair.generate();
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(settledLiquidityKeypair, { closesAt });
+      await castVote(settledLiquidityKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: settledLiquidityKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Rejects liquidity for a resolved poll", async () => {
+      const vault = poolVaultFor(settledLiquidityKeypair.publicKey);
+      const vaultBefore = await getAccount(provider.connection, vault);
+      try {
+        await program.methods
+          .addLiquidity([new anchor.BN(1000000), new anchor.BN(1000000)])
+          .accounts({
+            poll: settledLiquidityKeypair.publicKey,
+            user: admin.publicKey,
+            userTokenAccount: adminTokenAccount,
+          })
+          .signers([admin])
+          .rpc();
+        expect.fail("The poll is already resolved");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is not active");
+      }
+      const vaultAfter = await getAccount(provider.connection, vault);
+      expect(Number(vaultAfter.amount)).to.equal(Number(vaultBefore.amount));
+    });
+  });
+
+  describe("vote events", () => {
+    const eventPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(eventPollKeypair);
+    });
+
+    it("Emits VoteCastEvent with the post-swap reserves", async () => {
+      let event: any = null;
+      const listener = program.addEventListener("voteCastEvent", (e) => {
+        if (e.poll.equals(eventPollKeypair.publicKey)) {
+          event = e;
+        }
+      });
+
+      const voteKey = await castVote(eventPollKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+      for (let i = 0; i < 20 && event === null; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      const pollAccount = await program.account.poll.fetch(eventPollKeypair.publicKey);
+      const voteAccount = await program.account.vote.fetch(voteKey);
+      expect(event).to.not.equal(null);
+      expect(event.user.toString()).to.equal(user2.publicKey.toString());
+      expect(event.votedForNft).to.equal(2);
+      expect(event.value.toString()).to.equal("10000000");
+      // amount * fee_bps / 10000 at the default 3%
+      expect(event.fee.toNumber()).to.equal((10000000 * pollAccount.feeBps) / 10000);
+      expect(event.fee.toNumber()).to.equal(300000);
+      expect(event.received.toString()).to.equal(voteAccount.amount.toString());
+      expect(event.priceAtTransaction.toString()).to.equal(voteAccount.priceAtTransaction.toString());
+      expect(event.createdAt.toString()).to.equal(voteAccount.createdAt.toString());
+      expect(voteAccount.createdAt.toNumber()).to.be.greaterThan(0);
+      expect(event.newShares.map((r) => r.toString())).to.deep.equal(
+        pollAccount.shares.map((r) => r.toString())
+      );
+    });
+
+    it("Emits a PriceUpdateEvent snapshot after each vote", async () => {
+      let event: any = null;
+      const listener = program.addEventListener("priceUpdateEvent", (e) => {
+        if (e.poll.equals(eventPollKeypair.publicKey)) {
+          event = e;
+        }
+      });
+
+      await castVote(eventPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      for (let i = 0; i < 20 && event === null; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      const pollAccount = await program.account.poll.fetch(eventPollKeypair.publicKey);
+      expect(event).to.not.equal(null);
+      expect(event.shares.map((r) => r.toString())).to.deep.equal(pollAccount.shares.map((r) => r.toString()));
+      // Prices are rounded down, so they sum to just under 10000
+      const total = event.prices.reduce((sum, price) => sum + price.toNumber(), 0);
+      expect(total).to.be.within(9999, 10000);
+    });
+  });
+
+  describe("vote accounts", () => {
+    const positionPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(positionPollKeypair);
+    });
+
+    it("Reuses the same PDA and accumulates repeated bets", async () => {
+      const first = await castVote(positionPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      const afterFirst = await program.account.vote.fetch(first);
+      const second = await castVote(positionPollKeypair.publicKey, user1, user1TokenAccount, 1, 20000000);
+      const afterSecond = await program.account.vote.fetch(second);
+
+      expect(second.toString()).to.equal(first.toString());
+      expect(first.toString()).to.equal(voteFor(positionPollKeypair.publicKey, user1.publicKey).toString());
+      expect(afterSecond.value.toNumber()).to.equal(30000000);
+      expect(afterSecond.amount.gt(afterFirst.amount)).to.equal(true);
+
+      const pollAccount = await program.account.poll.fetch(positionPollKeypair.publicKey);
+      expect(afterSecond.amount.toString()).to.equal(pollAccount.voteShares[0].toString());
+      expect(afterSecond.createdAt.toString()).to.equal(afterFirst.createdAt.toString());
+    });
+
+    it("Rejects a bet on another outcome by the same user", async () => {
+      try {
+        await castVote(positionPollKeypair.publicKey, user1, user1TokenAccount, 2, 10000000);
+        expect.fail("The user already backs another outcome");
+      } catch (error) {
+        expect(error.toString()).to.include("Already voted for another outcome");
+      }
+    });
+  });
+
+  describe("volume counters", () => {
+    const volumePollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(volumePollKeypair, { outcomes: [nft1, nft2, Keypair.generate().publicKey] });
+    });
+
+    it("Counts each bet and its full amount", async () => {
+      const amounts = [10000000, 25000000, 40000000];
+      await castVote(volumePollKeypair.publicKey, user1, user1TokenAccount, 1, amounts[0]);
+      await castVote(volumePollKeypair.publicKey, user2, user2TokenAccount, 2, amounts[1]);
+      await castVote(volumePollKeypair.publicKey, user1, user1TokenAccount, 1, amounts[2]);
+
+      const pollAccount = await program.account.poll.fetch(volumePollKeypair.publicKey);
+      expect(pollAccount.voteCount.toNumber()).to.equal(3);
+      expect(pollAccount.totalVolume.toNumber()).to.equal(amounts.reduce((a, b) => a + b));
+    });
+  });
+
+  describe("resolution timing", () => {
+    const expiringKeypair = Keypair.generate();
+    const closedKeypair = Keypair.generate();
+    let closesAt: number;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(expiringKeypair, { closesAt });
+      await createPoll(closedKeypair, { closesAt });
+    });
+
+    async function resolve(pollKey: PublicKey) {
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: pollKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    }
+
+    it("Rejects resolution while betting is open", async () => {
+      try {
+        await resolve(expiringKeypair.publicKey);
+        expect.fail("Poll should not resolve before closes_at");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is still open");
+      }
+    });
+
+    it("Resolves an active poll once closes_at has passed", async () => {
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await resolve(expiringKeypair.publicKey);
+
+      const pollAccount = await program.account.poll.fetch(expiringKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ resolved: {} });
+    });
+
+    it("Resolves a poll after close_poll", async () => {
+      await program.methods
+        .closePoll()
+        .accounts({ poll: closedKeypair.publicKey, caller: user1.publicKey })
+        .signers([user1])
+        .rpc();
+      await resolve(closedKeypair.publicKey);
+
+      const pollAccount = await program.account.poll.fetch(closedKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ resolved: {} });
+    });
+  });
+
+  describe("close vote", () => {
+    const settledPollKeypair = Keypair.generate();
+    let winningVote: PublicKey;
+    let losingVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(settledPollKeypair, { closesAt });
+      winningVote = await castVote(settledPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      losingVote = await castVote(settledPollKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: settledPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    async function closeVote(vote: PublicKey, user: Keypair) {
+      await program.methods
+        .closeVote()
+        .accounts({ poll: settledPollKeypair.publicKey, vote, user: user.publicKey })
+        .signers([user])
+        .rpc();
+    }
+
+    it("Keeps an unclaimed winning vote open", async () => {
+      try {
+        await closeVote(winningVote, user1);
+        expect.fail("Closing would forfeit the winnings");
+      } catch (error) {
+        expect(error.toString()).to.include("Vote is not settled yet");
+      }
+    });
+
+    it("Returns the vote rent to the user after claiming", async () => {
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: settledPollKeypair.publicKey,
+          vote: winningVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: null,
+          destination: null,
+        })
+        .signers([user1])
+        .rpc();
+
+      const rent = await provider.connection.getBalance(winningVote);
+      const before = await provider.connection.getBalance(user1.publicKey);
+      await closeVote(winningVote, user1);
+      const after = await provider.connection.getBalance(user1.publicKey);
+
+      expect(rent).to.be.greaterThan(0);
+      expect(after - before).to.equal(rent);
+      expect(await provider.connection.getAccountInfo(winningVote)).to.equal(null);
+    });
+
+    it("Lets a losing vote be closed", async () => {
+      await closeVote(losingVote, user2);
+      expect(await provider.connection.getAccountInfo(losingVote)).to.equal(null);
+    });
+  });
+
+  describe("bet limits", () => {
+    const limitedPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(limitedPollKeypair, {
+        minBet: new anchor.BN(1000000),
+        maxBet: new anchor.BN(50000000),
+      });
+    });
+
+    it("Rejects a bet below the minimum", async () => {
+      try {
+        await castVote(limitedPollKeypair.publicKey, user1, user1TokenAccount, 1, 999999);
+        expect.fail("The bet is below the minimum");
+      } catch (error) {
+        expect(error.toString()).to.include("Bet too small");
+      }
+    });
+
+    it("Rejects a bet above the maximum", async () => {
+      try {
+        await castVote(limitedPollKeypair.publicKey, user1, user1TokenAccount, 1, 50000001);
+        expect.fail("The bet is above the maximum");
+      } catch (error) {
+        expect(error.toString()).to.include("Bet too large");
+      }
+    });
+
+    it("Accepts bets at both limits", async () => {
+      await castVote(limitedPollKeypair.publicKey, user1, user1TokenAccount, 1, 1000000);
+      await castVote(limitedPollKeypair.publicKey, user2, user2TokenAccount, 2, 50000000);
+
+      const pollAccount = await program.account.poll.fetch(limitedPollKeypair.publicKey);
+      expect(pollAccount.voteCount.toNumber()).to.equal(2);
+    });
+
+    it("Rejects a zero bet even without a minimum", async () => {
+      const unlimitedPollKeypair = Keypair.generate();
+      await createPoll(unlimitedPollKeypair, { minBet: new anchor.BN(0) });
+      const before = await getAccount(provider.connection, user1TokenAccount);
+      try {
+        await castVote(unlimitedPollKeypair.publicKey, user1, user1TokenAccount, 1, 0);
+        expect.fail("A zero bet buys nothing");
+      } catch (error) {
+        expect(error.toString()).to.include("Bet too small");
+      }
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      expect(after.amount.toString()).to.equal(before.amount.toString());
+      const voteKey = voteFor(unlimitedPollKeypair.publicKey, user1.publicKey);
+      expect(await provider.connection.getAccountInfo(voteKey)).to.equal(null);
+    });
+
+    it("Rejects a minimum above the maximum", async () => {
+      try {
+        await createPoll(Keypair.generate(), {
+          minBet: new anchor.BN(2),
+          maxBet: new anchor.BN(1),
+        });
+        expect.fail("The limits are inverted");
+      } catch (error) {
+        expect(error.toString()).to.include("Invalid bet limits");
+      }
+    });
+  });
+
+  describe("pause switch", () => {
+    const pausePollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(pausePollKeypair);
+    });
+
+    async function setPaused(paused: boolean, signer: Keypair = admin) {
+      await program.methods
+        .setPaused(paused)
+        .accounts({ admin: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    it("Blocks votes while paused and accepts them again once unpaused", async () => {
+      await setPaused(true);
+      try {
+        await castVote(pausePollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+        expect.fail("Voting is paused");
+      } catch (error) {
+        expect(error.toString()).to.include("Program is paused");
+      } finally {
+        await setPaused(false);
+      }
+
+      await castVote(pausePollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      const pollAccount = await program.account.poll.fetch(pausePollKeypair.publicKey);
+      expect(pollAccount.voteCount.toNumber()).to.equal(1);
+    });
+
+    it("Emits PausedEvent on every toggle", async () => {
+      const events: any[] = [];
+      const listener = program.addEventListener("pausedEvent", (e) => {
+        events.push(e);
+      });
+
+      await setPaused(true);
+      await setPaused(false);
+      for (let i = 0; i < 20 && events.length < 2; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      expect(events.map((e) => e.paused)).to.deep.equal([true, false]);
+      expect(events[0].admin.toString()).to.equal(admin.publicKey.toString());
+    });
+
+    it("Only lets the admin pause", async () => {
+      try {
+        await setPaused(true, user1);
+        expect.fail("Only the admin may pause");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("admin transfer", () => {
+    const newAdmin = Keypair.generate();
+
+    async function transferAdmin(to: PublicKey, signer: Keypair) {
+      await program.methods
+        .transferAdmin(to)
+        .accounts({ admin: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    async function acceptAdmin(signer: Keypair) {
+      await program.methods
+        .acceptAdmin()
+        .accounts({ newAdmin: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    async function fetchConfig() {
+      const [configKey] = PublicKey.findProgramAddressSync([Buffer.from("config")], program.programId);
+      return program.account.config.fetch(configKey);
+    }
+
+    it("Rejects a transfer proposed by anyone but the admin", async () => {
+      try {
+        await transferAdmin(user1.publicKey, user1);
+        expect.fail("Only the admin may propose a successor");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("Hands over only once the proposed admin accepts", async () => {
+      let proposed: any = null;
+      let transferred: any = null;
+      const proposedListener = program.addEventListener("adminTransferProposed", (e) => {
+        proposed = e;
+      });
+      const transferredListener = program.addEventListener("adminTransferred", (e) => {
+        transferred = e;
+      });
+
+      await transferAdmin(newAdmin.publicKey, admin);
+      let config = await fetchConfig();
+      expect(config.admin.toString()).to.equal(admin.publicKey.toString());
+      expect(config.pendingAdmin.toString()).to.equal(newAdmin.publicKey.toString());
+
+      try {
+        await acceptAdmin(user1);
+        expect.fail("Only the proposed admin may accept");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+
+      await acceptAdmin(newAdmin);
+      config = await fetchConfig();
+      expect(config.admin.toString()).to.equal(newAdmin.publicKey.toString());
+      expect(config.pendingAdmin).to.equal(null);
+
+      for (let i = 0; i < 20 && (proposed === null || transferred === null); i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(proposedListener);
+      await program.removeEventListener(transferredListener);
+      expect(proposed.admin.toString()).to.equal(admin.publicKey.toString());
+      expect(proposed.pendingAdmin.toString()).to.equal(newAdmin.publicKey.toString());
+      expect(transferred.previousAdmin.toString()).to.equal(admin.publicKey.toString());
+      expect(transferred.newAdmin.toString()).to.equal(newAdmin.publicKey.toString());
+
+      // Hand the role back for the remaining tests
+      await transferAdmin(admin.publicKey, newAdmin);
+      await acceptAdmin(admin);
+      expect((await fetchConfig()).admin.toString()).to.equal(admin.publicKey.toString());
+    });
+  });
+
+  describe("fee withdrawal", () => {
+    const feeWithdrawalKeypair = Keypair.generate();
+    let feeVault: PublicKey;
+
+    before(async () => {
+      await createPoll(feeWithdrawalKeypair);
+      feeVault = feeVaultFor(feeWithdrawalKeypair.publicKey);
+      // 3% of the vote accrues in the fee vault
+      await castVote(feeWithdrawalKeypair.publicKey, user1, user1TokenAccount, 1, 100000000);
+    });
+
+    async function withdrawFees(amount: anchor.BN | null, signer: Keypair = admin, destination = adminTokenAccount) {
+      await program.methods
+        .withdrawFees(amount)
+        .accounts({ poll: feeWithdrawalKeypair.publicKey, admin: signer.publicKey, destination })
+        .signers([signer])
+        .rpc();
+    }
+
+    it("Withdraws part of the fees to the admin", async () => {
+      const adminBefore = await getAccount(provider.connection, adminTokenAccount);
+      await withdrawFees(new anchor.BN(1000000));
+      const adminAfter = await getAccount(provider.connection, adminTokenAccount);
+
+      expect(Number(adminAfter.amount) - Number(adminBefore.amount)).to.equal(1000000);
+      expect(Number((await getAccount(provider.connection, feeVault)).amount)).to.equal(2000000);
+    });
+
+    it("Rejects withdrawing more than the vault holds", async () => {
+      try {
+        await withdrawFees(new anchor.BN(2000001));
+        expect.fail("The fee vault would be overdrawn");
+      } catch (error) {
+        expect(error.toString()).to.include("Not enough fees in the vault");
+      }
+    });
+
+    it("Rejects withdrawals by anyone but the admin", async () => {
+      try {
+        await withdrawFees(null, user1, user1TokenAccount);
+        expect.fail("Only the admin may withdraw fees");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("Withdraws the full balance when no amount is given", async () => {
+      const adminBefore = await getAccount(provider.connection, adminTokenAccount);
+      await withdrawFees(null);
+      const adminAfter = await getAccount(provider.connection, adminTokenAccount);
+
+      expect(Number(adminAfter.amount) - Number(adminBefore.amount)).to.equal(2000000);
+      expect(Number((await getAccount(provider.connection, feeVault)).amount)).to.equal(0);
+    });
+  });
+
+  describe("sell position", () => {
+    const sellPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(sellPollKeypair);
+    });
+
+    async function sellPosition(vote: PublicKey, user: Keypair, userTokenAccount: PublicKey, minTokensOut = 0) {
+      await program.methods
+        .sellPosition(new anchor.BN(minTokensOut))
+        .accounts({ poll: sellPollKeypair.publicKey, vote, user: user.publicKey, userTokenAccount })
+        .signers([user])
+        .rpc();
+    }
+
+    it("Pays back slightly less than the stake on an immediate sale", async () => {
+      const amount = 10000000;
+      const before = await getAccount(provider.connection, user1TokenAccount);
+      const vote = await castVote(sellPollKeypair.publicKey, user1, user1TokenAccount, 1, amount);
+      const stake = (await program.account.vote.fetch(vote)).stake.toNumber();
+
+      await sellPosition(vote, user1, user1TokenAccount);
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      const received = Number(after.amount) - Number(before.amount) + amount;
+
+      // The fee is charged again on the sale, and rounding favors the pool
+      expect(received).to.be.lessThan(stake);
+      expect(received).to.be.greaterThan(stake * 0.96);
+      expect(await provider.connection.getAccountInfo(vote)).to.equal(null);
+
+      const pollAccount = await program.account.poll.fetch(sellPollKeypair.publicKey);
+      expect(pollAccount.shares.map((s) => s.toNumber())).to.deep.equal([1000000000, 1000000001]);
+      expect(pollAccount.outcomePools[0].toNumber()).to.equal(0);
+    });
+
+    it("Rejects a sale below the minimum proceeds", async () => {
+      const vote = await castVote(sellPollKeypair.publicKey, user1, user1TokenAccount, 2, 10000000);
+      try {
+        await sellPosition(vote, user1, user1TokenAccount, 10000000);
+        expect.fail("The proceeds are below the minimum");
+      } catch (error) {
+        expect(error.toString()).to.include("Slippage exceeded");
+      }
+    });
+
+    it("Rejects selling another user's vote", async () => {
+      const vote = await castVote(sellPollKeypair.publicKey, user1, user1TokenAccount, 2, 10000000);
+      try {
+        await sellPosition(vote, user2, user2TokenAccount);
+        expect.fail("Only the voter may sell");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("batch claims", () => {
+    const batchPolls = [Keypair.generate(), Keypair.generate()];
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 8;
+      for (const pollKp of batchPolls) {
+        await createPoll(pollKp, { closesAt });
+        await castVote(pollKp.publicKey, user1, user1TokenAccount, 1, 10000000);
+        await castVote(pollKp.publicKey, user2, user2TokenAccount, 2, 10000000);
+      }
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      for (const pollKp of batchPolls) {
+        await program.methods
+          .resolvePoll(nft1)
+          .accounts({ poll: pollKp.publicKey, authority: admin.publicKey, parentPoll: null, ownershipProof: null })
+          .signers([admin])
+          .rpc();
+      }
+    });
+
+    // One (poll, vote, pool vault, fee vault, pool authority) group per position
+    function claimGroups(user: PublicKey, pollKeys: PublicKey[]) {
+      return pollKeys.flatMap((pollKey) => [
+        { pubkey: pollKey, isWritable: true, isSigner: false },
+        { pubkey: voteFor(pollKey, user), isWritable: true, isSigner: false },
+        { pubkey: poolVaultFor(pollKey), isWritable: true, isSigner: false },
+        { pubkey: feeVaultFor(pollKey), isWritable: true, isSigner: false },
+        {
+          pubkey: PublicKey.findProgramAddressSync([Buffer.from("pool"), pollKey.toBuffer()], program.programId)[0],
+          isWritable: false,
+          isSigner: false,
+        },
+      ]);
+    }
+
+    async function claimBatch(user: Keypair, userTokenAccount: PublicKey): Promise<number> {
+      const before = await getAccount(provider.connection, userTokenAccount);
+      await program.methods
+        .claimWinningsBatch()
+        .accounts({ user: user.publicKey, userTokenAccount })
+        .remainingAccounts(claimGroups(user.publicKey, batchPolls.map((pollKp) => pollKp.publicKey)))
+        .signers([user])
+        .rpc();
+      const after = await getAccount(provider.connection, userTokenAccount);
+      return Number(after.amount) - Number(before.amount);
+    }
+
+    it("Claims two resolved polls in one call", async () => {
+      let expected = 0;
+      for (const pollKp of batchPolls) {
+        const pollAccount = await program.account.poll.fetch(pollKp.publicKey);
+        const voteAccount = await program.account.vote.fetch(voteFor(pollKp.publicKey, user1.publicKey));
+        const totalPool = pollAccount.outcomePools.reduce((a, b) => a.add(b));
+        expected += voteAccount.amount.mul(totalPool).div(pollAccount.winningSideShares).toNumber();
+      }
+
+      expect(await claimBatch(user1, user1TokenAccount)).to.equal(expected);
+      for (const pollKp of batchPolls) {
+        const voteAccount = await program.account.vote.fetch(voteFor(pollKp.publicKey, user1.publicKey));
+        expect(voteAccount.claimed).to.equal(true);
+      }
+    });
+
+    it("Skips claimed and losing positions instead of failing", async () => {
+      expect(await claimBatch(user1, user1TokenAccount)).to.equal(0);
+      expect(await claimBatch(user2, user2TokenAccount)).to.equal(0);
+    });
+  });
+
+  describe("SOL polls", () => {
+    const solPollKeypair = Keypair.generate();
+    const stake = 100000000; // 0.1 SOL
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 8;
+      await createPoll(solPollKeypair, { closesAt, tokenMint: NATIVE_MINT });
+    });
+
+    async function voteSol(user: Keypair, nftChoice: number, amount: number) {
+      await program.methods
+        .voteSol(nftChoice, new anchor.BN(amount), new anchor.BN(0), null, null)
+        .accounts({
+          poll: solPollKeypair.publicKey,
+          vote: voteFor(solPollKeypair.publicKey, user.publicKey),
+          delegation: null,
+          user: user.publicKey,
+        })
+        .signers([user])
+        .rpc();
+    }
+
+    it("Rejects SOL bets on a token poll", async () => {
+      try {
+        await program.methods
+          .voteSol(1, new anchor.BN(stake), new anchor.BN(0), null, null)
+          .accounts({ poll, vote: voteFor(poll, user1.publicKey), delegation: null, user: user1.publicKey })
+          .signers([user1])
+          .rpc();
+        expect.fail("The poll is denominated in an SPL token");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is not denominated in SOL");
+      }
+    });
+
+    it("Bets lamports and pays the winner back in lamports", async () => {
+      const pollKey = solPollKeypair.publicKey;
+      const before = await provider.connection.getBalance(user1.publicKey);
+      await voteSol(user1, 1, stake);
+      await voteSol(user2, 2, stake / 2);
+      const voteRent = await provider.connection.getBalance(voteFor(pollKey, user1.publicKey));
+      const afterVote = await provider.connection.getBalance(user1.publicKey);
+      expect(before - afterVote).to.equal(stake + voteRent);
+
+      // The stake after the 3% fee is held as wrapped SOL
+      const pollAccount = await program.account.poll.fetch(pollKey);
+      const totalPool = pollAccount.outcomePools.reduce((a, b) => a.add(b));
+      const vault = await getAccount(provider.connection, poolVaultFor(pollKey));
+      expect(Number(vault.amount)).to.equal(totalPool.toNumber());
+
+      await sleep((pollAccount.closesAt.toNumber() + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({ poll: pollKey, authority: admin.publicKey, parentPoll: null, ownershipProof: null })
+        .signers([admin])
+        .rpc();
+
+      await program.methods
+        .claimWinningsSol()
+        .accountsPartial({
+          poll: pollKey,
+          vote: voteFor(pollKey, user1.publicKey),
+          user: user1.publicKey,
+          tokenMint: NATIVE_MINT,
+          parentPoll: null,
+        })
+        .signers([user1])
+        .rpc();
+      const afterClaim = await provider.connection.getBalance(user1.publicKey);
+
+      // The only winner takes the whole pool; the temporary wSOL account's
+      // rent is refunded when it is closed
+      expect(afterClaim - afterVote).to.equal(totalPool.toNumber());
+      const unwrapAccount = PublicKey.findProgramAddressSync(
+        [Buffer.from("unwrap"), pollKey.toBuffer(), user1.publicKey.toBuffer()],
+        program.programId
+      )[0];
+      expect(await provider.connection.getAccountInfo(unwrapAccount)).to.equal(null);
+    });
+  });
+
+  describe("dispute window", () => {
+    const disputePollKeypair = Keypair.generate();
+    const disputePeriod = 6;
+    let winningVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 8;
+      await createPoll(disputePollKeypair, { closesAt, disputePeriod });
+      winningVote = await castVote(disputePollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: disputePollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    async function claim() {
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: disputePollKeypair.publicKey,
+          vote: winningVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: null,
+          destination: null,
+        })
+        .signers([user1])
+        .rpc();
+    }
+
+    it("Records when the poll was resolved", async () => {
+      const pollAccount = await program.account.poll.fetch(disputePollKeypair.publicKey);
+      expect(pollAccount.disputePeriod.toNumber()).to.equal(disputePeriod);
+      expect(pollAccount.resolvedAt.toNumber()).to.be.at.least(pollAccount.closesAt.toNumber());
+    });
+
+    it("Rejects a claim while the dispute window is open", async () => {
+      try {
+        await claim();
+        expect.fail("Winnings are still locked");
+      } catch (error) {
+        expect(error.toString()).to.include("Winnings are locked until the dispute window closes");
+      }
+    });
+
+    it("Pays out once the dispute window has passed", async () => {
+      const pollAccount = await program.account.poll.fetch(disputePollKeypair.publicKey);
+      await sleep((pollAccount.resolvedAt.toNumber() + disputePeriod + 2) * 1000 - Date.now());
+      await claim();
+      expect((await program.account.vote.fetch(winningVote)).claimed).to.equal(true);
+    });
+  });
+
+  describe("resolution override", () => {
+    const overridePollKeypair = Keypair.generate();
+    const disputePeriod = 6;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 8;
+      await createPoll(overridePollKeypair, { closesAt, disputePeriod });
+      await castVote(overridePollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await castVote(overridePollKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: overridePollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    async function overrideResolution(winningNft: PublicKey, signer: Keypair) {
+      await program.methods
+        .overrideResolution(winningNft)
+        .accounts({ poll: overridePollKeypair.publicKey, admin: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    it("Rejects an override by anyone but the admin", async () => {
+      try {
+        await overrideResolution(nft2, user1);
+        expect.fail("Only the admin may override");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("Moves the win to another outcome within the dispute window", async () => {
+      await overrideResolution(nft2, admin);
+      const pollAccount = await program.account.poll.fetch(overridePollKeypair.publicKey);
+      expect(pollAccount.winningNft.toString()).to.equal(nft2.toString());
+      expect(pollAccount.winningSideShares.toString()).to.equal(pollAccount.voteShares[1].toString());
+    });
+
+    it("Rejects an override once the dispute window has passed", async () => {
+      const pollAccount = await program.account.poll.fetch(overridePollKeypair.publicKey);
+      await sleep((pollAccount.resolvedAt.toNumber() + disputePeriod + 2) * 1000 - Date.now());
+      try {
+        await overrideResolution(nft1, admin);
+        expect.fail("The dispute window has closed");
+      } catch (error) {
+        expect(error.toString()).to.include("The dispute window has closed");
+      }
+    });
+  });
+
+  describe("vote quotes", () => {
+    const quotePollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(quotePollKeypair);
+    });
+
+    it("Quotes exactly what the vote then receives", async () => {
+      const amount = 25000000;
+      const simulation = await program.methods
+        .quoteVote(2, new anchor.BN(amount))
+        .accounts({ poll: quotePollKeypair.publicKey })
+        .simulate();
+      const quote = simulation.events.find((e) => e.name.toLowerCase() === "quoteevent").data as any;
+
+      // The quote left the reserves untouched
+      const pollBefore = await program.account.poll.fetch(quotePollKeypair.publicKey);
+      expect(pollBefore.shares.map((r) => r.toNumber())).to.deep.equal([1000000000, 1000000000]);
+
+      const voteKey = await castVote(quotePollKeypair.publicKey, user1, user1TokenAccount, 2, amount);
+      const voteAccount = await program.account.vote.fetch(voteKey);
+      expect(quote.received.toString()).to.equal(voteAccount.amount.toString());
+      expect(quote.priceAtTransaction.toString()).to.equal(voteAccount.priceAtTransaction.toString());
+      expect(quote.fee.toNumber()).to.equal(amount - voteAccount.stake.toNumber());
+    });
+  });
+
+  describe("poll extension", () => {
+    const extendPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(extendPollKeypair);
+    });
+
+    async function extendPoll(closesAt: anchor.BN, signer: Keypair = admin) {
+      await program.methods
+        .extendPoll(closesAt)
+        .accounts({ poll: extendPollKeypair.publicKey, authority: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    it("Moves closes_at later", async () => {
+      const before = await program.account.poll.fetch(extendPollKeypair.publicKey);
+      const extended = before.closesAt.add(new anchor.BN(3600));
+      await extendPoll(extended);
+      const after = await program.account.poll.fetch(extendPollKeypair.publicKey);
+      expect(after.closesAt.toString()).to.equal(extended.toString());
+    });
+
+    it("Rejects moving closes_at earlier", async () => {
+      const pollAccount = await program.account.poll.fetch(extendPollKeypair.publicKey);
+      try {
+        await extendPoll(pollAccount.closesAt.sub(new anchor.BN(60)));
+        expect.fail("A poll cannot be shortened");
+      } catch (error) {
+        expect(error.toString()).to.include("Close time must be in the future");
+      }
+    });
+
+    it("Rejects an extension by anyone but the authority or admin", async () => {
+      const pollAccount = await program.account.poll.fetch(extendPollKeypair.publicKey);
+      try {
+        await extendPoll(pollAccount.closesAt.add(new anchor.BN(60)), user1);
+        expect.fail("Only the authority or admin may extend");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("poll fee changes", () => {
+    const feePollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(feePollKeypair, { feeBps: 300 });
+    });
+
+    async function setPollFee(feeBps: number, signer: Keypair = admin) {
+      await program.methods
+        .setPollFee(feeBps)
+        .accounts({ poll: feePollKeypair.publicKey, authority: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    it("Rejects a fee change by anyone but the authority or admin", async () => {
+      try {
+        await setPollFee(100, user1);
+        expect.fail("Only the authority or admin may change the fee");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("Rejects a fee above the maximum", async () => {
+      try {
+        await setPollFee(1001);
+        expect.fail("The fee is above MAX_FEE_BPS");
+      } catch (error) {
+        expect(error.toString()).to.include("Fee too high");
+      }
+    });
+
+    it("Charges later votes the new fee", async () => {
+      const events: any[] = [];
+      const listener = program.addEventListener("feeChangedEvent", (e) => {
+        events.push(e);
+      });
+      await setPollFee(100);
+      for (let i = 0; i < 20 && events.length < 1; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+      expect(events[0].previousFeeBps).to.equal(300);
+      expect(events[0].feeBps).to.equal(100);
+
+      const feeVault = feeVaultFor(feePollKeypair.publicKey);
+      const before = await getAccount(provider.connection, feeVault);
+      await castVote(feePollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      const after = await getAccount(provider.connection, feeVault);
+      // 1% of the bet instead of the 3% the poll was created with
+      expect(Number(after.amount) - Number(before.amount)).to.equal(100000);
+    });
+  });
+
+  describe("liquidity providers", () => {
+    const lpPollKeypair = Keypair.generate();
+    let closesAt: number;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 8;
+      await createPoll(lpPollKeypair, { closesAt });
+      await mintTo(provider.connection, admin, mint, adminTokenAccount, admin.publicKey, 20000000);
+      // The admin provides a quarter of the liquidity, user2 the rest
+      for (const [lp, tokenAccount, amount] of [
+        [admin, adminTokenAccount, 10000000],
+        [user2, user2TokenAccount, 30000000],
+      ] as [Keypair, PublicKey, number][]) {
+        await program.methods
+          .addLiquidity([new anchor.BN(amount), new anchor.BN(amount)])
+          .accounts({ poll: lpPollKeypair.publicKey, user: lp.publicKey, userTokenAccount: tokenAccount })
+          .signers([lp])
+          .rpc();
+      }
+      // 3% of the vote accrues in the fee vault
+      await castVote(lpPollKeypair.publicKey, user1, user1TokenAccount, 1, 100000000);
+    });
+
+    async function withdrawLp(lp: Keypair, tokenAccount: PublicKey) {
+      await program.methods
+        .withdrawLp()
+        .accounts({ poll: lpPollKeypair.publicKey, provider: lp.publicKey, providerTokenAccount: tokenAccount })
+        .signers([lp])
+        .rpc();
+    }
+
+    it("Tracks each provider's deposit", async () => {
+      const pollAccount = await program.account.poll.fetch(lpPollKeypair.publicKey);
+      const position = await program.account.lpPosition.fetch(lpPositionFor(lpPollKeypair.publicKey, user2.publicKey));
+      expect(pollAccount.lpDeposits.toString()).to.equal("80000000");
+      expect(position.deposited.toString()).to.equal("60000000");
+      expect(position.provider.toString()).to.equal(user2.publicKey.toString());
+    });
+
+    it("Rejects withdrawing before the poll is settled", async () => {
+      try {
+        await withdrawLp(user2, user2TokenAccount);
+        expect.fail("The poll is still active");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is not resolved");
+      }
+    });
+
+    it("Lets the admin take only the protocol's part of the fees", async () => {
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({ poll: lpPollKeypair.publicKey, authority: admin.publicKey, parentPoll: null, ownershipProof: null })
+        .signers([admin])
+        .rpc();
+      const withdrawFees = (amount: anchor.BN | null) =>
+        program.methods
+          .withdrawFees(amount)
+          .accounts({ poll: lpPollKeypair.publicKey, admin: admin.publicKey, destination: adminTokenAccount })
+          .signers([admin])
+          .rpc();
+      // Half of the 3000000 in fees is set aside for the providers
+      try {
+        await withdrawFees(new anchor.BN(1500001));
+        expect.fail("Part of the fees belongs to the providers");
+      } catch (error) {
+        expect(error.toString()).to.include("Not enough fees in the vault");
+      }
+      await withdrawFees(null);
+      const feeVault = await getAccount(provider.connection, feeVaultFor(lpPollKeypair.publicKey));
+      expect(Number(feeVault.amount)).to.equal(1500000);
+    });
+
+    it("Splits liquidity and fees in proportion to the deposits", async () => {
+      const adminBefore = await getAccount(provider.connection, adminTokenAccount);
+      const user2Before = await getAccount(provider.connection, user2TokenAccount);
+      await withdrawLp(admin, adminTokenAccount);
+      await withdrawLp(user2, user2TokenAccount);
+      const adminAfter = await getAccount(provider.connection, adminTokenAccount);
+      const user2After = await getAccount(provider.connection, user2TokenAccount);
+
+      expect(Number(adminAfter.amount) - Number(adminBefore.amount)).to.equal(20000000 + 375000);
+      expect(Number(user2After.amount) - Number(user2Before.amount)).to.equal(60000000 + 1125000);
+      const pollAccount = await program.account.poll.fetch(lpPollKeypair.publicKey);
+      expect(pollAccount.lpDeposits.toString()).to.equal("0");
+      expect(pollAccount.liquidity.toString()).to.equal("0");
+      expect(await provider.connection.getAccountInfo(lpPositionFor(lpPollKeypair.publicKey, user2.publicKey))).to.equal(null);
+    });
+  });
+
+  describe("price impact limit", () => {
+    const thinPollKeypair = Keypair.generate();
+
+    before(async () => {
+      // A thin 10 / 10 token pool where a large bet swings the price
+      await createPoll(thinPollKeypair, { initialShares: new anchor.BN(10000000) });
+    });
+
+    it("Rejects a large bet that moves the price beyond the limit", async () => {
+      try {
+        // Takes the price from 50% to roughly 80%
+        await castVote(thinPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000, 0, 500);
+        expect.fail("The bet moves the price by far more than 5%");
+      } catch (error) {
+        expect(error.toString()).to.include("Vote would move the price more than allowed");
+      }
+      const pollAccount = await program.account.poll.fetch(thinPollKeypair.publicKey);
+      expect(pollAccount.shares.map((r) => r.toString())).to.deep.equal(["10000000", "10000000"]);
+    });
+
+    it("Accepts a small bet within the limit", async () => {
+      // Moves the price by under half a percent
+      const vote = await castVote(thinPollKeypair.publicKey, user1, user1TokenAccount, 1, 100000, 0, 500);
+      const voteAccount = await program.account.vote.fetch(vote);
+      expect(voteAccount.priceAtTransaction.toNumber()).to.be.within(5000, 5500);
+    });
+  });
+
+  describe("liquidity events", () => {
+    const liquidityEventKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(liquidityEventKeypair);
+      await mintTo(provider.connection, admin, mint, user2TokenAccount, admin.publicKey, 5000000);
+    });
+
+    it("Emits LiquidityAddedEvent with the deposit and the new reserves", async () => {
+      let event: any = null;
+      const listener = program.addEventListener("liquidityAddedEvent", (e) => {
+        if (e.poll.equals(liquidityEventKeypair.publicKey)) {
+          event = e;
+        }
+      });
+
+      await program.methods
+        .addLiquidity([new anchor.BN(2500000), new anchor.BN(2500000)])
+        .accounts({ poll: liquidityEventKeypair.publicKey, user: user2.publicKey, userTokenAccount: user2TokenAccount })
+        .signers([user2])
+        .rpc();
+      for (let i = 0; i < 20 && event === null; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      const pollAccount = await program.account.poll.fetch(liquidityEventKeypair.publicKey);
+      expect(event).to.not.equal(null);
+      expect(event.provider.toString()).to.equal(user2.publicKey.toString());
+      expect(event.amounts.map((a) => a.toString())).to.deep.equal(["2500000", "2500000"]);
+      expect(event.newShares.map((r) => r.toString())).to.deep.equal(["1002500000", "1002500000"]);
+      expect(event.newShares.map((r) => r.toString())).to.deep.equal(pollAccount.shares.map((r) => r.toString()));
+      expect(event.liquidity.toString()).to.equal("5000000");
+      expect(event.deposited.toString()).to.equal("5000000");
+    });
+
+    it("Rejects a deposit that would move the price", async () => {
+      const pollBefore = await program.account.poll.fetch(liquidityEventKeypair.publicKey);
+      try {
+        await program.methods
+          .addLiquidity([new anchor.BN(1000000), new anchor.BN(3000000)])
+          .accounts({
+            poll: liquidityEventKeypair.publicKey,
+            user: user2.publicKey,
+            userTokenAccount: user2TokenAccount,
+          })
+          .signers([user2])
+          .rpc();
+        expect.fail("The deposit is skewed towards the second outcome");
+      } catch (error) {
+        expect(error.toString()).to.include("Liquidity must be added in proportion to the current reserves");
+      }
+      const pollAfter = await program.account.poll.fetch(liquidityEventKeypair.publicKey);
+      expect(pollAfter.shares.map((r) => r.toString())).to.deep.equal(pollBefore.shares.map((r) => r.toString()));
+    });
+  });
+
+  describe("title encoding", () => {
+    it("Stores a multibyte UTF-8 title intact", async () => {
+      const titleKeypair = Keypair.generate();
+      const title = "Qui gagnera ? 猫 vs 犬 🐱";
+      expect(Buffer.from(title).length).to.be.below(64);
+      await createPoll(titleKeypair, { title: Buffer.from(title) });
+
+      const pollAccount = await program.account.poll.fetch(titleKeypair.publicKey);
+      expect(Buffer.from(pollAccount.title).toString("utf8")).to.equal(title);
+    });
+
+    it("Rejects a title that is not valid UTF-8", async () => {
+      try {
+        // A lone continuation byte and a truncated multibyte sequence
+        await createPoll(Keypair.generate(), { title: Buffer.from([0x54, 0x80, 0x65, 0xe2, 0x82]) });
+        expect.fail("The title cannot be decoded");
+      } catch (error) {
+        expect(error.toString()).to.include("Title is not valid UTF-8");
+      }
+    });
+  });
+
+  describe("title limit", () => {
+    const longTitle = Buffer.from("x".repeat(100));
+
+    async function setMaxTitleLen(maxTitleLen: number) {
+      await program.methods.setMaxTitleLen(maxTitleLen).accounts({ admin: admin.publicKey }).signers([admin]).rpc();
+    }
+
+    after(async () => {
+      await setMaxTitleLen(64);
+    });
+
+    it("Rejects titles above the configured limit", async () => {
+      try {
+        await createPoll(Keypair.generate(), { title: longTitle });
+        expect.fail("The title is longer than 64 bytes");
+      } catch (error) {
+        expect(error.toString()).to.include("Title longer than the configured limit");
+      }
+    });
+
+    it("Rejects a limit above the hard cap", async () => {
+      try {
+        await setMaxTitleLen(257);
+        expect.fail("The limit is above MAX_TITLE_LEN");
+      } catch (error) {
+        expect(error.toString()).to.include("Title limit exceeds the hard cap");
+      }
+    });
+
+    it("Accepts a longer title once the limit is raised", async () => {
+      await setMaxTitleLen(128);
+      const longPollKeypair = Keypair.generate();
+      await createPoll(longPollKeypair, { title: longTitle });
+      const pollAccount = await program.account.poll.fetch(longPollKeypair.publicKey);
+      expect(Buffer.from(pollAccount.title).toString()).to.equal(longTitle.toString());
+    });
+  });
+
+  describe("early-bird bonus", () => {
+    const bonusPollKeypair = Keypair.generate();
+    let earlyVote: PublicKey;
+    let closesAt: number;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 12;
+      await createPoll(bonusPollKeypair, { closesAt, bonusEnabled: true });
+      earlyVote = await castVote(bonusPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+    });
+
+    it("Grants fewer bonus shares to a later bet of the same size", async () => {
+      await sleep((closesAt - 3) * 1000 - Date.now());
+      const lateVote = await castVote(bonusPollKeypair.publicKey, user2, user2TokenAccount, 1, 10000000);
+
+      const early = await program.account.vote.fetch(earlyVote);
+      const late = await program.account.vote.fetch(lateVote);
+      const bonusRatio = (vote) => vote.bonus.toNumber() / vote.amount.toNumber();
+      // At most 20% on top, shrinking as the close approaches
+      expect(bonusRatio(early)).to.be.within(0.1, 0.2);
+      expect(bonusRatio(late)).to.be.below(bonusRatio(early));
+      expect(early.amount.add(early.bonus).gt(late.amount.add(late.bonus))).to.equal(true);
+
+      const pollAccount = await program.account.poll.fetch(bonusPollKeypair.publicKey);
+      expect(pollAccount.bonusEnabled).to.equal(true);
+      expect(pollAccount.voteShares[0].toString()).to.equal(
+        early.amount.add(early.bonus).add(late.amount).add(late.bonus).toString()
+      );
+    });
+
+    it("Grants no bonus on polls without it", async () => {
+      const plainPollKeypair = Keypair.generate();
+      await createPoll(plainPollKeypair);
+      const vote = await castVote(plainPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      expect((await program.account.vote.fetch(vote)).bonus.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("odds", () => {
+    async function getOdds(pollKey: PublicKey): Promise<number[]> {
+      const simulation = await program.methods.getOdds().accounts({ poll: pollKey }).simulate();
+      const odds = simulation.events.find((e) => e.name.toLowerCase() === "oddsevent").data as any;
+      return odds.probabilities.map((p) => p.toNumber());
+    }
+
+    it("Derives the probabilities from the reserves", async () => {
+      const oddsPollKeypair = Keypair.generate();
+      await createPoll(oddsPollKeypair);
+      await castVote(oddsPollKeypair.publicKey, user2, user2TokenAccount, 1, 100000000);
+
+      // Two outcomes: outcome 1's probability is r2 / (r1 + r2), so the
+      // scarcer reserve is the likelier outcome
+      const [r1, r2] = (await program.account.poll.fetch(oddsPollKeypair.publicKey)).shares.map((r) => r.toNumber());
+      const odds = await getOdds(oddsPollKeypair.publicKey);
+      expect(odds[0]).to.be.above(odds[1]);
+      expect(Math.abs(odds[0] - (r2 * 10000) / (r1 + r2))).to.be.below(1);
+    });
+
+    it("Always adds up to exactly 10000", async () => {
+      const threeWayKeypair = Keypair.generate();
+      await createPoll(threeWayKeypair, { outcomes: [nft1, nft2, Keypair.generate().publicKey] });
+      const odds = await getOdds(threeWayKeypair.publicKey);
+      expect(odds.reduce((sum, p) => sum + p, 0)).to.equal(10000);
+    });
+  });
+
+  describe("cancel events", () => {
+    const canceledPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(canceledPollKeypair);
+      await castVote(canceledPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await castVote(canceledPollKeypair.publicKey, user2, user2TokenAccount, 2, 20000000);
+    });
+
+    it("Reports the reason and the votes at cancellation", async () => {
+      let event: any = null;
+      const listener = program.addEventListener("pollCanceledEvent", (e) => {
+        if (e.poll.equals(canceledPollKeypair.publicKey)) {
+          event = e;
+        }
+      });
+
+      await program.methods
+        .cancelPoll(7)
+        .accounts({ poll: canceledPollKeypair.publicKey, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      for (let i = 0; i < 20 && event === null; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      expect(event).to.not.equal(null);
+      expect(event.authority.toString()).to.equal(admin.publicKey.toString());
+      expect(event.reasonCode).to.equal(7);
+      expect(event.voteCount.toNumber()).to.equal(2);
+      expect(event.totalVolume.toNumber()).to.equal(30000000);
+    });
+  });
+
+  describe("unbacked resolution", () => {
+    const unbackedPollKeypair = Keypair.generate();
+    const nft3 = Keypair.generate().publicKey;
+    let losingVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(unbackedPollKeypair, { closesAt, outcomes: [nft1, nft2, nft3] });
+      losingVote = await castVote(unbackedPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await castVote(unbackedPollKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      // Nobody bet on nft3
+      await program.methods
+        .resolvePoll(nft3)
+        .accounts({
+          poll: unbackedPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Refunds voters when the winning outcome has no shares", async () => {
+      const pollAccount = await program.account.poll.fetch(unbackedPollKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ resolved: {} });
+      expect(pollAccount.winningSideShares.toNumber()).to.equal(0);
+
+      const before = await getAccount(provider.connection, user1TokenAccount);
+      await program.methods
+        .claimRefund()
+        .accounts({
+          poll: unbackedPollKeypair.publicKey,
+          vote: losingVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+        })
+        .signers([user1])
+        .rpc();
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      const stake = (await program.account.vote.fetch(losingVote)).stake.toNumber();
+      expect(Number(after.amount) - Number(before.amount)).to.equal(stake);
+    });
+  });
+
+  describe("active poll cap", () => {
+    const registry = PublicKey.findProgramAddressSync(
+      [Buffer.from("registry"), admin.publicKey.toBuffer()],
+      program.programId
+    )[0];
+
+    async function setMaxActivePolls(max: number) {
+      await program.methods.setMaxActivePolls(max).accounts({ admin: admin.publicKey }).signers([admin]).rpc();
+    }
+
+    after(async () => {
+      await setMaxActivePolls(0);
+    });
+
+    it("Counts the authority's unsettled polls", async () => {
+      const before = (await program.account.pollRegistry.fetch(registry)).activePollCount;
+      const countedKeypair = Keypair.generate();
+      await createPoll(countedKeypair);
+      expect((await program.account.pollRegistry.fetch(registry)).activePollCount).to.equal(before + 1);
+
+      await program.methods
+        .cancelPoll(0)
+        .accounts({ poll: countedKeypair.publicKey, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      expect((await program.account.pollRegistry.fetch(registry)).activePollCount).to.equal(before);
+    });
+
+    it("Rejects polls beyond the cap until one is settled", async () => {
+      // Leave room for exactly two more polls
+      const active = (await program.account.pollRegistry.fetch(registry)).activePollCount;
+      await setMaxActivePolls(active + 2);
+      const first = Keypair.generate();
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(first, { closesAt });
+      await createPoll(Keypair.generate());
+
+      try {
+        await createPoll(Keypair.generate());
+        expect.fail("The authority is at its cap");
+      } catch (error) {
+        expect(error.toString()).to.include("Authority already has the maximum number of active polls");
+      }
+
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({ poll: first.publicKey, authority: admin.publicKey, parentPoll: null, ownershipProof: null })
+        .signers([admin])
+        .rpc();
+      await createPoll(Keypair.generate());
+      expect((await program.account.pollRegistry.fetch(registry)).activePollCount).to.equal(active + 2);
+    });
+  });
+
+  describe("authority fee split", () => {
+    const splitPollKeypair = Keypair.generate();
+
+    before(async () => {
+      // 1% of the 3% network fee goes to the poll authority
+      await createPoll(splitPollKeypair, { authorityFeeBps: 100 });
+    });
+
+    it("Rejects an authority cut larger than the network fee", async () => {
+      try {
+        await createPoll(Keypair.generate(), { feeBps: 300, authorityFeeBps: 301 });
+        expect.fail("The authority cannot take more than the whole fee");
+      } catch (error) {
+        expect(error.toString()).to.include("Authority fee exceeds the network fee");
+      }
+    });
+
+    it("Splits the fee of a bet between both vaults", async () => {
+      await castVote(splitPollKeypair.publicKey, user1, user1TokenAccount, 1, 100000000);
+      const protocolFees = await getAccount(provider.connection, feeVaultFor(splitPollKeypair.publicKey));
+      const authorityFees = await getAccount(provider.connection, authorityFeeVaultFor(splitPollKeypair.publicKey));
+      expect(Number(protocolFees.amount)).to.equal(2000000);
+      expect(Number(authorityFees.amount)).to.equal(1000000);
+    });
+
+    it("Lets only the poll authority withdraw its cut", async () => {
+      try {
+        await program.methods
+          .withdrawAuthorityFees(null)
+          .accounts({ poll: splitPollKeypair.publicKey, authority: user1.publicKey, destination: user1TokenAccount })
+          .signers([user1])
+          .rpc();
+        expect.fail("user1 is not the poll authority");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+
+      const before = await getAccount(provider.connection, adminTokenAccount);
+      await program.methods
+        .withdrawAuthorityFees(null)
+        .accounts({ poll: splitPollKeypair.publicKey, authority: admin.publicKey, destination: adminTokenAccount })
+        .signers([admin])
+        .rpc();
+      const after = await getAccount(provider.connection, adminTokenAccount);
+      expect(Number(after.amount) - Number(before.amount)).to.equal(1000000);
+    });
+  });
+
+  describe("close poll accounts", () => {
+    const settledPollKeypair = Keypair.generate();
+    let winningVote: PublicKey;
+    let losingVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(settledPollKeypair, { closesAt });
+      winningVote = await castVote(settledPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      losingVote = await castVote(settledPollKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: settledPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    async function closePollAccounts() {
+      await program.methods
+        .closePollAccounts()
+        .accounts({
+          poll: settledPollKeypair.publicKey,
+          caller: admin.publicKey,
+          authority: admin.publicKey,
+          treasury: adminTokenAccount,
+          authorityTokenAccount: adminTokenAccount,
+        })
+        .signers([admin])
+        .rpc();
+    }
+
+    it("Refuses to close while a winning vote is unclaimed", async () => {
+      expect((await program.account.poll.fetch(settledPollKeypair.publicKey)).unclaimedWinners).to.equal(1);
+      try {
+        await closePollAccounts();
+        expect.fail("The winner has not claimed yet");
+      } catch (error) {
+        expect(error.toString()).to.include("Winning votes are still unclaimed");
+      }
+    });
+
+    it("Sweeps the dust and returns the rent once settled", async () => {
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: settledPollKeypair.publicKey,
+          vote: winningVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: null,
+          destination: null,
+        })
+        .signers([user1])
+        .rpc();
+      await program.methods
+        .closeVote()
+        .accounts({ poll: settledPollKeypair.publicKey, vote: losingVote, user: user2.publicKey })
+        .signers([user2])
+        .rpc();
+
+      const accounts = [
+        settledPollKeypair.publicKey,
+        poolVaultFor(settledPollKeypair.publicKey),
+        feeVaultFor(settledPollKeypair.publicKey),
+        authorityFeeVaultFor(settledPollKeypair.publicKey),
+      ];
+      let rent = 0;
+      for (const account of accounts) {
+        rent += await provider.connection.getBalance(account);
+      }
+      const before = await provider.connection.getBalance(admin.publicKey);
+      await closePollAccounts();
+      const after = await provider.connection.getBalance(admin.publicKey);
+
+      // admin also pays the transaction fee
+      expect(after - before).to.equal(rent - 5000);
+      for (const account of accounts) {
+        expect(await provider.connection.getAccountInfo(account)).to.equal(null);
+      }
+    });
+  });
+
+  describe("allowed mints", () => {
+    const otherMint = Keypair.generate().publicKey;
+
+    async function updateAllowedMints(add: boolean, allowedMint: PublicKey) {
+      const method = add ? program.methods.addAllowedMint(allowedMint) : program.methods.removeAllowedMint(allowedMint);
+      await method.accounts({ admin: admin.publicKey }).signers([admin]).rpc();
+    }
+
+    after(async () => {
+      // Leave every mint allowed for the other suites
+      await updateAllowedMints(false, otherMint);
+      await updateAllowedMints(false, mint);
+    });
+
+    it("Rejects polls on a mint outside the list", async () => {
+      await updateAllowedMints(true, otherMint);
+      try {
+        await createPoll(Keypair.generate());
+        expect.fail("Only otherMint is allowed");
+      } catch (error) {
+        expect(error.toString()).to.include("Token mint is not allowed");
+      }
+    });
+
+    it("Accepts polls on an allowed mint", async () => {
+      await updateAllowedMints(true, mint);
+      const allowedPollKeypair = Keypair.generate();
+      await createPoll(allowedPollKeypair);
+      const pollAccount = await program.account.poll.fetch(allowedPollKeypair.publicKey);
+      expect(pollAccount.tokenMint.toString()).to.equal(mint.toString());
+    });
+
+    it("Lets only the admin change the list", async () => {
+      try {
+        await program.methods
+          .addAllowedMint(user1.publicKey)
+          .accounts({ admin: user1.publicKey })
+          .signers([user1])
+          .rpc();
+        expect.fail("user1 is not the admin");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("funded bets", () => {
+    const fundedPollKeypair = Keypair.generate();
+    let closesAt: number;
+    let creditedVote: PublicKey;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(fundedPollKeypair, { closesAt });
+    });
+
+    it("Rejects a credit the beneficiary did not approve", async () => {
+      try {
+        await program.methods
+          .vote(1, new anchor.BN(10000000), new anchor.BN(0), null, user2.publicKey)
+          .accounts({
+            poll: fundedPollKeypair.publicKey,
+            vote: voteFor(fundedPollKeypair.publicKey, user2.publicKey),
+            delegation: null,
+            user: user1.publicKey,
+            userTokenAccount: user1TokenAccount,
+          })
+          .signers([user1])
+          .rpc();
+        expect.fail("user2 has not approved user1");
+      } catch (error) {
+        expect(error.toString()).to.include("Beneficiary has not approved this delegate");
+      }
+
+      await program.methods
+        .approveDelegate(user1.publicKey)
+        .accounts({ beneficiary: user2.publicKey })
+        .signers([user2])
+        .rpc();
+      const delegation = await program.account.delegation.fetch(delegationFor(user2.publicKey, user1.publicKey));
+      expect(delegation.delegate.toString()).to.equal(user1.publicKey.toString());
+    });
+
+    it("Credits a bet paid by user1 to user2", async () => {
+      const before = await getAccount(provider.connection, user1TokenAccount);
+      creditedVote = await castVote(
+        fundedPollKeypair.publicKey,
+        user1,
+        user1TokenAccount,
+        1,
+        10000000,
+        0,
+        null,
+        user2.publicKey
+      );
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      expect(Number(before.amount) - Number(after.amount)).to.equal(10000000);
+
+      const voteAccount = await program.account.vote.fetch(creditedVote);
+      expect(voteAccount.user.toString()).to.equal(user2.publicKey.toString());
+      expect(creditedVote.toString()).to.equal(voteFor(fundedPollKeypair.publicKey, user2.publicKey).toString());
+    });
+
+    it("Lets only the beneficiary claim", async () => {
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: fundedPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+
+      try {
+        await program.methods
+          .claimWinnings(null)
+          .accounts({
+            poll: fundedPollKeypair.publicKey,
+            vote: creditedVote,
+            user: user1.publicKey,
+            userTokenAccount: user1TokenAccount,
+            parentPoll: null,
+            destination: null,
+          })
+          .signers([user1])
+          .rpc();
+        expect.fail("user1 only paid for the bet");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+
+      const before = await getAccount(provider.connection, user2TokenAccount);
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: fundedPollKeypair.publicKey,
+          vote: creditedVote,
+          user: user2.publicKey,
+          userTokenAccount: user2TokenAccount,
+          parentPoll: null,
+          destination: null,
+        })
+        .signers([user2])
+        .rpc();
+      const after = await getAccount(provider.connection, user2TokenAccount);
+      expect(Number(after.amount)).to.be.greaterThan(Number(before.amount));
+    });
+  });
+
+  describe("stale polls", () => {
+    const stalePollKeypair = Keypair.generate();
+    let closesAt: number;
+
+    before(async () => {
+      closesAt = Math.floor(Date.now() / 1000) + 5;
+      // The resolver gets two seconds after the close
+      await createPoll(stalePollKeypair, { closesAt, resolutionGracePeriod: 2 });
+      await castVote(stalePollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+    });
+
+    async function forceCancelStale(caller: Keypair) {
+      await program.methods
+        .forceCancelStale()
+        .accounts({ poll: stalePollKeypair.publicKey, caller: caller.publicKey })
+        .signers([caller])
+        .rpc();
+    }
+
+    it("Records the resolution deadline", async () => {
+      const pollAccount = await program.account.poll.fetch(stalePollKeypair.publicKey);
+      expect(pollAccount.resolutionDeadline.toNumber()).to.equal(closesAt + 2);
+    });
+
+    it("Rejects a force-cancel before the deadline", async () => {
+      try {
+        await forceCancelStale(user2);
+        expect.fail("The resolver still has time");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll can still be resolved");
+      }
+    });
+
+    it("Lets anyone cancel once the deadline has passed", async () => {
+      await sleep((closesAt + 4) * 1000 - Date.now());
+      await forceCancelStale(user2);
+      const pollAccount = await program.account.poll.fetch(stalePollKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ canceled: {} });
+
+      const before = await getAccount(provider.connection, user1TokenAccount);
+      await program.methods
+        .claimRefund()
+        .accounts({
+          poll: stalePollKeypair.publicKey,
+          vote: voteFor(stalePollKeypair.publicKey, user1.publicKey),
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+        })
+        .signers([user1])
+        .rpc();
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      expect(Number(after.amount)).to.be.greaterThan(Number(before.amount));
+    });
+  });
+
+  describe("duplicate resolution", () => {
+    const resolvedPollKeypair = Keypair.generate();
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(resolvedPollKeypair, { closesAt });
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: resolvedPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Rejects resolving a poll a second time", async () => {
+      try {
+        await program.methods
+          .resolvePoll(nft2)
+          .accounts({
+            poll: resolvedPollKeypair.publicKey,
+            authority: admin.publicKey,
+            parentPoll: null,
+            ownershipProof: null,
+          })
+          .signers([admin])
+          .rpc();
+        expect.fail("The poll is already resolved");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll is already resolved");
+      }
+      const pollAccount = await program.account.poll.fetch(resolvedPollKeypair.publicKey);
+      expect(pollAccount.winningNft.toString()).to.equal(nft1.toString());
+    });
+  });
+
+  describe("fee rounding", () => {
+    async function setFeeRounding(roundFeeUp: boolean) {
+      await program.methods.setFeeRounding(roundFeeUp).accounts({ admin: admin.publicKey }).signers([admin]).rpc();
+    }
+
+    after(async () => {
+      await setFeeRounding(false);
+    });
+
+    it("Truncates fees by default and rounds them up on request", async () => {
+      // 3% of 1000001 is 30000.03
+      const truncatedPollKeypair = Keypair.generate();
+      await createPoll(truncatedPollKeypair);
+      await castVote(truncatedPollKeypair.publicKey, user1, user1TokenAccount, 1, 1000001);
+      const truncated = await getAccount(provider.connection, feeVaultFor(truncatedPollKeypair.publicKey));
+      expect(Number(truncated.amount)).to.equal(30000);
+
+      await setFeeRounding(true);
+      const roundedPollKeypair = Keypair.generate();
+      await createPoll(roundedPollKeypair);
+      await castVote(roundedPollKeypair.publicKey, user1, user1TokenAccount, 1, 1000001);
+      const rounded = await getAccount(provider.connection, feeVaultFor(roundedPollKeypair.publicKey));
+      expect(Number(rounded.amount)).to.equal(30001);
+    });
+  });
+
+  describe("default fee", () => {
+    const USE_DEFAULT_FEE = 65535;
+
+    async function setDefaultFee(defaultFeeBps: number) {
+      await program.methods.setDefaultFee(defaultFeeBps).accounts({ admin: admin.publicKey }).signers([admin]).rpc();
+    }
+
+    after(async () => {
+      await setDefaultFee(0);
+    });
+
+    it("Rejects a default fee above the maximum", async () => {
+      try {
+        await setDefaultFee(1001);
+        expect.fail("The default fee is above MAX_FEE_BPS");
+      } catch (error) {
+        expect(error.toString()).to.include("Fee too high");
+      }
+    });
+
+    it("Uses the config's default fee when a poll asks for it", async () => {
+      await setDefaultFee(150);
+      const defaultPollKeypair = Keypair.generate();
+      await createPoll(defaultPollKeypair, { feeBps: USE_DEFAULT_FEE });
+      const defaultPoll = await program.account.poll.fetch(defaultPollKeypair.publicKey);
+      expect(defaultPoll.feeBps).to.equal(150);
+
+      const explicitPollKeypair = Keypair.generate();
+      await createPoll(explicitPollKeypair, { feeBps: 200 });
+      const explicitPoll = await program.account.poll.fetch(explicitPollKeypair.publicKey);
+      expect(explicitPoll.feeBps).to.equal(200);
+    });
+  });
+
+  describe("market state", () => {
+    const settledPollKeypair = Keypair.generate();
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(settledPollKeypair, { closesAt });
+      await castVote(settledPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await castVote(settledPollKeypair.publicKey, user2, user2TokenAccount, 2, 30000000);
+      await sleep((closesAt + 2) * 1000 - Date.now());
+    });
+
+    it("Emits the final market state on resolution", async () => {
+      let event: any = null;
+      const listener = program.addEventListener("marketStateEvent", (e) => {
+        if (e.poll.equals(settledPollKeypair.publicKey)) {
+          event = e;
+        }
+      });
+
+      await program.methods
+        .resolvePoll(nft2)
+        .accounts({
+          poll: settledPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+      for (let i = 0; i < 20 && event === null; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      const pollAccount = await program.account.poll.fetch(settledPollKeypair.publicKey);
+      expect(event).to.not.equal(null);
+      expect(event.winningNft.toString()).to.equal(nft2.toString());
+      // Both stakes net of the 3% fee
+      expect(event.outcomePools.map((pool) => pool.toNumber())).to.deep.equal([9700000, 29100000]);
+      expect(event.totalVolume.toNumber()).to.equal(40000000);
+      expect(event.voteCount.toNumber()).to.equal(2);
+      expect(event.reserves.map((reserve) => reserve.toString())).to.deep.equal(
+        pollAccount.shares.map((reserve) => reserve.toString())
+      );
+      expect(event.probabilities.reduce((sum, bps) => sum + bps.toNumber(), 0)).to.equal(10000);
+    });
+  });
+
+  describe("payout destination", () => {
+    const custodyPollKeypair = Keypair.generate();
+    let winningVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(custodyPollKeypair, { closesAt });
+      winningVote = await castVote(custodyPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: custodyPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Pays winnings into another account of the same mint", async () => {
+      const ownBefore = await getAccount(provider.connection, user1TokenAccount);
+      const custodyBefore = await getAccount(provider.connection, user2TokenAccount);
+
+      await program.methods
+        .claimWinnings(null)
+        .accounts({
+          poll: custodyPollKeypair.publicKey,
+          vote: winningVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: null,
+          destination: user2TokenAccount,
+        })
+        .signers([user1])
+        .rpc();
+
+      const ownAfter = await getAccount(provider.connection, user1TokenAccount);
+      const custodyAfter = await getAccount(provider.connection, user2TokenAccount);
+      expect(Number(ownAfter.amount)).to.equal(Number(ownBefore.amount));
+      // The only stake on the winning side takes the whole pool
+      expect(Number(custodyAfter.amount) - Number(custodyBefore.amount)).to.equal(9700000);
+    });
+  });
+
+  describe("position summary", () => {
+    const summaryPollKeypair = Keypair.generate();
+    let position: PublicKey;
+
+    before(async () => {
+      await createPoll(summaryPollKeypair);
+      position = await castVote(summaryPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await castVote(summaryPollKeypair.publicKey, user1, user1TokenAccount, 1, 5000000);
+    });
+
+    it("Sums a user's votes on one side", async () => {
+      let event: any = null;
+      const listener = program.addEventListener("positionSummaryEvent", (e) => {
+        if (e.poll.equals(summaryPollKeypair.publicKey)) {
+          event = e;
+        }
+      });
+
+      await program.methods
+        .getPosition(user1.publicKey)
+        .accounts({ poll: summaryPollKeypair.publicKey })
+        .remainingAccounts([{ pubkey: position, isSigner: false, isWritable: false }])
+        .rpc();
+      for (let i = 0; i < 20 && event === null; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      const voteAccount = await program.account.vote.fetch(position);
+      expect(event).to.not.equal(null);
+      expect(event.user.toString()).to.equal(user1.publicKey.toString());
+      expect(event.shares.map((shares) => shares.toString())).to.deep.equal([voteAccount.amount.toString(), "0"]);
+      expect(event.totalValue.toNumber()).to.equal(15000000);
+      expect(event.totalStake.toNumber()).to.equal(14550000);
+    });
+
+    it("Rejects a position listed twice", async () => {
+      try {
+        await program.methods
+          .getPosition(user1.publicKey)
+          .accounts({ poll: summaryPollKeypair.publicKey })
+          .remainingAccounts([
+            { pubkey: position, isSigner: false, isWritable: false },
+            { pubkey: position, isSigner: false, isWritable: false },
+          ])
+          .rpc();
+        expect.fail("A position may only be counted once");
+      } catch (error) {
+        expect(error.toString()).to.include("Vote account listed more than once");
+      }
+    });
+  });
+
+  describe("ownership proof", () => {
+    const provenPollKeypair = Keypair.generate();
+    let nftMint: PublicKey;
+    let adminNftAccount: PublicKey;
+
+    before(async () => {
+      // A real one-of-one NFT held by the resolver
+      nftMint = await createMint(provider.connection, admin, admin.publicKey, null, 0);
+      adminNftAccount = (await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin,
+        nftMint,
+        admin.publicKey
+      )).address;
+      await mintTo(provider.connection, admin, nftMint, adminNftAccount, admin.publicKey, 1);
+
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(provenPollKeypair, { closesAt, outcomes: [nftMint, nft2], requireOwnershipProof: true });
+      await sleep((closesAt + 2) * 1000 - Date.now());
+    });
+
+    async function resolve(ownershipProof: PublicKey | null) {
+      await program.methods
+        .resolvePoll(nftMint)
+        .accounts({
+          poll: provenPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof,
+        })
+        .signers([admin])
+        .rpc();
+    }
+
+    it("Rejects resolving without proof of the winning NFT", async () => {
+      try {
+        await resolve(null);
+        expect.fail("The resolver must show they hold the NFT");
+      } catch (error) {
+        expect(error.toString()).to.include("Resolver must prove they hold the winning NFT");
+      }
+    });
+
+    it("Resolves once the resolver shows the NFT", async () => {
+      await resolve(adminNftAccount);
+      const pollAccount = await program.account.poll.fetch(provenPollKeypair.publicKey);
+      expect(pollAccount.winningNft.toString()).to.equal(nftMint.toString());
+    });
+  });
+
+  describe("vote rate limit", () => {
+    const limitedPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(limitedPollKeypair, { minVoteInterval: 3 });
+    });
+
+    it("Rejects a second bet inside the interval and accepts it after", async () => {
+      await castVote(limitedPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      try {
+        await castVote(limitedPollKeypair.publicKey, user1, user1TokenAccount, 1, 5000000);
+        expect.fail("The second bet came too soon");
+      } catch (error) {
+        expect(error.toString()).to.include("Voting too fast");
+      }
+
+      await sleep(4000);
+      const voteKey = await castVote(limitedPollKeypair.publicKey, user1, user1TokenAccount, 1, 5000000);
+      const voteAccount = await program.account.vote.fetch(voteKey);
+      expect(voteAccount.value.toNumber()).to.equal(15000000);
+    });
+  });
+
+  describe("required votes", () => {
+    const emptyPollKeypair = Keypair.generate();
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(emptyPollKeypair, { closesAt, requireVotes: true });
+      await sleep((closesAt + 2) * 1000 - Date.now());
+    });
+
+    it("Rejects resolving a poll nobody voted on", async () => {
+      try {
+        await program.methods
+          .resolvePoll(nft1)
+          .accounts({
+            poll: emptyPollKeypair.publicKey,
+            authority: admin.publicKey,
+            parentPoll: null,
+            ownershipProof: null,
+          })
+          .signers([admin])
+          .rpc();
+        expect.fail("The poll has no votes");
+      } catch (error) {
+        expect(error.toString()).to.include("No votes were cast on this poll");
+      }
+      const pollAccount = await program.account.poll.fetch(emptyPollKeypair.publicKey);
+      expect(pollAccount.status).to.deep.equal({ active: {} });
+    });
+  });
+
+  describe("partial claims", () => {
+    const partialPollKeypair = Keypair.generate();
+    let winningVote: PublicKey;
+
+    before(async () => {
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(partialPollKeypair, { closesAt });
+      winningVote = await castVote(partialPollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: partialPollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    async function claim(amount: anchor.BN | null) {
+      await program.methods
+        .claimWinnings(amount)
+        .accounts({
+          poll: partialPollKeypair.publicKey,
+          vote: winningVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+          parentPoll: null,
+          destination: null,
+        })
+        .signers([user1])
+        .rpc();
+    }
+
+    it("Rejects claiming more than the remaining winnings", async () => {
+      try {
+        await claim(new anchor.BN(9700001));
+        expect.fail("The position is owed 9700000");
+      } catch (error) {
+        expect(error.toString()).to.include("Claim amount must be positive and within the remaining winnings");
+      }
+    });
+
+    it("Pays the winnings out in two parts", async () => {
+      const before = await getAccount(provider.connection, user1TokenAccount);
+
+      await claim(new anchor.BN(4000000));
+      let voteAccount = await program.account.vote.fetch(winningVote);
+      expect(voteAccount.claimed).to.equal(false);
+      expect(voteAccount.claimedAmount.toNumber()).to.equal(4000000);
+
+      await claim(null);
+      voteAccount = await program.account.vote.fetch(winningVote);
+      expect(voteAccount.claimed).to.equal(true);
+      expect(voteAccount.claimedAmount.toNumber()).to.equal(9700000);
+
+      const after = await getAccount(provider.connection, user1TokenAccount);
+      expect(Number(after.amount) - Number(before.amount)).to.equal(9700000);
+      const pollAccount = await program.account.poll.fetch(partialPollKeypair.publicKey);
+      expect(pollAccount.vaultLiability.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("account versions", () => {
+    it("Writes the current version and refuses to migrate it again", async () => {
+      const versionedPollKeypair = Keypair.generate();
+      await createPoll(versionedPollKeypair);
+      const pollAccount = await program.account.poll.fetch(versionedPollKeypair.publicKey);
+      expect(pollAccount.version).to.equal(6);
+
+      try {
+        await program.methods
+          .migratePoll()
+          .accounts({
+            poll: versionedPollKeypair.publicKey,
+            authority: admin.publicKey,
+            tokenMint: mint,
+            payer: admin.publicKey,
+          })
+          .signers([admin])
+          .rpc();
+        expect.fail("The poll already uses the current layout");
+      } catch (error) {
+        expect(error.toString()).to.include("Poll already uses the current account layout");
+      }
+    });
+  });
+
+  describe("metadata updates", () => {
+    const metadataPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(metadataPollKeypair);
+    });
+
+    it("Grows the poll account for a longer URI and charges the authority the rent", async () => {
+      const before = await provider.connection.getAccountInfo(metadataPollKeypair.publicKey);
+      const uri = Buffer.from("https://example.com/polls/" + "b".repeat(100));
+
+      await program.methods
+        .updateMetadata(uri)
+        .accounts({ poll: metadataPollKeypair.publicKey, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      const after = await provider.connection.getAccountInfo(metadataPollKeypair.publicKey);
+      expect(after.data.length).to.equal(before.data.length + uri.length);
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(after.data.length);
+      expect(after.lamports).to.equal(rent);
+      const pollAccount = await program.account.poll.fetch(metadataPollKeypair.publicKey);
+      expect(Buffer.from(pollAccount.metadataUri).toString()).to.equal(uri.toString());
+    });
+
+    it("Rejects updates by anyone but the authority", async () => {
+      try {
+        await program.methods
+          .updateMetadata(Buffer.from("https://example.com/other.json"))
+          .accounts({ poll: metadataPollKeypair.publicKey, authority: user1.publicKey })
+          .signers([user1])
+          .rpc();
+        expect.fail("Only the poll authority may edit its metadata");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("poll state", () => {
+    it("Mirrors the poll account in a flat event", async () => {
+      const statePollKeypair = Keypair.generate();
+      await createPoll(statePollKeypair, { feeBps: 250, claimFeeBps: 100 });
+      await castVote(statePollKeypair.publicKey, user1, user1TokenAccount, 2, 10000000);
+
+      const simulation = await program.methods.getPollState().accounts({ poll: statePollKeypair.publicKey }).simulate();
+      const state = simulation.events.find((e) => e.name.toLowerCase() === "pollstateevent").data as any;
+      const pollAccount = await program.account.poll.fetch(statePollKeypair.publicKey);
+
+      expect(state.poll.toString()).to.equal(statePollKeypair.publicKey.toString());
+      expect(state.authority.toString()).to.equal(pollAccount.authority.toString());
+      expect(state.status).to.equal(0);
+      expect(state.resolutionMode).to.equal(0);
+      expect(state.winningNft.toString()).to.equal(PublicKey.default.toString());
+      expect(state.reserves.map((r) => r.toString())).to.deep.equal(pollAccount.shares.map((r) => r.toString()));
+      expect(state.feeBps).to.equal(250);
+      expect(state.claimFeeBps).to.equal(100);
+      expect(state.voteCount.toNumber()).to.equal(1);
+      expect(state.totalVolume.toString()).to.equal(pollAccount.totalVolume.toString());
+      expect(state.vaultLiability.toString()).to.equal(pollAccount.vaultLiability.toString());
+      expect(state.closesAt.toString()).to.equal(pollAccount.closesAt.toString());
+    });
+  });
+
+  describe("frozen vaults", () => {
+    const frozenPollKeypair = Keypair.generate();
+    let freezableMint: PublicKey;
+    let user1FreezableAccount: PublicKey;
+
+    before(async () => {
+      // The admin keeps the freeze authority of this mint
+      freezableMint = await createMint(provider.connection, admin, admin.publicKey, admin.publicKey, 6);
+      user1FreezableAccount = (await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin,
+        freezableMint,
+        user1.publicKey
+      )).address;
+      await mintTo(provider.connection, admin, freezableMint, user1FreezableAccount, admin.publicKey, 10000000);
+      await createPoll(frozenPollKeypair, { tokenMint: freezableMint });
+      await freezeAccount(
+        provider.connection,
+        admin,
+        poolVaultFor(frozenPollKeypair.publicKey),
+        freezableMint,
+        admin
+      );
+    });
+
+    it("Rejects bets into a frozen pool vault", async () => {
+      try {
+        await castVote(frozenPollKeypair.publicKey, user1, user1FreezableAccount, 1, 1000000);
+        expect.fail("The stake cannot reach a frozen vault");
+      } catch (error) {
+        expect(error.toString()).to.include("Pool vault is frozen");
+      }
+      const pollAccount = await program.account.poll.fetch(frozenPollKeypair.publicKey);
+      expect(pollAccount.voteCount.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("split votes", () => {
+    const splitVotePollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(splitVotePollKeypair);
+    });
+
+    it("Records a position on each outcome with one fee on the total", async () => {
+      const pollKey = splitVotePollKeypair.publicKey;
+      const feeVaultBefore = Number((await getAccount(provider.connection, feeVaultFor(pollKey))).amount);
+      await program.methods
+        .voteSplit(
+          { nftChoice: 1, amount: new anchor.BN(10000000), minSharesOut: new anchor.BN(0) },
+          { nftChoice: 2, amount: new anchor.BN(4000000), minSharesOut: new anchor.BN(0) }
+        )
+        .accountsPartial({
+          poll: pollKey,
+          vote: voteFor(pollKey, user1.publicKey),
+          secondVote: splitVoteFor(pollKey, user1.publicKey, 2),
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+        })
+        .signers([user1])
+        .rpc();
+
+      // The first leg is the user's regular position, the second the hedge
+      const first = await program.account.vote.fetch(voteFor(pollKey, user1.publicKey));
+      const second = await program.account.vote.fetch(splitVoteFor(pollKey, user1.publicKey, 2));
+      expect(first.votedForNft).to.equal(1);
+      expect(second.votedForNft).to.equal(2);
+      // 3% of 14M, shared 10:4 between the legs
+      expect(first.stake.toNumber()).to.equal(9700000);
+      expect(second.stake.toNumber()).to.equal(3880000);
+      const feeVaultAfter = Number((await getAccount(provider.connection, feeVaultFor(pollKey))).amount);
+      expect(feeVaultAfter - feeVaultBefore).to.equal(420000);
+
+      const pollAccount = await program.account.poll.fetch(pollKey);
+      expect(pollAccount.voteCount.toNumber()).to.equal(2);
+      expect(pollAccount.positions).to.deep.equal([1, 1]);
+    });
+
+    it("Keeps later bets on the outcome of the first leg", async () => {
+      try {
+        await castVote(splitVotePollKeypair.publicKey, user1, user1TokenAccount, 2, 1000000);
+        expect.fail("The regular position backs the first outcome");
+      } catch (error) {
+        expect(error.toString()).to.include("Already voted for another outcome");
+      }
+    });
+  });
+
+  describe("reserve floor", () => {
+    const floorPollKeypair = Keypair.generate();
+
+    before(async () => {
+      await createPoll(floorPollKeypair, {
+        initialShares: new anchor.BN(10000000),
+        feeBps: 0,
+        minReserve: new anchor.BN(5000000),
+      });
+    });
+
+    it("Rejects bets that would take a reserve below the floor", async () => {
+      const pollKey = floorPollKeypair.publicKey;
+      try {
+        await castVote(pollKey, user1, user1TokenAccount, 1, 20000000);
+        expect.fail("The first reserve would drop to a third");
+      } catch (error) {
+        expect(error.toString()).to.include("Not enough liquidity");
+      }
+
+      // Exactly on the floor is still allowed
+      await castVote(pollKey, user1, user1TokenAccount, 1, 10000000);
+      const pollAccount = await program.account.poll.fetch(pollKey);
+      expect(pollAccount.minReserve.toNumber()).to.equal(5000000);
+      expect(pollAccount.shares[0].toNumber()).to.equal(5000000);
+
+      try {
+        await castVote(pollKey, user2, user2TokenAccount, 1, 1000000);
+        expect.fail("The first reserve is already at the floor");
+      } catch (error) {
+        expect(error.toString()).to.include("Not enough liquidity");
+      }
+      await castVote(pollKey, user2, user2TokenAccount, 2, 1000000);
+    });
+  });
+
+  describe("disabled outcomes", () => {
+    const disablePollKeypair = Keypair.generate();
+    const nft3 = Keypair.generate().publicKey;
+    let disabledVote: PublicKey;
+
+    async function disableOutcome(outcomeIndex: number, signer: Keypair = admin) {
+      await program.methods
+        .disableOutcome(outcomeIndex)
+        .accounts({ poll: disablePollKeypair.publicKey, authority: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    before(async () => {
+      await createPoll(disablePollKeypair, { outcomes: [nft1, nft2, nft3] });
+      disabledVote = await castVote(disablePollKeypair.publicKey, user1, user1TokenAccount, 2, 10000000);
+    });
+
+    it("Rejects disabling by anyone but the authority or admin", async () => {
+      try {
+        await disableOutcome(1, user2);
+        expect.fail("Only the authority or admin may disable an outcome");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("Takes the outcome out of the market and refunds its bets", async () => {
+      const pollKey = disablePollKeypair.publicKey;
+      await disableOutcome(1);
+      const pollAccount = await program.account.poll.fetch(pollKey);
+      expect(pollAccount.outcomeDisabled).to.deep.equal([false, true, false]);
+      expect(pollAccount.shares[1].toNumber()).to.equal(0);
+
+      try {
+        await castVote(pollKey, user2, user2TokenAccount, 2, 1000000);
+        expect.fail("The outcome no longer takes bets");
+      } catch (error) {
+        expect(error.toString()).to.include("Outcome is disabled");
+      }
+      // The other outcomes keep trading
+      await castVote(pollKey, user2, user2TokenAccount, 3, 1000000);
+
+      const balanceBefore = Number((await getAccount(provider.connection, user1TokenAccount)).amount);
+      await program.methods
+        .claimRefund()
+        .accounts({
+          poll: pollKey,
+          vote: disabledVote,
+          user: user1.publicKey,
+          userTokenAccount: user1TokenAccount,
+        })
+        .signers([user1])
+        .rpc();
+      const balanceAfter = Number((await getAccount(provider.connection, user1TokenAccount)).amount);
+      const vote = await program.account.vote.fetch(disabledVote);
+      expect(balanceAfter - balanceBefore).to.equal(vote.stake.toNumber());
+    });
+
+    it("Keeps at least two outcomes in the market", async () => {
+      try {
+        await disableOutcome(0);
+        expect.fail("Only two outcomes are left");
+      } catch (error) {
+        expect(error.toString()).to.include("Invalid number of outcomes");
+      }
+    });
+  });
+
+  describe("claim deadline", () => {
+    const deadlinePollKeypair = Keypair.generate();
+    let unclaimedVote: PublicKey;
+
+    async function setClaimWindow(claimWindow: number) {
+      await program.methods
+        .setClaimWindow(new anchor.BN(claimWindow))
+        .accounts({ admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    }
+
+    async function sweepUnclaimed() {
+      await program.methods
+        .sweepUnclaimed()
+        .accounts({
+          poll: deadlinePollKeypair.publicKey,
+          admin: admin.publicKey,
+          treasury: adminTokenAccount,
+        })
+        .signers([admin])
+        .rpc();
+    }
+
+    before(async () => {
+      await setClaimWindow(6);
+      const closesAt = Math.floor(Date.now() / 1000) + 5;
+      await createPoll(deadlinePollKeypair, { closesAt });
+      unclaimedVote = await castVote(deadlinePollKeypair.publicKey, user1, user1TokenAccount, 1, 10000000);
+      await castVote(deadlinePollKeypair.publicKey, user2, user2TokenAccount, 2, 10000000);
+
+      await sleep((closesAt + 2) * 1000 - Date.now());
+      await program.methods
+        .resolvePoll(nft1)
+        .accounts({
+          poll: deadlinePollKeypair.publicKey,
+          authority: admin.publicKey,
+          parentPoll: null,
+          ownershipProof: null,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    after(async () => {
+      // The poll keeps the deadline it was resolved with
+      await setClaimWindow(0);
+    });
+
+    it("Refuses to sweep while the claim window is open", async () => {
+      const pollAccount = await program.account.poll.fetch(deadlinePollKeypair.publicKey);
+      expect(pollAccount.claimDeadline.toNumber()).to.equal(pollAccount.resolvedAt.toNumber() + 6);
+      try {
+        await sweepUnclaimed();
+        expect.fail("Winners still have time to claim");
+      } catch (error) {
+        expect(error.toString()).to.include("Claim window is still open");
+      }
+    });
+
+    it("Sweeps unclaimed winnings to the treasury after the deadline", async () => {
+      const pollKey = deadlinePollKeypair.publicKey;
+      const deadline = (await program.account.poll.fetch(pollKey)).claimDeadline.toNumber();
+      await sleep((deadline + 2) * 1000 - Date.now());
+      try {
+        await program.methods
+          .claimWinnings(null)
+          .accounts({
+            poll: pollKey,
+            vote: unclaimedVote,
+            user: user1.publicKey,
+            userTokenAccount: user1TokenAccount,
+            parentPoll: null,
+            destination: null,
+          })
+          .signers([user1])
+          .rpc();
+        expect.fail("The claim window has passed");
+      } catch (error) {
+        expect(error.toString()).to.include("Claim window has closed");
+      }
+
+      const owed = (await program.account.poll.fetch(pollKey)).vaultLiability.toNumber();
+      const treasuryBefore = Number((await getAccount(provider.connection, adminTokenAccount)).amount);
+      const events: any[] = [];
+      const listener = program.addEventListener("unclaimedSweptEvent", (e) => {
+        events.push(e);
+      });
+      await sweepUnclaimed();
+      for (let i = 0; i < 20 && events.length < 1; i++) {
+        await sleep(250);
+      }
+      await program.removeEventListener(listener);
+
+      const treasuryAfter = Number((await getAccount(provider.connection, adminTokenAccount)).amount);
+      expect(treasuryAfter - treasuryBefore).to.equal(owed);
+      expect(events[0].amount.toNumber()).to.equal(owed);
+      expect(events[0].unclaimedWinners).to.equal(1);
+      const pollAccount = await program.account.poll.fetch(pollKey);
+      expect(pollAccount.vaultLiability.toNumber()).to.equal(0);
+      expect(pollAccount.unclaimedWinners).to.equal(0);
+    });
   });
-});
\ No newline at end of file
+});